@@ -0,0 +1,254 @@
+use crate::{
+    completion::{self, Candidate},
+    decode::Decoder,
+    editor::{BufferInfo, Editor},
+    goto::GotoTarget,
+    input::{Input, Key},
+    keypress,
+    terminal::Terminal,
+    text_buffer::{BufferId, TextBuffer},
+};
+use std::cmp;
+use unicode_width::UnicodeWidthStr;
+
+pub(crate) const BUFFER_LIST_NAME: &str = "*Buffer List*";
+
+// Lines before the first buffer in the list
+const HEADER_LINES: usize = 2;
+
+/// Prompts for a buffer name with completion and shows the buffer.
+pub(crate) fn switch_to_buffer(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let mut buffers = editor.buffer_list();
+    // The current buffer is the least likely to be wanted
+    if !buffers.is_empty() {
+        let current = buffers.remove(0);
+        buffers.push(current);
+    }
+    let candidates = buffers
+        .iter()
+        .map(|info| {
+            let path = info.path.as_ref().map(|p| p.display().to_string());
+            Candidate::new(info.name.clone(), path.unwrap_or_default(), "")
+        })
+        .collect::<Vec<_>>();
+
+    let name = match completion::prompt(term, decoder, editor, "Switch to buffer: {}", &candidates)?
+    {
+        Some(name) => name,
+        None => {
+            editor.set_status_message("Switch aborted");
+            return Ok(());
+        }
+    };
+    match buffers.iter().find(|info| info.name == name) {
+        Some(info) => {
+            let _ = editor.switch_to_buffer(info.id);
+        }
+        None => editor.set_status_message(format!("No buffer named {}", name)),
+    }
+    Ok(())
+}
+
+/// Shows the open buffers in a list, where buffers can be opened, saved or killed.
+pub(crate) fn list_buffers(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let prev = editor.buffer_id();
+    // Showing buffers reorders them, but the list keeps the order when it was opened
+    let order = editor
+        .buffer_list()
+        .into_iter()
+        .map(|info| info.id)
+        .collect::<Vec<_>>();
+    let mut selected = 0;
+    loop {
+        let mut buffers = editor
+            .buffer_list()
+            .into_iter()
+            .filter(|info| info.name != BUFFER_LIST_NAME)
+            .collect::<Vec<_>>();
+        buffers.sort_by_key(|info| order.iter().position(|id| *id == info.id));
+        if buffers.is_empty() {
+            close_list(editor, None);
+            editor.set_status_message("No buffers");
+            return Ok(());
+        }
+        selected = cmp::min(selected, buffers.len() - 1);
+
+        editor.show_buffer(TextBuffer::from_text(
+            BUFFER_LIST_NAME,
+            &list_text(&buffers),
+        ));
+        editor.goto(GotoTarget::Line {
+            line: HEADER_LINES + selected + 1,
+            col: None,
+        });
+        keypress::refresh(term, editor)?;
+
+        let input = match keypress::read_input(term, decoder, editor)? {
+            Some(input) => input,
+            None => return Ok(()),
+        };
+        let info = &buffers[selected];
+        match input {
+            Input {
+                key: Key::ArrowUp, ..
+            } => selected = selected.saturating_sub(1),
+            Input {
+                key: Key::ArrowDown,
+                ..
+            } => selected += 1,
+            Input {
+                key: Key::Char(ch),
+                ctrl: true,
+                alt: false,
+                ..
+            } => match ch {
+                'P' => selected = selected.saturating_sub(1),
+                'N' => selected += 1,
+                'M' => {
+                    close_list(editor, Some(info.id));
+                    return Ok(());
+                }
+                '[' | 'G' => {
+                    close_list(editor, prev);
+                    return Ok(());
+                }
+                _ => {}
+            },
+            Input {
+                key: Key::Char(ch),
+                ctrl: false,
+                alt: false,
+                ..
+            } => match ch {
+                'o' => {
+                    close_list(editor, Some(info.id));
+                    return Ok(());
+                }
+                'q' => {
+                    close_list(editor, prev);
+                    return Ok(());
+                }
+                's' => {
+                    let _ = editor.switch_to_buffer(info.id);
+                    editor.save(term, decoder)?;
+                }
+                'k' => {
+                    let _ = editor.switch_to_buffer(info.id);
+                    editor.close_buffer(term, decoder)?;
+                }
+                'n' => selected += 1,
+                'p' => selected = selected.saturating_sub(1),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+// Shows the buffer if it is still open, and kills the list
+fn close_list(editor: &mut Editor, show: Option<BufferId>) {
+    let list = editor
+        .buffer_list()
+        .into_iter()
+        .find(|info| info.name == BUFFER_LIST_NAME);
+    if let Some(id) = show {
+        let _ = editor.switch_to_buffer(id);
+    }
+    if let Some(list) = list {
+        editor.kill_buffer(list.id);
+    }
+}
+
+fn list_text(buffers: &[BufferInfo]) -> String {
+    let width = |title: &str, f: &dyn Fn(&BufferInfo) -> usize| {
+        buffers
+            .iter()
+            .map(f)
+            .chain(Some(title.width()))
+            .max()
+            .unwrap()
+    };
+    let name_width = width("Name", &|info| info.name.width());
+    let lines_width = width("Lines", &|info| info.lines.to_string().len());
+    let filetype_width = width("Filetype", &|info| info.filetype.width());
+
+    let mut text = "Enter: open  s: save  k: kill  q: quit\n".to_string();
+    let header = format!(
+        "MR {:nw$}  {:>lw$}  {:fw$}  Path",
+        "Name",
+        "Lines",
+        "Filetype",
+        nw = name_width,
+        lw = lines_width,
+        fw = filetype_width,
+    );
+    text.push_str(&header);
+    text.push('\n');
+    for info in buffers {
+        let line = format!(
+            "{}{} {:nw$}  {:>lw$}  {:fw$}  {}",
+            if info.dirty { '*' } else { ' ' },
+            if info.readonly { '%' } else { ' ' },
+            info.name,
+            info.lines,
+            info.filetype,
+            info.path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_default(),
+            nw = name_width,
+            lw = lines_width,
+            fw = filetype_width,
+        );
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn text() {
+        let id = TextBuffer::new().id();
+        let buffers = vec![
+            BufferInfo {
+                id,
+                name: "main.rs".to_string(),
+                path: Some(PathBuf::from("src/main.rs")),
+                dirty: true,
+                readonly: false,
+                lines: 318,
+                filetype: "rust",
+            },
+            BufferInfo {
+                id,
+                name: "*Help*".to_string(),
+                path: None,
+                dirty: false,
+                readonly: true,
+                lines: 9,
+                filetype: "no ft",
+            },
+        ];
+        assert_eq!(
+            list_text(&buffers),
+            "Enter: open  s: save  k: kill  q: quit
+MR Name     Lines  Filetype  Path
+*  main.rs    318  rust      src/main.rs
+ % *Help*       9  no ft
+"
+        );
+    }
+}
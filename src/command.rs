@@ -0,0 +1,641 @@
+use crate::{
+    buffer_list,
+    completion::{self, Candidate},
+    decode::Decoder,
+    directory,
+    editor::{CursorMove, Editor},
+    find,
+    frame::SplitOrientation,
+    goto, help,
+    input::{Input, InputStrExt, ParseInputError},
+    keymap::{Action, KeyMap},
+    keypress,
+    macros::{self, Macro},
+    terminal::Terminal,
+};
+use itertools::Itertools;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::rc::Rc;
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Invalid keys {:?}: {}", keys, source))]
+    InvalidKeys {
+        keys: String,
+        source: ParseInputError,
+    },
+    #[snafu(display("Unknown command: {}", name))]
+    UnknownCommand { name: String },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The function of a command. It returns whether the editor should quit.
+pub(crate) type CommandFn<T> =
+    dyn Fn(&mut T, &mut Decoder, &mut Editor, &Commands<T>) -> keypress::Result<bool>;
+
+/// A named editor operation, which can be bound to keys or run from the `M-x` prompt.
+pub(crate) struct Command<T> {
+    name: String,
+    description: String,
+    func: Box<CommandFn<T>>,
+}
+
+impl<T> Command<T> {
+    pub(crate) fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        func: impl Fn(&mut T, &mut Decoder, &mut Editor, &Commands<T>) -> keypress::Result<bool>
+            + 'static,
+    ) -> Self {
+        Command {
+            name: name.into(),
+            description: description.into(),
+            func: Box::new(func),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub(crate) fn run(
+        &self,
+        term: &mut T,
+        decoder: &mut Decoder,
+        editor: &mut Editor,
+        commands: &Commands<T>,
+    ) -> keypress::Result<bool> {
+        (self.func)(term, decoder, editor, commands)
+    }
+}
+
+/// The registry of named commands and the keymap binding inputs to them.
+pub(crate) struct Commands<T> {
+    // Sorted by name
+    commands: Vec<Rc<Command<T>>>,
+    keymap: KeyMap<Command<T>>,
+    // Looked up before `keymap` in directory listings
+    directory_keymap: KeyMap<Command<T>>,
+}
+
+impl<T: Terminal> Commands<T> {
+    /// Returns the builtin commands with the default key bindings.
+    pub(crate) fn new() -> Self {
+        let mut commands = Commands {
+            commands: vec![],
+            keymap: KeyMap::new(),
+            directory_keymap: KeyMap::new(),
+        };
+        for cmd in builtin_commands() {
+            let _ = commands.insert(cmd);
+        }
+        for (keys, name) in DEFAULT_BINDINGS {
+            let displaced = commands.bind(keys, name).unwrap();
+            debug_assert!(displaced.is_none(), "{}: {:?}", keys, displaced);
+        }
+        for (keys, name) in DIRECTORY_BINDINGS {
+            let inputs = parse_keys(keys).unwrap();
+            let cmd = commands.get(name).unwrap();
+            let _ = commands.directory_keymap.insert(inputs.into_iter(), cmd);
+        }
+        commands
+    }
+}
+
+impl<T> Commands<T> {
+    /// Registers a command, replacing the command of the same name.
+    pub(crate) fn insert(&mut self, cmd: Command<T>) -> Rc<Command<T>> {
+        let cmd = Rc::new(cmd);
+        match self.commands.binary_search_by(|c| c.name().cmp(cmd.name())) {
+            Ok(idx) => self.commands[idx] = cmd.clone(),
+            Err(idx) => self.commands.insert(idx, cmd.clone()),
+        }
+        cmd
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<Rc<Command<T>>> {
+        let idx = self
+            .commands
+            .binary_search_by(|c| c.name().cmp(name))
+            .ok()?;
+        Some(self.commands[idx].clone())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Rc<Command<T>>> {
+        self.commands.iter()
+    }
+
+    pub(crate) fn keymap(&self) -> &KeyMap<Command<T>> {
+        &self.keymap
+    }
+
+    pub(crate) fn directory_keymap(&self) -> &KeyMap<Command<T>> {
+        &self.directory_keymap
+    }
+
+    /// Returns what the first input of a sequence is bound to, with the bindings of directory
+    /// listings taking precedence in one.
+    pub(crate) fn action(&self, editor: &Editor, input: &Input) -> Option<Action<Command<T>>> {
+        if editor.in_directory() {
+            if let Some(act) = self.directory_keymap.get(input) {
+                return Some(act);
+            }
+        }
+        self.keymap.get(input)
+    }
+
+    /// Binds `keys`, such as `C-x C-s`, to the named command. Returns a message describing the
+    /// bindings displaced by making a bound key a prefix or a prefix a key, if any.
+    pub(crate) fn bind(&mut self, keys: &str, name: &str) -> Result<Option<String>> {
+        let inputs = parse_keys(keys)?;
+        let cmd = self.get(name).context(UnknownCommand { name })?;
+        Ok(self.bind_inputs(&inputs, cmd))
+    }
+
+    /// Registers a command replaying a keyboard macro as `macro:<name>` and binds `keys` to it.
+    /// Returns a message describing the displaced bindings as `bind` does.
+    pub(crate) fn bind_macro(
+        &mut self,
+        keys: &str,
+        name: &str,
+        mac: Macro,
+    ) -> Result<Option<String>> {
+        let inputs = parse_keys(keys)?;
+        let cmd = Command::new(
+            format!("macro:{}", name),
+            format!("Run the keyboard macro {}", name),
+            move |_, decoder, editor, _| {
+                if !decoder.replay(&mac, 1) {
+                    editor.set_status_message("Keyboard macro is too long");
+                }
+                Ok(false)
+            },
+        );
+        let cmd = self.insert(cmd);
+        Ok(self.bind_inputs(&inputs, cmd))
+    }
+
+    fn bind_inputs(&mut self, inputs: &[Input], cmd: Rc<Command<T>>) -> Option<String> {
+        let keys = inputs.iter().join(" ");
+        let (displaced, old) = self.keymap.insert(inputs.iter().cloned(), cmd)?;
+        match old {
+            // Rebinding the same keys
+            Action::Func(_) if displaced.len() == inputs.len() => None,
+            Action::Func(old) => Some(format!(
+                "{} unbinds {} from {}",
+                keys,
+                displaced.iter().join(" "),
+                old.name()
+            )),
+            Action::KeyMap(_) => Some(format!("{} unbinds the keys starting with it", keys)),
+        }
+    }
+
+    /// Returns the input sequences bound to the named command, shortest first.
+    pub(crate) fn bindings_of(&self, name: &str) -> Vec<Vec<Input>> {
+        self.keymap
+            .bindings()
+            .into_iter()
+            .filter(|(_, cmd)| cmd.name() == name)
+            .map(|(inputs, _)| inputs)
+            .map(|inputs| (inputs.len(), inputs.iter().join(" "), inputs))
+            .sorted_by(|a, b| (a.0, a.1.len(), &a.1).cmp(&(b.0, b.1.len(), &b.1)))
+            .map(|(_, _, inputs)| inputs)
+            .collect()
+    }
+}
+
+fn parse_keys(keys: &str) -> Result<Vec<Input>> {
+    let inputs = keys
+        .inputs()
+        .collect::<Result<Vec<_>, _>>()
+        .context(InvalidKeys { keys })?;
+    if inputs.is_empty() {
+        return Err(ParseInputError::InvalidKey).context(InvalidKeys { keys });
+    }
+    Ok(inputs)
+}
+
+// Bindings used only in directory listings
+const DIRECTORY_BINDINGS: &[(&str, &str)] = &[("C-M", "open-entry")];
+
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("C-M", "newline"),
+    ("C-I", "insert-tab"),
+    ("C-?", "delete-backward-char"),
+    ("C-H", "delete-backward-char"),
+    ("<delete>", "delete-char"),
+    ("C-Q", "quit"),
+    ("C-Z", "suspend"),
+    ("C-L", "redraw-display"),
+    ("C-P", "previous-line"),
+    ("C-N", "next-line"),
+    ("C-B", "backward-char"),
+    ("C-F", "forward-char"),
+    ("C-A", "beginning-of-line"),
+    ("C-E", "end-of-line"),
+    ("C-V", "page-down"),
+    ("M-v", "page-up"),
+    ("M-<", "beginning-of-buffer"),
+    ("M->", "end-of-buffer"),
+    ("<up>", "previous-line"),
+    ("<down>", "next-line"),
+    ("<left>", "backward-char"),
+    ("<right>", "forward-char"),
+    ("<home>", "beginning-of-line"),
+    ("<end>", "end-of-line"),
+    ("<page up>", "page-up"),
+    ("<page down>", "page-down"),
+    ("C-O", "find-file"),
+    ("C-S", "save-buffer"),
+    ("C-G", "search"),
+    ("C-X", "next-buffer"),
+    ("M-X", "previous-buffer"),
+    ("C-C", "kill-buffer"),
+    ("M-2", "split-window-below"),
+    ("M-w", "toggle-line-wrap"),
+    ("M-i", "set-indent"),
+    ("M-g", "goto-line"),
+    ("C-]", "goto-matching-bracket"),
+    ("<f3>", "start-macro"),
+    ("<f4>", "end-or-run-macro"),
+    ("<M-f3>", "save-macro"),
+    ("<M-f4>", "run-macro"),
+    ("M-x", "execute-command"),
+    ("M-b", "switch-to-buffer"),
+    ("M-l", "list-buffers"),
+    ("<f1> k", "describe-key"),
+    ("<f1> b", "describe-bindings"),
+];
+
+fn builtin_commands<T: Terminal>() -> Vec<Command<T>> {
+    let mut commands = vec![
+        Command::new("newline", "Insert a line break", |_, _, editor, _| {
+            editor.insert_newline();
+            Ok(false)
+        }),
+        Command::new(
+            "insert-tab",
+            "Insert a tab or spaces up to the next tab stop",
+            |_, _, editor, _| {
+                editor.insert_tab();
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "delete-backward-char",
+            "Delete the character before the cursor",
+            |_, _, editor, _| {
+                editor.delete_back_char();
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "delete-char",
+            "Delete the character at the cursor",
+            |_, _, editor, _| {
+                editor.delete_char();
+                Ok(false)
+            },
+        ),
+        Command::new("quit", "Quit the editor", |term, decoder, editor, _| {
+            editor.quit(term, decoder)
+        }),
+        Command::new(
+            "suspend",
+            "Suspend the editor and return to the shell",
+            |term, _, _, _| {
+                keypress::suspend(term)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "redraw-display",
+            "Redraw the whole screen",
+            |_, _, editor, _| {
+                editor.redraw();
+                Ok(false)
+            },
+        ),
+        Command::new("find-file", "Open a file", |term, decoder, editor, _| {
+            editor.open_prompt(term, decoder)?;
+            Ok(false)
+        }),
+        Command::new(
+            "save-buffer",
+            "Save the current buffer to its file",
+            |term, decoder, editor, _| {
+                editor.save(term, decoder)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "search",
+            "Search the current buffer incrementally",
+            |term, decoder, editor, _| {
+                find::find(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new("next-buffer", "Show the next buffer", |_, _, editor, _| {
+            editor.next_buffer();
+            Ok(false)
+        }),
+        Command::new(
+            "previous-buffer",
+            "Show the previous buffer",
+            |_, _, editor, _| {
+                editor.prev_buffer();
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "switch-to-buffer",
+            "Show a buffer chosen by name",
+            |term, decoder, editor, _| {
+                buffer_list::switch_to_buffer(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "list-buffers",
+            "List the open buffers to open, save or kill them",
+            |term, decoder, editor, _| {
+                buffer_list::list_buffers(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "open-entry",
+            "Open the file or the directory at the cursor in a directory",
+            |_, _, editor, _| {
+                directory::open_entry(editor);
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "create-file",
+            "Create a file or a directory in the listed directory",
+            |term, decoder, editor, _| {
+                directory::create_file(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "rename-file",
+            "Rename the file at the cursor in a directory",
+            |term, decoder, editor, _| {
+                directory::rename_file(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "delete-file",
+            "Delete the file at the cursor in a directory",
+            |term, decoder, editor, _| {
+                directory::delete_file(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "kill-buffer",
+            "Close the current buffer",
+            |term, decoder, editor, _| {
+                editor.close_buffer(term, decoder)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "split-window-below",
+            "Split the current window into one above the other",
+            |_, _, editor, _| {
+                editor.split_frame(SplitOrientation::Vertical);
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "toggle-line-wrap",
+            "Switch between wrapping and truncating long lines",
+            |_, _, editor, _| {
+                editor.toggle_wrap_mode();
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "set-indent",
+            "Set the indent style of the current buffer",
+            |term, decoder, editor, _| {
+                editor.set_indent_prompt(term, decoder)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "goto-line",
+            "Move the cursor to a line",
+            |term, decoder, editor, _| {
+                goto::goto_line(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "goto-matching-bracket",
+            "Move the cursor to the bracket matching the one at the cursor",
+            |_, _, editor, _| {
+                editor.jump_to_matching_bracket();
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "start-macro",
+            "Start recording a keyboard macro",
+            |_, decoder, editor, _| {
+                macros::start_recording(decoder, editor);
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "end-or-run-macro",
+            "Stop recording a keyboard macro, or run the last one",
+            |_, decoder, editor, _| {
+                macros::stop_recording_or_replay(decoder, editor);
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "save-macro",
+            "Save the last keyboard macro with a name",
+            |term, decoder, editor, _| {
+                macros::save_prompt(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "run-macro",
+            "Run the last or a named keyboard macro some times",
+            |term, decoder, editor, _| {
+                macros::run_prompt(term, decoder, editor)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "execute-command",
+            "Run a command by name",
+            |term, decoder, editor, commands| execute_command(term, decoder, editor, commands),
+        ),
+        Command::new(
+            "describe-key",
+            "Show which command a key sequence runs",
+            |term, decoder, editor, commands| {
+                help::describe_key(term, decoder, editor, commands)?;
+                Ok(false)
+            },
+        ),
+        Command::new(
+            "describe-bindings",
+            "List all key bindings in the help buffer",
+            |_, _, editor, commands| {
+                help::describe_bindings(editor, commands);
+                Ok(false)
+            },
+        ),
+    ];
+
+    let move_cursor = &[
+        ("previous-line", "Move the cursor up", CursorMove::Up),
+        ("next-line", "Move the cursor down", CursorMove::Down),
+        ("backward-char", "Move the cursor left", CursorMove::Left),
+        ("forward-char", "Move the cursor right", CursorMove::Right),
+        (
+            "beginning-of-line",
+            "Move the cursor to the beginning of the line",
+            CursorMove::Home,
+        ),
+        (
+            "end-of-line",
+            "Move the cursor to the end of the line",
+            CursorMove::End,
+        ),
+        ("page-up", "Scroll up by a screen", CursorMove::PageUp),
+        ("page-down", "Scroll down by a screen", CursorMove::PageDown),
+        (
+            "beginning-of-buffer",
+            "Move the cursor to the beginning of the buffer",
+            CursorMove::BufferHome,
+        ),
+        (
+            "end-of-buffer",
+            "Move the cursor to the end of the buffer",
+            CursorMove::BufferEnd,
+        ),
+    ];
+    for &(name, description, mov) in move_cursor {
+        commands.push(Command::new(name, description, move |_, _, editor, _| {
+            editor.move_cursor(mov);
+            Ok(false)
+        }));
+    }
+
+    commands
+}
+
+/// Prompts for a command name with completion and runs the command.
+fn execute_command<T>(
+    term: &mut T,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+    commands: &Commands<T>,
+) -> keypress::Result<bool>
+where
+    T: Terminal,
+{
+    let candidates = commands
+        .iter()
+        .map(|cmd| {
+            let keys = commands
+                .bindings_of(cmd.name())
+                .first()
+                .map(|inputs| inputs.iter().join(" "))
+                .unwrap_or_default();
+            Candidate::new(cmd.name(), keys, cmd.description())
+        })
+        .collect::<Vec<_>>();
+
+    let name = match completion::prompt(term, decoder, editor, "M-x {}", &candidates)? {
+        Some(name) => name,
+        None => {
+            editor.set_status_message("Aborted");
+            return Ok(false);
+        }
+    };
+    match commands.get(name.trim()) {
+        Some(cmd) => cmd.run(term, decoder, editor, commands),
+        None => {
+            editor.set_status_message(format!("Unknown command: {}", name.trim()));
+            Ok(false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_terminal::VirtualTerminal;
+
+    fn keys(s: &str) -> Vec<Input> {
+        s.inputs().map(|i| i.unwrap()).collect()
+    }
+
+    #[test]
+    fn bind() {
+        let mut commands = Commands::<VirtualTerminal>::new();
+        assert!(commands.get("save-buffer").is_some());
+        assert!(commands.get("no-such-command").is_none());
+        assert_eq!(commands.bindings_of("save-buffer"), &[keys("C-S")]);
+        assert_eq!(
+            commands.bindings_of("next-line"),
+            &[keys("C-N"), keys("<down>")]
+        );
+
+        assert_eq!(
+            commands.bind("C-X C-S", "save-buffer").unwrap().as_deref(),
+            Some("C-X C-S unbinds C-X from next-buffer")
+        );
+        assert_eq!(
+            commands.bind("C-X", "previous-buffer").unwrap().as_deref(),
+            Some("C-X unbinds the keys starting with it")
+        );
+        assert_eq!(commands.bind("C-X", "next-buffer").unwrap(), None);
+        assert_eq!(commands.bindings_of("next-buffer"), &[keys("C-X")]);
+        commands.bind("C-X C-S", "save-buffer").unwrap();
+        assert_eq!(
+            commands.bindings_of("save-buffer"),
+            &[keys("C-S"), keys("C-X C-S")]
+        );
+        // `C-X` became a prefix
+        assert!(commands.bindings_of("next-buffer").is_empty());
+
+        assert!(matches!(
+            commands.bind("C-S", "no-such-command"),
+            Err(Error::UnknownCommand { .. })
+        ));
+        assert!(matches!(
+            commands.bind("<nokey>", "save-buffer"),
+            Err(Error::InvalidKeys { .. })
+        ));
+        assert!(matches!(
+            commands.bind("", "save-buffer"),
+            Err(Error::InvalidKeys { .. })
+        ));
+
+        commands
+            .bind_macro("<f5>", "abc", "a b".parse().unwrap())
+            .unwrap();
+        assert!(commands.get("macro:abc").is_some());
+        assert_eq!(commands.bindings_of("macro:abc"), &[keys("<f5>")]);
+    }
+}
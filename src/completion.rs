@@ -0,0 +1,222 @@
+use crate::{
+    decode::Decoder,
+    editor::Editor,
+    keypress::{self, PromptCommand},
+    terminal::Terminal,
+};
+use std::{cell::RefCell, cmp::Reverse};
+
+/// A completion candidate, shown with its annotation such as a key binding. The description is
+/// shown only for the selected candidate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Candidate {
+    text: String,
+    annotation: String,
+    description: String,
+}
+
+impl Candidate {
+    pub(crate) fn new(
+        text: impl Into<String>,
+        annotation: impl Into<String>,
+        description: impl Into<String>,
+    ) -> Self {
+        Candidate {
+            text: text.into(),
+            annotation: annotation.into(),
+            description: description.into(),
+        }
+    }
+}
+
+/// Scores how well `pattern` matches `text` as a subsequence ignoring case, or returns `None` if
+/// it does not match. Consecutive characters and characters at the start of words score higher.
+pub(crate) fn fuzzy_score(pattern: &str, text: &str) -> Option<usize> {
+    let text = text.chars().collect::<Vec<_>>();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev = None;
+    for pch in pattern.chars() {
+        let idx = (pos..text.len()).find(|&idx| eq_ignore_case(text[idx], pch))?;
+        score += 1;
+        if idx > 0 && prev == Some(idx - 1) {
+            score += 2;
+        }
+        if idx == 0 || !text[idx - 1].is_alphanumeric() {
+            score += 3;
+        }
+        prev = Some(idx);
+        pos = idx + 1;
+    }
+    Some(score)
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/// Returns the indices of the candidates matching `pattern`, best first.
+fn fuzzy_matches(pattern: &str, candidates: &[Candidate]) -> Vec<usize> {
+    let mut matches = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, cand)| Some((idx, fuzzy_score(pattern, &cand.text)?)))
+        .collect::<Vec<_>>();
+    if !pattern.is_empty() {
+        matches.sort_by_key(|&(idx, score)| (Reverse(score), candidates[idx].text.len()));
+    }
+    matches.into_iter().map(|(idx, _)| idx).collect()
+}
+
+#[derive(Debug)]
+struct Completion<'a> {
+    candidates: &'a [Candidate],
+    matches: Vec<usize>,
+    // Index into `matches`
+    selected: usize,
+}
+
+impl<'a> Completion<'a> {
+    fn new(candidates: &'a [Candidate]) -> Self {
+        Completion {
+            candidates,
+            matches: fuzzy_matches("", candidates),
+            selected: 0,
+        }
+    }
+
+    fn update(&mut self, pattern: &str) {
+        self.matches = fuzzy_matches(pattern, self.candidates);
+        self.selected = 0;
+    }
+
+    fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+        }
+    }
+
+    fn select_prev(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    fn selected(&self) -> Option<&Candidate> {
+        let idx = *self.matches.get(self.selected)?;
+        Some(&self.candidates[idx])
+    }
+
+    // The matches are listed from the selected one, which wraps around like a ring
+    fn message(&self, prompt: &str, input: &str) -> String {
+        let mut msg = prompt.replace("{}", input);
+        if self.matches.is_empty() {
+            msg.push_str(" [No match]");
+            return msg;
+        }
+        msg.push_str(" {");
+        let (head, tail) = self.matches.split_at(self.selected);
+        for (i, &idx) in tail.iter().chain(head).enumerate() {
+            if i > 0 {
+                msg.push_str(" | ");
+            }
+            let cand = &self.candidates[idx];
+            msg.push_str(&cand.text);
+            if !cand.annotation.is_empty() {
+                msg.push_str(&format!(" ({})", cand.annotation));
+            }
+            if i == 0 && !cand.description.is_empty() {
+                msg.push_str(&format!(": {}", cand.description));
+            }
+        }
+        msg.push('}');
+        msg
+    }
+}
+
+/// Prompts for one of the candidates with fuzzy completion.
+///
+/// The matches are listed after the input, best first. Arrow keys select a match, Tab completes
+/// the input to it, and Enter accepts it. If nothing matches, the input is returned as typed.
+pub(crate) fn prompt(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+    prompt: &str,
+    candidates: &[Candidate],
+) -> keypress::Result<Option<String>> {
+    let completion = RefCell::new(Completion::new(candidates));
+    keypress::prompt_with_message(
+        term,
+        decoder,
+        editor,
+        |input| completion.borrow().message(prompt, input),
+        |_, input, cmd| {
+            use PromptCommand::*;
+            let mut completion = completion.borrow_mut();
+            match cmd {
+                Input => completion.update(input),
+                SearchBackward => completion.select_prev(),
+                SearchForward => completion.select_next(),
+                Complete => {
+                    if let Some(cand) = completion.selected() {
+                        *input = cand.text.clone();
+                        completion.update(input);
+                    }
+                }
+                Execute => {
+                    if let Some(cand) = completion.selected() {
+                        *input = cand.text.clone();
+                    }
+                }
+                Cancel => {}
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("", "save-buffer"), Some(0));
+        assert_eq!(fuzzy_score("sb", "save-buffer"), Some(8));
+        assert_eq!(fuzzy_score("SAV", "save-buffer"), Some(10));
+        assert_eq!(fuzzy_score("bs", "save-buffer"), None);
+
+        let candidates = ["save-buffer", "kill-buffer", "search", "split-window-below"]
+            .iter()
+            .map(|text| Candidate::new(*text, "", ""))
+            .collect::<Vec<_>>();
+        assert_eq!(fuzzy_matches("", &candidates), &[0, 1, 2, 3]);
+        // Ties go to the shorter candidate
+        assert_eq!(fuzzy_matches("sb", &candidates), &[0, 3]);
+        assert_eq!(fuzzy_matches("se", &candidates), &[2, 0, 3]);
+        assert!(fuzzy_matches("xyz", &candidates).is_empty());
+    }
+
+    #[test]
+    fn message() {
+        let candidates = vec![
+            Candidate::new("goto-line", "M-g", "Go to a line"),
+            Candidate::new("goto-matching-bracket", "", ""),
+        ];
+        let mut completion = Completion::new(&candidates);
+        completion.update("go");
+        assert_eq!(
+            completion.message("M-x {}", "go"),
+            "M-x go {goto-line (M-g): Go to a line | goto-matching-bracket}"
+        );
+        completion.select_prev();
+        assert_eq!(completion.selected(), Some(&candidates[1]));
+        assert_eq!(
+            completion.message("M-x {}", "go"),
+            "M-x go {goto-matching-bracket | goto-line (M-g)}"
+        );
+        completion.update("x");
+        assert_eq!(completion.message("M-x {}", "x"), "M-x x [No match]");
+        assert_eq!(completion.selected(), None);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::{
+    decode,
+    indent::{self, IndentOverride, IndentSettings},
+    syntax::Syntax,
+};
+use snafu::{Backtrace, OptionExt, ResultExt, Snafu};
+use std::{
+    collections::HashMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Could not read config file {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("{}:{}: {}", filename.display(), line, source))]
+    Indent {
+        filename: PathBuf,
+        line: usize,
+        source: indent::Error,
+    },
+    #[snafu(display("{}:{}: invalid line", filename.display(), line))]
+    InvalidLine { filename: PathBuf, line: usize },
+    #[snafu(display("{}:{}: invalid value for {}: {}", filename.display(), line, key, value))]
+    InvalidValue {
+        filename: PathBuf,
+        line: usize,
+        key: String,
+        value: String,
+    },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// User configuration read from `$XDG_CONFIG_HOME/mirri/config`.
+///
+/// The file consists of `key = value` lines. Lines before the first section header are global
+/// settings, and a `[filetype]` section overrides them for the buffers of that filetype.
+///
+/// Besides the indent settings, the global section accepts `escape_delay`, the milliseconds to
+/// wait after ESC for an escape sequence or an Alt-modified key.
+///
+/// The `[bindings]` section binds keys to commands by name, such as `M-s = save-buffer`, and the
+/// `[macros]` section binds keys to saved keyboard macros, such as `<f5> = mymacro`. Binding keys
+/// starting with a bound key, such as `C-X C-S` while `C-X` is bound, unbinds that key.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Config {
+    indent: IndentOverride,
+    filetype_indent: HashMap<String, IndentOverride>,
+    escape_delay: Option<Duration>,
+    bindings: Vec<(String, String)>,
+    macro_bindings: Vec<(String, String)>,
+}
+
+impl Config {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn load() -> Result<Self> {
+        match config_file() {
+            Some(filename) if filename.exists() => Self::from_file(filename),
+            _ => Ok(Self::new()),
+        }
+    }
+
+    pub(crate) fn from_file(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let s = fs::read_to_string(filename).with_context(|| Read {
+            filename: filename.to_path_buf(),
+        })?;
+        Self::parse(filename, &s)
+    }
+
+    fn parse(filename: &Path, s: &str) -> Result<Self> {
+        let mut config = Self::new();
+        let mut section = None;
+        for (idx, line) in s.lines().enumerate() {
+            let line_no = idx + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = Some(line[1..line.len() - 1].trim().to_string());
+                continue;
+            }
+
+            let mut it = line.splitn(2, '=');
+            let (key, value) = match (it.next(), it.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => {
+                    return InvalidLine {
+                        filename,
+                        line: line_no,
+                    }
+                    .fail()
+                }
+            };
+
+            if section.is_none() && key == "escape_delay" {
+                let ms = value.parse().ok().context(InvalidValue {
+                    filename,
+                    line: line_no,
+                    key,
+                    value,
+                })?;
+                config.escape_delay = Some(Duration::from_millis(ms));
+                continue;
+            }
+
+            let bindings = match section.as_deref() {
+                Some("bindings") => Some(&mut config.bindings),
+                Some("macros") => Some(&mut config.macro_bindings),
+                _ => None,
+            };
+            if let Some(bindings) = bindings {
+                bindings.push((key.to_string(), value.to_string()));
+                continue;
+            }
+
+            let indent = match &section {
+                None => &mut config.indent,
+                Some(filetype) => config.filetype_indent.entry(filetype.clone()).or_default(),
+            };
+            indent.set(key, value).context(Indent {
+                filename,
+                line: line_no,
+            })?;
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn escape_delay(&self) -> Duration {
+        self.escape_delay.unwrap_or(decode::DEFAULT_ESCAPE_DELAY)
+    }
+
+    /// Returns the keys and the names of the commands bound to them.
+    pub(crate) fn bindings(&self) -> &[(String, String)] {
+        &self.bindings
+    }
+
+    /// Returns the keys and the names of the macros bound to them.
+    pub(crate) fn macro_bindings(&self) -> &[(String, String)] {
+        &self.macro_bindings
+    }
+
+    /// Resolves the indent settings for buffers of the given syntax: the built-in defaults of the
+    /// syntax, then the global settings, then the section of the filetype.
+    pub(crate) fn indent_settings(&self, syntax: &Syntax) -> IndentSettings {
+        let mut settings = IndentSettings::default();
+        settings.apply(&syntax.indent);
+        settings.apply(&self.indent);
+        if let Some(ovr) = self.filetype_indent.get(syntax.filetype) {
+            settings.apply(ovr);
+        }
+        settings
+    }
+}
+
+/// Returns `$XDG_CONFIG_HOME/mirri`, the directory of the files written by the user.
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("mirri"))
+}
+
+/// Returns `$XDG_STATE_HOME/mirri`, the directory of the state kept between runs, such as
+/// sessions.
+pub(crate) fn state_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(dir.join("mirri"))
+}
+
+fn config_file() -> Option<PathBuf> {
+    Some(config_dir()?.join("config"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let s = "
+# global settings
+tab_width = 4
+escape_delay = 200
+
+[rust]
+indent_width = 2
+
+[bindings]
+M-s = save-buffer
+
+[macros]
+<f5> = mymacro
+";
+        let config = Config::parse(Path::new("config"), s).unwrap();
+        let rust = Syntax::select(Some("main.rs"));
+        let indent = config.indent_settings(rust);
+        assert_eq!(indent.tab_width, 4);
+        assert_eq!(indent.indent_width, 2);
+        assert!(indent.expand_tab);
+        assert_eq!(config.escape_delay(), Duration::from_millis(200));
+        assert_eq!(
+            config.bindings(),
+            &[("M-s".to_string(), "save-buffer".to_string())]
+        );
+        assert_eq!(
+            config.macro_bindings(),
+            &[("<f5>".to_string(), "mymacro".to_string())]
+        );
+
+        let plain = Syntax::select(None::<&str>);
+        let indent = config.indent_settings(plain);
+        assert_eq!(indent.tab_width, 4);
+        assert_eq!(indent.indent_width, 8);
+        assert!(!indent.expand_tab);
+
+        // Global settings override the defaults of the syntax
+        let config = Config::parse(Path::new("config"), "expand_tab = false").unwrap();
+        let indent = config.indent_settings(rust);
+        assert_eq!(indent.indent_width, 4);
+        assert!(!indent.expand_tab);
+
+        assert!(Config::parse(Path::new("config"), "tab_width").is_err());
+        assert!(Config::parse(Path::new("config"), "tab_width = x").is_err());
+        assert!(Config::parse(Path::new("config"), "escape_delay = x").is_err());
+        assert!(Config::parse(Path::new("config"), "[rust]\nescape_delay = 10").is_err());
+    }
+}
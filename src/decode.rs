@@ -1,11 +1,17 @@
-use crate::input::{Input, Key};
+use crate::{
+    geom::Point,
+    input::{Input, Key, Mouse, MouseButton, MouseKind},
+    macros::Macro,
+};
 use log::{trace, warn};
 use smallvec::SmallVec;
 use snafu::{Backtrace, ResultExt, Snafu};
 use std::{
+    collections::VecDeque,
     fmt::Debug,
     io::{self, Read},
     str::{self, Utf8Error},
+    time::Duration,
 };
 
 #[derive(Debug, Snafu)]
@@ -26,20 +32,118 @@ pub(crate) enum Error {
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Terminal input that can be waited for with a timeout.
+pub(crate) trait InputSource: Read {
+    /// Waits until input is available or the timeout expires, and returns whether input is
+    /// available.
+    fn poll_input(&mut self, timeout: Duration) -> io::Result<bool>;
+}
+
 #[derive(Debug)]
 pub(crate) struct Decoder {
     unread_char: Option<char>,
     read_buf: String,
+    escape_delay: Duration,
+    // Inputs read while recording a keyboard macro
+    recording: Option<Vec<Input>>,
+    // Inputs of keyboard macros to return before reading the terminal
+    replay: VecDeque<Input>,
+    // Inputs returned from keyboard macros since the last one read from the terminal
+    replayed: usize,
+    // Limits `replayed`, since macros could run themselves endlessly
+    max_replay_inputs: usize,
+    // Inputs read from the terminal during a replay, returned after it
+    typed_ahead: VecDeque<Input>,
+    // Whether a replay was aborted by C-G or ESC since last asked
+    interrupted: bool,
 }
 
+pub(crate) const DEFAULT_ESCAPE_DELAY: Duration = Duration::from_millis(50);
+
+const PASTE_END: &str = "\x1b[201~";
+// How long to wait for the rest of a paste
+const PASTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const DEFAULT_MAX_REPLAY_INPUTS: usize = 100_000;
+
 impl Decoder {
     pub(crate) fn new() -> Self {
         Decoder {
             unread_char: None,
             read_buf: String::new(),
+            escape_delay: DEFAULT_ESCAPE_DELAY,
+            recording: None,
+            replay: VecDeque::new(),
+            replayed: 0,
+            max_replay_inputs: DEFAULT_MAX_REPLAY_INPUTS,
+            typed_ahead: VecDeque::new(),
+            interrupted: false,
         }
     }
 
+    /// Sets how long to wait after ESC for the rest of an escape sequence or an Alt-modified
+    /// key. A lone ESC is reported after the delay.
+    pub(crate) fn set_escape_delay(&mut self, escape_delay: Duration) {
+        self.escape_delay = escape_delay;
+    }
+
+    /// Sets how many inputs keyboard macros can replay without reading the terminal.
+    #[cfg(test)]
+    pub(crate) fn set_max_replay_inputs(&mut self, max_replay_inputs: usize) {
+        self.max_replay_inputs = max_replay_inputs;
+    }
+
+    /// Returns `true` if input has been read from the terminal but not returned yet, or a keyboard
+    /// macro is being replayed.
+    pub(crate) fn has_buffered_input(&self) -> bool {
+        self.unread_char.is_some() || !self.replay.is_empty() || !self.typed_ahead.is_empty()
+    }
+
+    /// Returns `true` once after a replay has been aborted by C-G or ESC.
+    pub(crate) fn take_interrupted(&mut self) -> bool {
+        std::mem::replace(&mut self.interrupted, false)
+    }
+
+    /// Starts recording the inputs read from the terminal.
+    pub(crate) fn start_recording(&mut self) {
+        self.recording = Some(vec![]);
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Stops recording and returns the recorded inputs, except for the last one which is the
+    /// input that stopped the recording.
+    pub(crate) fn stop_recording(&mut self) -> Option<Macro> {
+        let mut inputs = self.recording.take()?;
+        let _ = inputs.pop();
+        Some(Macro::new(inputs))
+    }
+
+    /// Queues the inputs of a macro `count` times, to be read before any further input. Returns
+    /// `false` and drops the queued inputs if too many would be replayed without reading the
+    /// terminal, as when a macro runs itself.
+    pub(crate) fn replay(&mut self, mac: &Macro, count: usize) -> bool {
+        let len = mac.inputs().len().saturating_mul(count);
+        let total = self.replayed.saturating_add(self.replay.len());
+        if total.saturating_add(len) > self.max_replay_inputs {
+            self.replay.clear();
+            return false;
+        }
+        // Macros run from a macro are replayed before the rest of it
+        let mut replay = mac
+            .inputs()
+            .iter()
+            .cycle()
+            .take(len)
+            .cloned()
+            .collect::<VecDeque<_>>();
+        replay.append(&mut self.replay);
+        self.replay = replay;
+        true
+    }
+
     fn read_byte(&mut self, reader: &mut impl Read) -> Result<Option<u8>> {
         let mut buf = [0];
         let byte = match reader.read(&mut buf).context(TerminalInput)? {
@@ -86,12 +190,25 @@ impl Decoder {
         Ok(ch)
     }
 
+    // Reads a char only if it arrives within the timeout
+    fn read_char_within(
+        &mut self,
+        reader: &mut impl InputSource,
+        timeout: Duration,
+    ) -> Result<Option<char>> {
+        if self.unread_char.is_none() && !reader.poll_input(timeout).context(TerminalInput)? {
+            trace!("read_char_within: timed out");
+            return Ok(None);
+        }
+        self.read_char(reader)
+    }
+
     fn set_unread_char(&mut self, ch: char) {
         assert!(self.unread_char.is_none());
         self.unread_char = Some(ch);
     }
 
-    fn read_raw_input(&mut self, reader: &mut impl Read) -> Result<Option<Input>> {
+    fn read_raw_input(&mut self, reader: &mut impl InputSource) -> Result<Option<Input>> {
         use Key::*;
 
         match self.read_char(reader)? {
@@ -99,39 +216,21 @@ impl Decoder {
             Some(esc @ '\x1b') => {
                 self.read_buf.clear();
                 self.read_buf.push(esc);
-                let ch = match self.read_char(reader)? {
-                    Some(ch) if ch != '[' => {
-                        self.set_unread_char(ch);
-                        return Ok(Some(Input::ctrl(Char('['))));
+                match self.read_char_within(reader, self.escape_delay)? {
+                    Some('[') => {
+                        self.read_buf.push('[');
+                        self.read_csi(reader).map(Some)
                     }
-                    Some(ch) => ch,
-                    None => return Ok(Some(Input::ctrl(Char('[')))),
-                };
-
-                self.read_buf.push(ch);
-                while let Some(ch) = self.read_char(reader)? {
-                    self.read_buf.push(ch);
-                    match ch {
-                        'A' | 'B' | 'C' | 'D' | 'H' | 'F' | 'Z' | '~' => break,
-                        _ => continue,
+                    Some('O') => {
+                        self.read_buf.push('O');
+                        self.read_ss3(reader).map(Some)
                     }
-                }
-                let key = match &self.read_buf[..] {
-                    "\x1b[1~" | "\x1b[7~" | "\x1b[H" => Home,
-                    "\x1b[3~" => Delete,
-                    "\x1b[4~" | "\x1b[8~" | "\x1b[F" => End,
-                    "\x1b[5~" => PageUp,
-                    "\x1b[6~" => PageDown,
-                    "\x1b[A" => ArrowUp,
-                    "\x1b[B" => ArrowDown,
-                    "\x1b[C" => ArrowRight,
-                    "\x1b[D" => ArrowLeft,
-                    seq => {
-                        warn!("read_char_raw: unknown seq {:?}", seq);
-                        return Ok(Some(Input::new(Char('?'))));
+                    Some(ch) => {
+                        self.set_unread_char(ch);
+                        Ok(Some(Input::ctrl(Char('['))))
                     }
-                };
-                Ok(Some(Input::new(key)))
+                    None => Ok(Some(Input::ctrl(Char('[')))),
+                }
             }
             Some(ch) if ch.is_ascii_control() => {
                 let key = Key::Char((ch as u8 ^ 0x40) as char);
@@ -141,9 +240,241 @@ impl Decoder {
         }
     }
 
-    pub(crate) fn read_input(&mut self, reader: &mut impl Read) -> Result<Option<Input>> {
+    // CSI: `ESC [ <params> <final>`
+    fn read_csi(&mut self, reader: &mut impl InputSource) -> Result<Input> {
+        use Key::*;
+
+        let mut params = String::new();
+        let final_ch = loop {
+            match self.read_char_within(reader, self.escape_delay)? {
+                // Linux console function keys: `ESC [ [ A` .. `ESC [ [ E`
+                Some('[') if params.is_empty() => {
+                    self.read_buf.push('[');
+                    return Ok(match self.read_char_within(reader, self.escape_delay)? {
+                        Some(ch @ 'A'..='E') => Input::new(F(ch as u8 - b'A' + 1)),
+                        _ => self.unknown_sequence(),
+                    });
+                }
+                Some(ch) => {
+                    self.read_buf.push(ch);
+                    if ('\x40'..='\x7e').contains(&ch) {
+                        break ch;
+                    }
+                    params.push(ch);
+                }
+                // A lone `ESC [` is M-[
+                None if self.read_buf.len() == 2 => return Ok(Input::alt(Char('['))),
+                None => return Ok(self.unknown_sequence()),
+            }
+        };
+
+        if let Some(params) = params.strip_prefix('<') {
+            if final_ch == 'M' || final_ch == 'm' {
+                return Ok(self.sgr_mouse(params, final_ch == 'M'));
+            }
+        }
+
+        let params = params
+            .split(';')
+            // Ignore sub-parameters such as the shifted key in kitty's `<code>:<shifted>`
+            .map(|p| p.split(':').next().unwrap().parse::<u32>().ok())
+            .collect::<SmallVec<[_; 3]>>();
+        let param = |idx: usize| params.get(idx).copied().flatten();
+
+        let key = match final_ch {
+            'A' => ArrowUp,
+            'B' => ArrowDown,
+            'C' => ArrowRight,
+            'D' => ArrowLeft,
+            'H' => Home,
+            'F' => End,
+            'P' => F(1),
+            'Q' => F(2),
+            'R' => F(3),
+            'S' => F(4),
+            'Z' => BackTab,
+            // kitty keyboard protocol: `CSI <code> ; <modifier> u`
+            'u' => {
+                return Ok(param(0)
+                    .and_then(|code| keycode_input(code, param(1)))
+                    .unwrap_or_else(|| self.unknown_sequence()))
+            }
+            // modifyOtherKeys: `CSI 27 ; <modifier> ; <code> ~`
+            '~' if param(0) == Some(27) => {
+                return Ok(param(2)
+                    .and_then(|code| keycode_input(code, param(1)))
+                    .unwrap_or_else(|| self.unknown_sequence()))
+            }
+            '~' if param(0) == Some(200) => return self.read_paste(reader),
+            '~' => match param(0) {
+                Some(1) | Some(7) => Home,
+                Some(2) => Insert,
+                Some(3) => Delete,
+                Some(4) | Some(8) => End,
+                Some(5) => PageUp,
+                Some(6) => PageDown,
+                Some(n @ 11..=15) => F((n - 10) as u8),
+                Some(n @ 17..=21) => F((n - 11) as u8),
+                Some(n @ 23..=24) => F((n - 12) as u8),
+                _ => return Ok(self.unknown_sequence()),
+            },
+            _ => return Ok(self.unknown_sequence()),
+        };
+        Ok(with_modifier(key, param(1)))
+    }
+
+    // SS3: `ESC O <final>`, sent by keypads in application mode
+    fn read_ss3(&mut self, reader: &mut impl InputSource) -> Result<Input> {
+        use Key::*;
+
+        let mut modifier = String::new();
+        let final_ch = loop {
+            match self.read_char_within(reader, self.escape_delay)? {
+                Some(ch) if ch.is_ascii_digit() => {
+                    self.read_buf.push(ch);
+                    modifier.push(ch);
+                }
+                Some(ch) => {
+                    self.read_buf.push(ch);
+                    break ch;
+                }
+                // A lone `ESC O` is M-O
+                None if modifier.is_empty() => return Ok(Input::alt(Char('O'))),
+                None => return Ok(self.unknown_sequence()),
+            }
+        };
+
+        let key = match final_ch {
+            'A' => ArrowUp,
+            'B' => ArrowDown,
+            'C' => ArrowRight,
+            'D' => ArrowLeft,
+            'H' => Home,
+            'F' => End,
+            'P' => F(1),
+            'Q' => F(2),
+            'R' => F(3),
+            'S' => F(4),
+            _ => return Ok(self.unknown_sequence()),
+        };
+        Ok(with_modifier(key, modifier.parse().ok()))
+    }
+
+    // Reads text up to `ESC [ 201 ~` after `ESC [ 200 ~`
+    fn read_paste(&mut self, reader: &mut impl InputSource) -> Result<Input> {
+        let mut text = String::new();
+        while !text.ends_with(PASTE_END) {
+            match self.read_char_within(reader, PASTE_TIMEOUT)? {
+                Some(ch) => text.push(ch),
+                None => {
+                    warn!("read_paste: end of paste not found");
+                    break;
+                }
+            }
+        }
+        if text.ends_with(PASTE_END) {
+            text.truncate(text.len() - PASTE_END.len());
+        }
+        Ok(Input::new(Key::Paste(text)))
+    }
+
+    // SGR mouse report: `ESC [ < <button> ; <x> ; <y> M` (or `m` when released)
+    fn sgr_mouse(&self, params: &str, pressed: bool) -> Input {
+        let params = params
+            .split(';')
+            .map(|p| p.parse::<usize>().ok())
+            .collect::<SmallVec<[_; 3]>>();
+        let (code, x, y) = match params[..] {
+            [Some(code), Some(x), Some(y)] if x > 0 && y > 0 => (code, x - 1, y - 1),
+            _ => return self.unknown_sequence(),
+        };
+
+        let button = match code & 0b11 {
+            0 => MouseButton::Left,
+            1 => MouseButton::Middle,
+            2 => MouseButton::Right,
+            _ => return self.unknown_sequence(),
+        };
+        let kind = match (code & 0b0110_0000, pressed) {
+            (0b0100_0000, _) if code & 0b11 == 0 => MouseKind::WheelUp,
+            (0b0100_0000, _) if code & 0b11 == 1 => MouseKind::WheelDown,
+            (0b0010_0000, _) => MouseKind::Drag(button),
+            (0, true) => MouseKind::Press(button),
+            (0, false) => MouseKind::Release(button),
+            _ => return self.unknown_sequence(),
+        };
+        Input {
+            key: Key::Mouse(Mouse {
+                kind,
+                pos: Point { x, y },
+            }),
+            shift: code & 0b0_0100 != 0,
+            alt: code & 0b0_1000 != 0,
+            ctrl: code & 0b1_0000 != 0,
+        }
+    }
+
+    fn unknown_sequence(&self) -> Input {
+        warn!("read_raw_input: unknown seq {:?}", self.read_buf);
+        Input::new(Key::Char('?'))
+    }
+
+    /// Reads an input, which comes from a replayed keyboard macro if any.
+    ///
+    /// Input typed during a replay is read after it, except for C-G or ESC, which abort it and
+    /// are dropped; `None` is returned then. While recording, inputs read from the terminal are
+    /// recorded.
+    pub(crate) fn read_input(&mut self, reader: &mut impl InputSource) -> Result<Option<Input>> {
+        if !self.replay.is_empty() {
+            let typed = self.unread_char.is_some()
+                || reader
+                    .poll_input(Duration::from_secs(0))
+                    .context(TerminalInput)?;
+            if typed {
+                match self.read_terminal_input(reader)? {
+                    Some(input)
+                        if input == Input::ctrl(Key::Char('G'))
+                            || input == Input::ctrl(Key::Char('[')) =>
+                    {
+                        trace!("read_input: replay aborted by {:?}", input);
+                        self.replay.clear();
+                        self.replayed = 0;
+                        self.interrupted = true;
+                        return Ok(None);
+                    }
+                    Some(input) => self.typed_ahead.push_back(input),
+                    None => {}
+                }
+            }
+        }
+        if let Some(input) = self.replay.pop_front() {
+            trace!("read_input (from macro): Some({:?})", input);
+            self.replayed += 1;
+            return Ok(Some(input));
+        }
+        self.replayed = 0;
+        if let Some(input) = self.typed_ahead.pop_front() {
+            return Ok(Some(input));
+        }
+        self.read_terminal_input(reader)
+    }
+
+    fn read_terminal_input(&mut self, reader: &mut impl InputSource) -> Result<Option<Input>> {
+        let input = self.decode_input(reader)?;
+        if let (Some(recording), Some(input)) = (&mut self.recording, &input) {
+            recording.push(input.clone());
+        }
+        Ok(input)
+    }
+
+    fn decode_input(&mut self, reader: &mut impl InputSource) -> Result<Option<Input>> {
         if let Some(input) = self.read_raw_input(reader)? {
-            if input != Input::ctrl(Key::Char('[')) {
+            // ESC starts an Alt sequence only if the next key followed within the escape delay.
+            // An escape key reported as a CSI sequence never does.
+            if input != Input::ctrl(Key::Char('['))
+                || self.read_buf != "\x1b"
+                || self.unread_char.is_none()
+            {
                 trace!("read_input: Some({:?})", input);
                 return Ok(Some(input));
             }
@@ -160,11 +491,92 @@ impl Decoder {
     }
 }
 
+// xterm encodes modifiers as `1 + (shift | alt << 1 | ctrl << 2 | meta << 3)`
+fn with_modifier(key: Key, modifier: Option<u32>) -> Input {
+    let bits = modifier.unwrap_or(1).saturating_sub(1);
+    Input {
+        key,
+        shift: bits & 0b0001 != 0,
+        alt: bits & 0b1010 != 0,
+        ctrl: bits & 0b0100 != 0,
+    }
+}
+
+// Converts a key reported by its Unicode code point into the same input as the legacy encoding
+// where one exists. Only the keys that the legacy encoding cannot express differ: `C-i` and `C-m`
+// are distinct from `C-I` (Tab) and `C-M` (Enter), and `C-S-X` keeps the shift modifier.
+fn keycode_input(code: u32, modifier: Option<u32>) -> Option<Input> {
+    let mut input = with_modifier(Key::Char(char::from_u32(code)?), modifier);
+    let ch = match input.key {
+        Key::Char(ch) => ch,
+        _ => unreachable!(),
+    };
+    match code {
+        // Tab, Enter, Escape and Backspace
+        9 if input.shift => {
+            input.key = Key::BackTab;
+            input.shift = false;
+        }
+        9 | 13 | 27 | 127 => {
+            input.key = Key::Char((code as u8 ^ 0x40) as char);
+            input.ctrl = true;
+        }
+        _ if input.ctrl && (ch == 'i' || ch == 'm') => {}
+        _ if input.ctrl && (ch == ' ' || ('@'..='\x7f').contains(&ch)) => {
+            input.key = Key::Char(((code as u8 & 0x1f) ^ 0x40) as char);
+        }
+        _ => {}
+    }
+    // Without Ctrl, Shift is a part of the character as in the legacy encoding
+    if let (Key::Char(ch), false, true) = (&input.key, input.ctrl, input.shift) {
+        let mut upper = ch.to_uppercase();
+        if let (Some(upper), None) = (upper.next(), upper.next()) {
+            input.key = Key::Char(upper);
+            input.shift = false;
+        }
+    }
+    Some(input)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::input::InputStrExt;
-    use std::{io::Cursor, iter};
+    use std::{collections::VecDeque, io::Cursor, iter};
+
+    impl<T: AsRef<[u8]>> InputSource for Cursor<T> {
+        fn poll_input(&mut self, _timeout: Duration) -> io::Result<bool> {
+            Ok((self.position() as usize) < self.get_ref().as_ref().len())
+        }
+    }
+
+    // Input arriving in chunks, with the escape delay expiring between them
+    struct Chunks(VecDeque<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            while let Some(chunk) = self.0.front_mut() {
+                if !chunk.is_empty() {
+                    return chunk.read(buf);
+                }
+                self.0.pop_front();
+            }
+            Ok(0)
+        }
+    }
+
+    impl InputSource for Chunks {
+        fn poll_input(&mut self, _timeout: Duration) -> io::Result<bool> {
+            match self.0.front() {
+                Some(chunk) if !chunk.is_empty() => Ok(true),
+                Some(_) => {
+                    self.0.pop_front();
+                    Ok(false)
+                }
+                None => Ok(false),
+            }
+        }
+    }
 
     #[test]
     fn decode_char() {
@@ -257,5 +669,108 @@ mod tests {
         check("\x1b\x1b", "C-M-[".inputs().map(|i| i.unwrap()).collect());
         check("\x1b\x00", "C-M-@".inputs().map(|i| i.unwrap()).collect());
         check("\x1b\x05", "C-M-E".inputs().map(|i| i.unwrap()).collect());
+        check("\x1b[", "M-[".inputs().map(|i| i.unwrap()).collect());
+        check("\x1bO", "M-O".inputs().map(|i| i.unwrap()).collect());
+    }
+
+    #[test]
+    fn decode_esc_with_delay() {
+        fn check(chunks: &[&'static [u8]], expected: &str) {
+            let mut decoder = Decoder::new();
+            let mut output = vec![];
+            let mut reader = Chunks(chunks.iter().copied().collect());
+            while let Ok(Some(input)) = decoder.read_input(&mut reader) {
+                output.push(input);
+            }
+            let expected = expected.inputs().map(|i| i.unwrap()).collect::<Vec<_>>();
+            assert_eq!(output, expected, "{:?}", chunks);
+        }
+
+        check(&[b"\x1b", b"a"], "C-[ a");
+        check(&[b"\x1ba"], "M-a");
+        check(&[b"\x1b", b"\x1b", b"a"], "C-[ C-[ a");
+        check(&[b"\x1b\x1b", b"[A"], "C-M-[ [ A");
+        check(&[b"\x1b[", b"A"], "M-[ A");
+        check(&[b"\x1b[1;5", b"A"], "? A");
+    }
+
+    #[test]
+    fn decode_escape_sequence() {
+        fn check(input: &str, expected: &str) {
+            let mut decoder = Decoder::new();
+            let mut output = vec![];
+            let mut cur = Cursor::new(input.as_bytes());
+            while let Ok(Some(input)) = decoder.read_input(&mut cur) {
+                output.push(input);
+            }
+            let expected = expected.inputs().map(|i| i.unwrap()).collect::<Vec<_>>();
+            assert_eq!(output, expected, "{:?}", input);
+        }
+
+        check("\x1b[A\x1b[B\x1b[C\x1b[D", "<up> <down> <right> <left>");
+        check("\x1bOA\x1bOH\x1bOF", "<up> <home> <end>");
+        check("\x1b[1~\x1b[7~\x1b[H", "<home> <home> <home>");
+        check("\x1b[4~\x1b[8~\x1b[F", "<end> <end> <end>");
+        check(
+            "\x1b[2~\x1b[3~\x1b[5~\x1b[6~",
+            "<insert> <delete> <page up> <page down>",
+        );
+        check("\x1b[Z", "<backtab>");
+        check("\x1bOP\x1bOS\x1b[15~\x1b[24~", "<f1> <f4> <f5> <f12>");
+        check("\x1b[11~\x1b[17~\x1b[21~", "<f1> <f6> <f10>");
+        check("\x1b[[A\x1b[[E", "<f1> <f5>");
+        check("\x1b[1;5C\x1b[1;2A\x1b[1;3D", "<C-right> <S-up> <M-left>");
+        check(
+            "\x1b[1;8H\x1b[3;5~\x1b[1;2P",
+            "<C-M-S-home> <C-delete> <S-f1>",
+        );
+        check("\x1bO5C", "<C-right>");
+        check("\x1b\x1b[A", "<M-up>");
+        check("\x1b[99~a", "? a");
+        check(
+            "\x1b[<0;1;2M\x1b[<32;5;2M\x1b[<0;5;3m",
+            "<left press 0,1> <left drag 4,1> <left release 4,2>",
+        );
+        check(
+            "\x1b[<64;10;10M\x1b[<65;10;10M\x1b[<18;3;4M",
+            "<wheel up 9,9> <wheel down 9,9> <C-right press 2,3>",
+        );
+        check("\x1b[<0;0;0M", "?");
+    }
+
+    #[test]
+    fn decode_keycode() {
+        fn check(input: &str, expected: &str) {
+            let mut decoder = Decoder::new();
+            let mut output = vec![];
+            let mut cur = Cursor::new(input.as_bytes());
+            while let Ok(Some(input)) = decoder.read_input(&mut cur) {
+                output.push(input);
+            }
+            let expected = expected.inputs().map(|i| i.unwrap()).collect::<Vec<_>>();
+            assert_eq!(output, expected, "{:?}", input);
+        }
+
+        // kitty
+        check("\x1b[105;5u\x1b[9u\x1b[9;2u", "C-i C-I <backtab>");
+        check("\x1b[109;5u\x1b[13u\x1b[27u\x1b[127u", "C-m C-M C-[ C-?");
+        check("\x1b[120;5u\x1b[120;6u\x1b[120;3u", "C-X C-S-X M-x");
+        check("\x1b[120;4u\x1b[32;5u\x1b[49;5u", "M-X C-@ C-1");
+        check("\x1b[97:65;6u\x1b[1;5A", "C-S-A <C-up>");
+        // modifyOtherKeys
+        check("\x1b[27;5;105~\x1b[27;6;120~", "C-i C-S-X");
+    }
+
+    #[test]
+    fn decode_paste() {
+        let input = "a\x1b[200~b\r\x1b[Ac\x1b[201~d\x1b[200~e";
+        let mut decoder = Decoder::new();
+        let mut cur = Cursor::new(input.as_bytes());
+        let mut read = || decoder.read_input(&mut cur).unwrap().unwrap();
+        assert_eq!(read(), Input::new(Key::Char('a')));
+        assert_eq!(read(), Input::new(Key::Paste("b\r\x1b[Ac".into())));
+        assert_eq!(read(), Input::new(Key::Char('d')));
+        // Unterminated paste
+        assert_eq!(read(), Input::new(Key::Paste("e".into())));
     }
 }
@@ -0,0 +1,344 @@
+use crate::{decode::Decoder, editor::Editor, file, keypress, terminal::Terminal};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Lines before the first entry in a listing
+const HEADER_LINES: usize = 2;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: OsString,
+    is_dir: bool,
+    size: u64,
+    mode: u32,
+    modified: Option<SystemTime>,
+}
+
+impl Entry {
+    fn display_name(&self) -> String {
+        let mut name = self.name.to_string_lossy().into_owned();
+        if self.is_dir {
+            name.push('/');
+        }
+        name
+    }
+}
+
+/// The files in a directory, as shown in a directory buffer. Directories come first.
+#[derive(Debug, Clone)]
+pub(crate) struct Listing {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+}
+
+impl Listing {
+    pub(crate) fn read(dir: impl Into<PathBuf>) -> file::Result<Self> {
+        let dir = dir.into();
+        let mut entries = file::read_dir(&dir)?
+            .into_iter()
+            .map(|(name, metadata)| Entry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                mode: metadata.permissions().mode(),
+                modified: metadata.modified().ok(),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+        Ok(Listing { dir, entries })
+    }
+
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the name shown for the buffer, such as `src/`.
+    pub(crate) fn name(&self) -> String {
+        match self.dir.file_name() {
+            Some(name) => format!("{}/", name.to_string_lossy()),
+            None => self.dir.display().to_string(),
+        }
+    }
+
+    /// Returns the path of the entry shown at the 0-based line.
+    pub(crate) fn path_at(&self, line: usize) -> Option<PathBuf> {
+        let entry = self.entries.get(line.checked_sub(HEADER_LINES)?)?;
+        if entry.name == ".." {
+            return self.dir.parent().map(Path::to_path_buf);
+        }
+        Some(self.dir.join(&entry.name))
+    }
+
+    /// Returns the 0-based line showing the entry of the name.
+    pub(crate) fn line_of(&self, name: &OsStr) -> Option<usize> {
+        let idx = self.entries.iter().position(|entry| entry.name == name)?;
+        Some(idx + HEADER_LINES)
+    }
+
+    pub(crate) fn text(&self) -> String {
+        let sizes = self
+            .entries
+            .iter()
+            .map(|entry| {
+                if entry.is_dir {
+                    "-".to_string()
+                } else {
+                    entry.size.to_string()
+                }
+            })
+            .collect::<Vec<_>>();
+        let size_width = sizes.iter().map(String::len).max().unwrap_or(0);
+
+        let mut text = format!("{}:\n", self.dir.display());
+        text.push_str("Enter: open  M-x create-file, rename-file, delete-file\n");
+        for (entry, size) in self.entries.iter().zip(sizes) {
+            text.push_str(&format!(
+                "{}  {:>sw$}  {}  {}\n",
+                mode_string(entry.mode, entry.is_dir),
+                size,
+                entry
+                    .modified
+                    .map(format_time)
+                    .unwrap_or_else(|| format!("{:16}", "?")),
+                entry.display_name(),
+                sw = size_width,
+            ));
+        }
+        text
+    }
+}
+
+// Formats the permissions as `ls -l` does, such as `drwxr-xr-x`
+fn mode_string(mode: u32, is_dir: bool) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(if is_dir { 'd' } else { '-' });
+    for shift in &[6, 3, 0] {
+        let bits = mode >> shift;
+        s.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        s.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        s.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    s
+}
+
+// Formats the time in UTC as `YYYY-MM-DD hh:mm`
+fn format_time(time: SystemTime) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60
+    )
+}
+
+// Converts days since 1970-01-01 to a date in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// The directory of the current buffer and the path of the entry at the cursor
+fn current(editor: &Editor) -> Option<(PathBuf, Option<PathBuf>)> {
+    let line = editor.status()?.cursor.y;
+    let buffer = editor.buffer()?;
+    let listing = buffer.directory()?;
+    Some((listing.dir().to_path_buf(), listing.path_at(line)))
+}
+
+/// Opens the file or the directory at the cursor.
+pub(crate) fn open_entry(editor: &mut Editor) {
+    match current(editor) {
+        Some((_, Some(path))) => {
+            let _ = editor.open(path);
+        }
+        Some((_, None)) => {}
+        None => editor.set_status_message("Not a directory listing"),
+    }
+}
+
+/// Prompts for the name of a file to create in the listed directory. A name ending with `/`
+/// creates a directory.
+pub(crate) fn create_file(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let dir = match current(editor) {
+        Some((dir, _)) => dir,
+        None => {
+            editor.set_status_message("Not a directory listing");
+            return Ok(());
+        }
+    };
+    let name = match keypress::prompt(
+        term,
+        decoder,
+        editor,
+        "create-file",
+        "Create file: {} (ESC to cancel)",
+    )? {
+        Some(name) if !name.is_empty() => name,
+        _ => {
+            editor.set_status_message("Create aborted");
+            return Ok(());
+        }
+    };
+    let path = dir.join(&name);
+    if file::exists(&path) {
+        editor.set_status_message(format!("{} already exists", path.display()));
+        return Ok(());
+    }
+    let prompt = format!("Create {}? (yes or no) {{}}", path.display());
+    if !keypress::prompt_confirm(term, decoder, editor, &prompt)? {
+        editor.set_status_message("Create aborted");
+        return Ok(());
+    }
+    let res = if name.ends_with('/') {
+        file::create_dir(&path)
+    } else {
+        file::create(&path)
+    };
+    finish(editor, res, &path);
+    Ok(())
+}
+
+/// Prompts for a new name of the file at the cursor, relative to the listed directory.
+pub(crate) fn rename_file(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let (dir, from) = match entry_at_cursor(editor) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    let from_name = from.file_name().unwrap_or_default().to_string_lossy();
+    let prompt = format!("Rename {} to: {{}} (ESC to cancel)", from_name);
+    let to = match keypress::prompt(term, decoder, editor, "rename-file", &prompt)? {
+        Some(name) if !name.is_empty() => dir.join(name),
+        _ => {
+            editor.set_status_message("Rename aborted");
+            return Ok(());
+        }
+    };
+    let prompt = if file::exists(&to) {
+        format!("{} exists; overwrite? (yes or no) {{}}", to.display())
+    } else {
+        format!("Rename {} to {}? (yes or no) {{}}", from_name, to.display())
+    };
+    if !keypress::prompt_confirm(term, decoder, editor, &prompt)? {
+        editor.set_status_message("Rename aborted");
+        return Ok(());
+    }
+    let res = file::rename(&from, &to);
+    finish(editor, res, &to);
+    Ok(())
+}
+
+/// Deletes the file or the empty directory at the cursor.
+pub(crate) fn delete_file(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let (_, path) = match entry_at_cursor(editor) {
+        Some(entry) => entry,
+        None => return Ok(()),
+    };
+    let prompt = format!("Delete {}? (yes or no) {{}}", path.display());
+    if !keypress::prompt_confirm(term, decoder, editor, &prompt)? {
+        editor.set_status_message("Delete aborted");
+        return Ok(());
+    }
+    let res = file::remove(&path);
+    finish(editor, res, &path);
+    Ok(())
+}
+
+// The listed directory and the entry at the cursor, which cannot be `..`
+fn entry_at_cursor(editor: &mut Editor) -> Option<(PathBuf, PathBuf)> {
+    match current(editor) {
+        Some((dir, Some(path))) if path.parent() == Some(&dir) => Some((dir, path)),
+        Some(_) => {
+            editor.set_status_message("No file at the cursor");
+            None
+        }
+        None => {
+            editor.set_status_message("Not a directory listing");
+            None
+        }
+    }
+}
+
+// Reports the result and reads the listing again, moving the cursor to the file if it exists
+fn finish(editor: &mut Editor, res: file::Result<()>, path: &Path) {
+    if let Err(e) = res {
+        editor.set_status_message(format!("{}", e));
+    }
+    editor.reload_directory(path.file_name());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::{fs, process};
+
+    #[test]
+    fn format() {
+        assert_eq!(mode_string(0o755, true), "drwxr-xr-x");
+        assert_eq!(mode_string(0o100_640, false), "-rw-r-----");
+
+        assert_eq!(format_time(UNIX_EPOCH), "1970-01-01 00:00");
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(951_827_696);
+        assert_eq!(format_time(time), "2000-02-29 12:34");
+    }
+
+    #[test]
+    fn listing() {
+        let temp = TempDir::new("listing");
+        let dir = temp.path();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "hello\n").unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+
+        let listing = Listing::read(dir).unwrap();
+        let text = listing.text();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], format!("{}:", dir.display()));
+        assert_eq!(lines.len(), HEADER_LINES + 4);
+        assert!(lines[2].starts_with('d') && lines[2].ends_with("  ../"));
+        assert!(
+            lines[3].starts_with('d') && lines[3].contains("  -  ") && lines[3].ends_with("  sub/")
+        );
+        assert!(lines[4].ends_with("  a.txt"));
+        assert!(lines[5].contains("  6  ") && lines[5].ends_with("  b.txt"));
+
+        assert_eq!(listing.name(), format!("mirri-listing-{}/", process::id()));
+        assert_eq!(listing.path_at(1), None);
+        assert_eq!(listing.path_at(2).as_deref(), dir.parent());
+        assert_eq!(listing.path_at(3), Some(dir.join("sub")));
+        assert_eq!(listing.path_at(6), None);
+        assert_eq!(listing.line_of(OsStr::new("b.txt")), Some(5));
+    }
+}
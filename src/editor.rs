@@ -1,19 +1,33 @@
 use crate::{
+    config::Config,
     decode::Decoder,
+    file,
     frame::{self, Frame, SplitOrientation},
     geom::{Point, Size},
+    goto::GotoTarget,
+    history::Histories,
+    indent::IndentOverride,
+    input::{Mouse, MouseButton, MouseKind},
     keypress,
-    status_message::StatusMessage,
-    terminal::RawTerminal,
-    text_buffer::TextBuffer,
+    macros::Macros,
+    positions::{self, Position, Positions},
+    screen::Screen,
+    session::{Session, ViewState},
+    status_message::{StatusMessage, STATUS_MESSAGE_TIMEOUT},
+    terminal::Terminal,
+    text_buffer::{BufferId, TextBuffer},
     text_buffer_view::{self, Status, TextBufferView},
+    timer::TimerWheel,
     welcome::{self, Welcome},
 };
 use itertools::Either;
 use std::{
     cell::{Ref, RefMut},
-    collections::VecDeque,
-    path::{Path, PathBuf},
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
 };
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -30,6 +44,32 @@ pub(crate) enum CursorMove {
     BufferEnd,
 }
 
+// Lines scrolled by a notch of the mouse wheel
+const WHEEL_SCROLL_LINES: isize = 3;
+
+const TIMER_TICK: Duration = Duration::from_millis(100);
+// How often files are checked for modifications by other programs
+const FILE_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Events scheduled on the timer wheel of the editor.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Timer {
+    StatusMessage,
+    FileWatch,
+}
+
+/// A summary of an open buffer, as shown in the buffer list.
+#[derive(Debug, Clone)]
+pub(crate) struct BufferInfo {
+    pub(crate) id: BufferId,
+    pub(crate) name: String,
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) dirty: bool,
+    pub(crate) readonly: bool,
+    pub(crate) lines: usize,
+    pub(crate) filetype: &'static str,
+}
+
 #[derive(Debug)]
 pub(crate) struct Editor {
     frame: Frame,
@@ -37,22 +77,117 @@ pub(crate) struct Editor {
     welcome: Welcome,
     render_size: Size,
     status_message: StatusMessage,
+    config: Config,
+    stdout_output: Option<Vec<u8>>,
+    // What is displayed on the terminal, or `None` if the whole screen must be redrawn
+    screen: Option<Screen>,
+    timers: TimerWheel<Timer>,
+    macros: Macros,
+    histories: Histories,
+    positions: Positions,
 }
 
 impl Editor {
-    pub(crate) fn new(render_size: Size) -> Self {
+    pub(crate) fn new(render_size: Size, config: Config) -> Self {
+        let now = Instant::now();
+        let mut timers = TimerWheel::new(now, TIMER_TICK);
+        timers.schedule(now, FILE_WATCH_INTERVAL, Timer::FileWatch);
+
         Editor {
             frame: Frame::new(render_size),
             buffer_view: VecDeque::new(),
             welcome: Welcome::new(render_size),
             render_size,
             status_message: StatusMessage::new(),
+            config,
+            stdout_output: None,
+            screen: None,
+            timers,
+            macros: Macros::new(),
+            histories: Histories::new(),
+            positions: Positions::new(),
         }
     }
 
-    pub(crate) fn open(&mut self, filename: impl Into<PathBuf>) {
+    /// Opens a file, or shows the buffer visiting it if it is already open.
+    pub(crate) fn open(&mut self, filename: impl Into<PathBuf>) -> bool {
         let filename = filename.into();
-        match TextBuffer::from_file(filename) {
+        let path = file::canonical_path(&filename);
+        let opened = self
+            .buffer_views()
+            .into_iter()
+            .map(|bv| bv.buffer())
+            .find(|buffer| buffer.canonical_path() == Some(&path))
+            .map(|buffer| buffer.id());
+        if let Some(id) = opened {
+            return self.switch_to_buffer(id);
+        }
+        if file::is_dir(&filename) {
+            return self.show_loaded_buffer(TextBuffer::from_directory(filename));
+        }
+        let buffer = TextBuffer::from_file(filename, &self.config);
+        if !self.show_loaded_buffer(buffer) {
+            return false;
+        }
+        // Put the cursor back where it was when the file was closed, unless the file got shorter
+        if let Some(pos) = self.positions.get(&path) {
+            if let Some(bv) = self.buffer_view_mut() {
+                if bv.buffer().lines() >= pos.lines {
+                    bv.set_position(pos.cursor, pos.top);
+                }
+            }
+        }
+        true
+    }
+
+    pub(crate) fn open_stdin(&mut self, to_stdout: bool) -> bool {
+        let buffer = TextBuffer::from_stdin(io::stdin(), &self.config, to_stdout);
+        self.show_loaded_buffer(buffer)
+    }
+
+    /// Shows a buffer not visiting a file, replacing the hidden buffer of the same name.
+    pub(crate) fn show_buffer(&mut self, buffer: TextBuffer) {
+        let name = buffer.name();
+        self.buffer_view.retain(|bv| bv.buffer().name() != name);
+        let bv = TextBufferView::new(buffer, self.render_size);
+        if let Some(bv) = self.frame.set_buffer_view(bv) {
+            if bv.buffer().name() != name {
+                self.buffer_view.push_back(bv);
+            }
+        }
+    }
+
+    /// Returns `true` if the current buffer is a directory listing.
+    pub(crate) fn in_directory(&self) -> bool {
+        self.buffer()
+            .map(|buffer| buffer.directory().is_some())
+            .unwrap_or(false)
+    }
+
+    /// Reads the directory listed in the current buffer again, and moves the cursor to the entry of
+    /// the name if it is given and still exists.
+    pub(crate) fn reload_directory(&mut self, name: Option<&OsStr>) {
+        let res = match self.buffer_mut() {
+            Some(mut buffer) => buffer.reload_directory(),
+            None => return,
+        };
+        if let Err(e) = res {
+            self.set_status_message(format!("{}", e));
+        }
+        let line = name.and_then(|name| self.buffer()?.directory()?.line_of(name));
+        let target = match line {
+            Some(line) => GotoTarget::Line {
+                line: line + 1,
+                col: None,
+            },
+            // Keeps the cursor within the buffer
+            None => GotoTarget::Relative(0),
+        };
+        self.goto(target);
+    }
+
+    fn show_loaded_buffer(&mut self, buffer: file::Result<TextBuffer>) -> bool {
+        match buffer {
             Ok(buffer) => {
                 if let Some(bv) = self
                     .frame
@@ -60,19 +195,129 @@ impl Editor {
                 {
                     self.buffer_view.push_back(bv);
                 }
+                true
             }
-            Err(e) => self.set_status_message(format!("{}", e)),
+            Err(e) => {
+                self.set_status_message(format!("{}", e));
+                false
+            }
+        }
+    }
+
+    pub(crate) fn macros_mut(&mut self) -> &mut Macros {
+        &mut self.macros
+    }
+
+    /// Returns the views of the files and the frames to save the session.
+    pub(crate) fn session(&self) -> Session {
+        // Buffers not visiting files, and paths which cannot be written in a line, are not saved
+        let view_state = |bv: &TextBufferView| {
+            let buffer = bv.buffer();
+            let path = buffer.canonical_path()?;
+            if path.to_str()?.contains('\n') {
+                return None;
+            }
+            Some(ViewState {
+                path: path.to_path_buf(),
+                cursor: bv.cursor(),
+                top: bv.top_line(),
+            })
+        };
+        Session {
+            frames: self.frame.layout().filter_map(&mut |bv| view_state(bv)),
+            hidden: self.buffer_view.iter().filter_map(view_state).collect(),
+            histories: self.histories.clone(),
+        }
+    }
+
+    /// Replaces the frames and the buffers with the ones of the session. The files which no
+    /// longer exist are skipped.
+    pub(crate) fn restore_session(&mut self, session: &Session) {
+        let mut loaded = HashMap::<PathBuf, TextBufferView>::new();
+        let mut error = None;
+        let (config, render_size) = (&self.config, self.render_size);
+        let mut load = |state: &ViewState| {
+            if !loaded.contains_key(&state.path) {
+                if !file::exists(&state.path) {
+                    return None;
+                }
+                let buffer = if file::is_dir(&state.path) {
+                    TextBuffer::from_directory(&state.path)
+                } else {
+                    TextBuffer::from_file(&state.path, config)
+                };
+                match buffer {
+                    Ok(buffer) => {
+                        let bv = TextBufferView::new(buffer, render_size);
+                        let _ = loaded.insert(state.path.clone(), bv);
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        return None;
+                    }
+                }
+            }
+            // Views of the same file share the buffer
+            let mut bv = loaded[&state.path].clone();
+            bv.set_position(state.cursor, state.top);
+            Some(bv)
+        };
+        let frames = session.frames.clone().filter_map(&mut |state| load(&state));
+        let hidden = session.hidden.iter().filter_map(&mut load).collect();
+        self.frame = Frame::from_layout(frames, self.render_size);
+        self.buffer_view = hidden;
+        if self.frame.buffer_view().is_none() {
+            self.next_buffer();
+        }
+        self.histories = session.histories.clone();
+        if let Some(e) = error {
+            self.set_status_message(format!("{}", e));
+        }
+    }
+
+    pub(crate) fn set_positions(&mut self, positions: Positions) {
+        self.positions = positions;
+    }
+
+    /// Records the cursor positions in the open files, and saves all the positions.
+    pub(crate) fn save_positions(&mut self) -> positions::Result<()> {
+        // The current view is recorded last to win over the other views of the same file
+        let views = self
+            .buffer_view
+            .iter()
+            .chain(self.frame.buffer_views())
+            .chain(self.frame.buffer_view());
+        for bv in views {
+            record_position(&mut self.positions, bv);
         }
+        self.positions.save()
+    }
+
+    pub(crate) fn histories(&self) -> &Histories {
+        &self.histories
+    }
+
+    pub(crate) fn histories_mut(&mut self) -> &mut Histories {
+        &mut self.histories
+    }
+
+    /// Returns the last saved contents of the buffer read in filter mode.
+    pub(crate) fn take_stdout_output(&mut self) -> Option<Vec<u8>> {
+        self.stdout_output.take()
     }
 
     pub(crate) fn open_prompt(
         &mut self,
-        term: &mut RawTerminal,
+        term: &mut impl Terminal,
         decoder: &mut Decoder,
     ) -> keypress::Result<()> {
-        if let Some(filename) =
-            keypress::prompt(term, decoder, self, "Open file: {} (ESC to cancel)")?
-        {
+        if let Some(filename) = keypress::prompt(
+            term,
+            decoder,
+            self,
+            "open-file",
+            "Open file: {} (ESC to cancel)",
+        )? {
             self.open(filename);
         } else {
             self.set_status_message("Open aborted")
@@ -82,19 +327,66 @@ impl Editor {
 
     pub(crate) fn save(
         &mut self,
-        term: &mut RawTerminal,
+        term: &mut impl Terminal,
         decoder: &mut Decoder,
     ) -> keypress::Result<()> {
         if self.buffer().is_none() {
             return Ok(());
         }
 
+        if self.buffer().unwrap().readonly() {
+            self.set_status_message("Buffer is readonly");
+            return Ok(());
+        }
+
+        if self.buffer().unwrap().to_stdout() {
+            let res = self.buffer_mut().unwrap().save_to_bytes();
+            match res {
+                Ok(bytes) => {
+                    self.set_status_message(format!(
+                        "{} bytes will be written to stdout on exit",
+                        bytes.len()
+                    ));
+                    self.stdout_output = Some(bytes);
+                }
+                Err(e) => {
+                    self.set_status_message(format!("Can't save! {}", e));
+                }
+            }
+            return Ok(());
+        }
+
         if self.buffer().unwrap().filename().is_none() {
-            if let Some(filename) =
-                keypress::prompt(term, decoder, self, "Save as: {} (ESC to cancel)")?
-                    .map(Into::into)
+            if let Some(filename) = keypress::prompt(
+                term,
+                decoder,
+                self,
+                "save-as",
+                "Save as: {} (ESC to cancel)",
+            )?
+            .map(PathBuf::from)
             {
-                self.buffer_mut().unwrap().set_filename(Some(filename));
+                // Buffers are unique per file, as `open` shows the buffer of an open file
+                let path = file::canonical_path(&filename);
+                let id = self.buffer().unwrap().id();
+                let other = self
+                    .buffer_views()
+                    .into_iter()
+                    .map(|bv| bv.buffer())
+                    .find(|buffer| buffer.id() != id && buffer.canonical_path() == Some(&path))
+                    .map(|buffer| buffer.name());
+                if let Some(name) = other {
+                    self.set_status_message(format!(
+                        "Can't save! {} is open in buffer {}",
+                        filename.display(),
+                        name
+                    ));
+                    return Ok(());
+                }
+                let config = &self.config;
+                let mut buffer = self.frame.buffer_view_mut().unwrap().buffer_mut();
+                buffer.set_filename(Some(filename));
+                buffer.apply_config(config);
             } else {
                 self.set_status_message("Save aborted");
                 return Ok(());
@@ -123,10 +415,15 @@ impl Editor {
     }
 
     fn buffer_view_or_create(&mut self) -> &mut TextBufferView {
-        self.frame.buffer_view_or_create()
+        let created = self.frame.buffer_view().is_none();
+        let buffer_view = self.frame.buffer_view_or_create();
+        if created {
+            buffer_view.buffer_mut().apply_config(&self.config);
+        }
+        buffer_view
     }
 
-    fn buffer(&self) -> Option<Ref<TextBuffer>> {
+    pub(crate) fn buffer(&self) -> Option<Ref<TextBuffer>> {
         self.buffer_view().map(|bv| bv.buffer())
     }
 
@@ -134,6 +431,94 @@ impl Editor {
         self.buffer_view_mut().map(|bv| bv.buffer_mut())
     }
 
+    pub(crate) fn buffer_id(&self) -> Option<BufferId> {
+        self.buffer().map(|b| b.id())
+    }
+
+    // The buffer views of the open buffers: the current one, the others shown in frames, and then
+    // the hidden ones
+    fn buffer_views(&self) -> Vec<&TextBufferView> {
+        let mut views = self.buffer_view().into_iter().collect::<Vec<_>>();
+        views.extend(self.frame.buffer_views());
+        views.extend(&self.buffer_view);
+        let mut ids = HashSet::new();
+        views.retain(|bv| ids.insert(bv.buffer().id()));
+        views
+    }
+
+    /// Returns the open buffers, the current one first.
+    ///
+    /// Buffers visiting files of the same name are named with their distinct parent directories,
+    /// such as `mod.rs<frame>` and `mod.rs<input>`.
+    pub(crate) fn buffer_list(&self) -> Vec<BufferInfo> {
+        let views = self.buffer_views();
+        let buffers = views.iter().map(|bv| bv.buffer()).collect::<Vec<_>>();
+        let names = unique_names(
+            &buffers
+                .iter()
+                .map(|buffer| (buffer.name(), buffer.canonical_path()))
+                .collect::<Vec<_>>(),
+        );
+        buffers
+            .iter()
+            .zip(names)
+            .map(|(buffer, name)| BufferInfo {
+                id: buffer.id(),
+                name,
+                path: buffer.filename().map(Path::to_path_buf),
+                dirty: buffer.dirty(),
+                readonly: buffer.readonly(),
+                lines: buffer.lines(),
+                filetype: buffer.syntax().filetype,
+            })
+            .collect()
+    }
+
+    /// Shows the buffer in the current frame. The buffer shown before is the next one to be
+    /// shown by `next_buffer`.
+    pub(crate) fn switch_to_buffer(&mut self, id: BufferId) -> bool {
+        if self.buffer_id() == Some(id) {
+            return true;
+        }
+        let bv = if let Some(idx) = self
+            .buffer_view
+            .iter()
+            .position(|bv| bv.buffer().id() == id)
+        {
+            self.buffer_view.remove(idx).unwrap()
+        } else if let Some(bv) = self
+            .frame
+            .buffer_views()
+            .into_iter()
+            .find(|bv| bv.buffer().id() == id)
+        {
+            bv.clone()
+        } else {
+            return false;
+        };
+        if let Some(bv) = self.frame.set_buffer_view(bv) {
+            self.buffer_view.push_front(bv);
+        }
+        true
+    }
+
+    /// Closes a buffer without asking to save it. Frames showing it elsewhere keep it.
+    pub(crate) fn kill_buffer(&mut self, id: BufferId) {
+        for bv in &self.buffer_view {
+            if bv.buffer().id() == id {
+                record_position(&mut self.positions, bv);
+            }
+        }
+        self.buffer_view.retain(|bv| bv.buffer().id() != id);
+        if self.buffer_id() == Some(id) {
+            if let Some(bv) = self.frame.buffer_view() {
+                record_position(&mut self.positions, bv);
+            }
+            self.frame.close();
+            self.next_buffer();
+        }
+    }
+
     pub(crate) fn next_buffer(&mut self) {
         if let Some(bv) = self.buffer_view.pop_front() {
             if let Some(bv) = self.frame.set_buffer_view(bv) {
@@ -152,7 +537,7 @@ impl Editor {
 
     pub(crate) fn close_buffer(
         &mut self,
-        term: &mut RawTerminal,
+        term: &mut impl Terminal,
         decoder: &mut Decoder,
     ) -> keypress::Result<()> {
         if self.buffer().map(|b| b.dirty()).unwrap_or(false) {
@@ -168,6 +553,9 @@ impl Editor {
                 return Ok(());
             }
         }
+        if let Some(bv) = self.frame.buffer_view() {
+            record_position(&mut self.positions, bv);
+        }
         self.frame.close();
         self.next_buffer();
         Ok(())
@@ -175,7 +563,7 @@ impl Editor {
 
     pub(crate) fn quit(
         &mut self,
-        term: &mut RawTerminal,
+        term: &mut impl Terminal,
         decoder: &mut Decoder,
     ) -> keypress::Result<bool> {
         if self.dirty()
@@ -191,13 +579,37 @@ impl Editor {
         Ok(true)
     }
 
+    pub(crate) fn set_readonly(&mut self, readonly: bool) {
+        if let Some(mut buffer) = self.buffer_mut() {
+            buffer.set_readonly(readonly);
+        }
+    }
+
     pub(crate) fn dirty(&self) -> bool {
         self.frame.dirty() || self.buffer_view.iter().any(|b| b.buffer().dirty())
     }
 
     pub(crate) fn status(&self) -> Option<Status> {
         let bv = self.buffer_view()?;
-        Some(bv.status())
+        let mut status = bv.status();
+        // Only the buffers of the same name are needed to tell the current one apart
+        let buffers = self
+            .buffer_views()
+            .into_iter()
+            .map(|bv| bv.buffer())
+            .filter(|buffer| buffer.name() == status.name)
+            .collect::<Vec<_>>();
+        if buffers.len() > 1 {
+            let names = unique_names(
+                &buffers
+                    .iter()
+                    .map(|buffer| (buffer.name(), buffer.canonical_path()))
+                    .collect::<Vec<_>>(),
+            );
+            // The current buffer comes first
+            status.name = names.into_iter().next().unwrap();
+        }
+        Some(status)
     }
 
     pub(crate) fn set_render_size(&mut self, render_size: Size) {
@@ -214,6 +626,19 @@ impl Editor {
         }
     }
 
+    pub(crate) fn screen(&self) -> Option<&Screen> {
+        self.screen.as_ref()
+    }
+
+    pub(crate) fn set_screen(&mut self, screen: Screen) {
+        self.screen = Some(screen);
+    }
+
+    /// Redraws the whole screen on the next refresh.
+    pub(crate) fn redraw(&mut self) {
+        self.screen = None;
+    }
+
     pub(crate) fn scroll(&mut self) -> Point {
         self.frame.scroll()
     }
@@ -226,12 +651,55 @@ impl Editor {
         self.status_message.message()
     }
 
+    /// Shows a message which disappears after a while.
     pub(crate) fn set_status_message(&mut self, s: impl Into<String>) {
-        self.status_message.set_message(s)
+        self.status_message.set_message(s);
+        self.timers
+            .schedule(Instant::now(), STATUS_MESSAGE_TIMEOUT, Timer::StatusMessage);
+    }
+
+    /// Shows a prompt in the message bar until it is replaced.
+    pub(crate) fn set_prompt_message(&mut self, s: impl Into<String>) {
+        self.status_message.set_message(s);
+        self.timers.cancel(&Timer::StatusMessage);
+    }
+
+    /// Returns how long to wait for the next timer, or `None` if no timer is scheduled.
+    pub(crate) fn next_timeout(&self) -> Option<Duration> {
+        self.timers.next_timeout(Instant::now())
+    }
+
+    /// Handles the timers which have expired.
+    pub(crate) fn process_timers(&mut self) {
+        let now = Instant::now();
+        for timer in self.timers.expire(now) {
+            match timer {
+                Timer::StatusMessage => self.status_message.clear(),
+                Timer::FileWatch => {
+                    self.check_modified_files();
+                    self.timers
+                        .schedule(now, FILE_WATCH_INTERVAL, Timer::FileWatch);
+                }
+            }
+        }
     }
 
-    pub(crate) fn update_status_message(&mut self) {
-        self.status_message.update()
+    fn check_modified_files(&mut self) {
+        let mut modified = vec![];
+        for bv in self
+            .frame
+            .buffer_views_mut()
+            .into_iter()
+            .chain(&mut self.buffer_view)
+        {
+            let mut buffer = bv.buffer_mut();
+            if buffer.check_modified_on_disk() {
+                modified.extend(buffer.filename().map(|p| p.display().to_string()));
+            }
+        }
+        if !modified.is_empty() {
+            self.set_status_message(format!("{} changed on disk", modified.join(", ")));
+        }
     }
 
     pub(crate) fn move_cursor(&mut self, mv: CursorMove) {
@@ -252,6 +720,14 @@ impl Editor {
         self.buffer_view_or_create().insert_char(ch)
     }
 
+    pub(crate) fn insert_tab(&mut self) {
+        if !self.is_editable() {
+            self.set_status_message("Buffer is readonly");
+            return;
+        }
+        self.buffer_view_or_create().insert_tab()
+    }
+
     pub(crate) fn insert_newline(&mut self) {
         if !self.is_editable() {
             self.set_status_message("Buffer is readonly");
@@ -260,6 +736,60 @@ impl Editor {
         self.buffer_view_or_create().insert_newline()
     }
 
+    pub(crate) fn mouse(&mut self, mouse: Mouse) {
+        use MouseKind::*;
+        match mouse.kind {
+            Press(MouseButton::Left) => {
+                if let Some((bv, pos)) = self.frame.buffer_view_at(mouse.pos, true) {
+                    bv.mouse_press(pos);
+                }
+            }
+            Drag(MouseButton::Left) | Release(MouseButton::Left) => {
+                // Keep selecting in the focused frame even if the pointer is out of it
+                let origin = self.frame.focus_origin();
+                let pos = Point {
+                    x: mouse.pos.x.saturating_sub(origin.x),
+                    y: mouse.pos.y.saturating_sub(origin.y),
+                };
+                if let Some(bv) = self.frame.buffer_view_mut() {
+                    if mouse.pos.y < origin.y {
+                        bv.scroll_view(-1);
+                    }
+                    if mouse.kind == Release(MouseButton::Left) {
+                        bv.mouse_release(pos);
+                    } else {
+                        bv.mouse_drag(pos);
+                    }
+                }
+            }
+            WheelUp | WheelDown => {
+                if let Some((bv, _)) = self.frame.buffer_view_at(mouse.pos, false) {
+                    let n = if mouse.kind == WheelUp {
+                        -WHEEL_SCROLL_LINES
+                    } else {
+                        WHEEL_SCROLL_LINES
+                    };
+                    bv.scroll_view(n);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn clear_selection(&mut self) {
+        if let Some(bv) = self.frame.buffer_view_mut() {
+            bv.clear_selection();
+        }
+    }
+
+    pub(crate) fn paste(&mut self, s: &str) {
+        if !self.is_editable() {
+            self.set_status_message("Buffer is readonly");
+            return;
+        }
+        self.buffer_view_or_create().insert_str(s)
+    }
+
     pub(crate) fn delete_back_char(&mut self) {
         if !self.is_editable() {
             self.set_status_message("Buffer is readonly");
@@ -287,6 +817,64 @@ impl Editor {
         })
     }
 
+    pub(crate) fn toggle_wrap_mode(&mut self) {
+        let wrap_mode = if let Some(mut buffer) = self.buffer_mut() {
+            let wrap_mode = buffer.wrap_mode().next();
+            buffer.set_wrap_mode(wrap_mode);
+            wrap_mode
+        } else {
+            return;
+        };
+        self.set_status_message(format!("Wrap mode: {}", wrap_mode.name()));
+    }
+
+    pub(crate) fn set_indent_prompt(
+        &mut self,
+        term: &mut impl Terminal,
+        decoder: &mut Decoder,
+    ) -> keypress::Result<()> {
+        if self.buffer().is_none() {
+            return Ok(());
+        }
+
+        let indent = self.buffer().unwrap().indent();
+        let prompt = format!(
+            "Indent: {{}} (tab_width={} indent_width={} expand_tab={}, ESC to cancel)",
+            indent.tab_width, indent.indent_width, indent.expand_tab
+        );
+        let input = match keypress::prompt(term, decoder, self, "set-indent", &prompt)? {
+            Some(input) => input,
+            None => {
+                self.set_status_message("Set indent aborted");
+                return Ok(());
+            }
+        };
+        match IndentOverride::parse(&input) {
+            Ok(ovr) => {
+                let mut buffer = self.buffer_mut().unwrap();
+                let mut indent = buffer.indent();
+                indent.apply(&ovr);
+                buffer.set_indent(indent);
+            }
+            Err(e) => self.set_status_message(format!("{}", e)),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn goto(&mut self, target: GotoTarget) {
+        if let Some(buffer_view) = self.buffer_view_mut() {
+            buffer_view.goto(target);
+        }
+    }
+
+    pub(crate) fn jump_to_matching_bracket(&mut self) {
+        if let Some(buffer_view) = self.buffer_view_mut() {
+            if !buffer_view.jump_to_matching_bracket() {
+                self.set_status_message("No matching bracket");
+            }
+        }
+    }
+
     pub(crate) fn split_frame(&mut self, orientation: SplitOrientation) {
         self.frame.split(orientation)
     }
@@ -324,3 +912,184 @@ impl Find {
         }
     }
 }
+
+// Records the cursor position of a view of a file
+fn record_position(positions: &mut Positions, bv: &TextBufferView) {
+    let buffer = bv.buffer();
+    if buffer.filename().is_none() {
+        return;
+    }
+    if let Some(path) = buffer.canonical_path() {
+        let pos = Position {
+            cursor: bv.cursor(),
+            top: bv.top_line(),
+            lines: buffer.lines(),
+        };
+        positions.record(path, pos);
+    }
+}
+
+// Appends the parent directories to the names of buffers visiting files of the same name until
+// they are distinct
+fn unique_names(buffers: &[(String, Option<&Path>)]) -> Vec<String> {
+    let mut names = buffers
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    let mut groups = HashMap::<&str, Vec<usize>>::new();
+    for (idx, (name, path)) in buffers.iter().enumerate() {
+        if path.is_some() {
+            groups.entry(name).or_default().push(idx);
+        }
+    }
+
+    for idxs in groups.values().filter(|idxs| idxs.len() > 1) {
+        // Parent directories, the nearest first
+        let dirs = idxs
+            .iter()
+            .map(|&idx| {
+                let parent = buffers[idx].1.and_then(Path::parent);
+                parent
+                    .into_iter()
+                    .flat_map(|p| p.components().rev())
+                    .filter_map(|c| match c {
+                        Component::Normal(s) => Some(s.to_string_lossy()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let max_depth = dirs.iter().map(Vec::len).max().unwrap_or(0);
+        let suffixes = |depth: usize| {
+            dirs.iter()
+                .map(|dirs| {
+                    let mut dirs = dirs.iter().take(depth).cloned().collect::<Vec<_>>();
+                    dirs.reverse();
+                    dirs.join("/")
+                })
+                .collect::<Vec<_>>()
+        };
+        let suffixes = (1..=max_depth)
+            .map(suffixes)
+            .find(|suffixes| suffixes.iter().collect::<HashSet<_>>().len() == suffixes.len())
+            .unwrap_or_else(|| suffixes(max_depth));
+        for (&idx, suffix) in idxs.iter().zip(suffixes) {
+            names[idx] = format!("{}<{}>", names[idx], suffix);
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+    use std::fs;
+
+    #[test]
+    fn unique() {
+        let paths = [
+            "/src/frame/mod.rs",
+            "/src/input/mod.rs",
+            "/a/x/lib.rs",
+            "/b/x/lib.rs",
+            "/src/main.rs",
+        ]
+        .iter()
+        .map(Path::new)
+        .collect::<Vec<_>>();
+        let mut buffers = paths
+            .iter()
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, Some(*path))
+            })
+            .collect::<Vec<_>>();
+        buffers.push(("[No Name]".to_string(), None));
+        buffers.push(("[No Name]".to_string(), None));
+
+        assert_eq!(
+            unique_names(&buffers),
+            &[
+                "mod.rs<frame>",
+                "mod.rs<input>",
+                "lib.rs<a/x>",
+                "lib.rs<b/x>",
+                "main.rs",
+                "[No Name]",
+                "[No Name]",
+            ]
+        );
+    }
+
+    #[test]
+    fn session() {
+        let temp = TempDir::new("session");
+        let dir = temp.path();
+        fs::write(dir.join("a.txt"), "1\n2\n3\n4\n").unwrap();
+        fs::write(dir.join("b.txt"), "b\n").unwrap();
+        fs::write(dir.join("c.txt"), "c\n").unwrap();
+        let size = Size { cols: 20, rows: 10 };
+
+        let mut editor = Editor::new(size, Config::new());
+        assert!(editor.open(dir.join("c.txt")));
+        assert!(editor.open(dir.join("a.txt")));
+        editor.split_frame(SplitOrientation::Vertical);
+        // Move the cursor in the lower frame
+        let _ = editor.frame.buffer_view_at(Point { x: 0, y: 8 }, true);
+        editor.goto(GotoTarget::Line {
+            line: 3,
+            col: Some(2),
+        });
+        let _ = editor.frame.buffer_view_at(Point { x: 0, y: 0 }, true);
+        editor.show_buffer(TextBuffer::from_text("*Help*", "help"));
+        assert!(editor.open(dir.join("b.txt")));
+        editor.histories_mut().push("goto-line", "3");
+        let session = editor.session();
+        // `*Help*` is not saved
+        assert_eq!(session.hidden.len(), 2);
+
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        let mut restored = Editor::new(size, Config::new());
+        restored.restore_session(&session);
+        assert_eq!(restored.status().unwrap().name, "b.txt");
+        assert_eq!(restored.histories().get("goto-line"), &["3"]);
+        let views = restored.frame.buffer_views();
+        assert_eq!(views.len(), 2);
+        assert_eq!(views[1].cursor(), Point { x: 1, y: 2 });
+        // The deleted file is skipped, and the views of `a.txt` share the buffer
+        assert_eq!(restored.buffer_view.len(), 1);
+        assert_eq!(restored.buffer_view[0].cursor(), Point { x: 0, y: 0 });
+        assert_eq!(
+            restored.buffer_view[0].buffer().id(),
+            views[1].buffer().id()
+        );
+        assert_eq!(restored.buffer_list().len(), 2);
+    }
+
+    #[test]
+    fn positions() {
+        let temp = TempDir::new("positions-editor");
+        let filename = temp.path().join("a.txt");
+        fs::write(&filename, "1\n2\n3\n4\n5\n").unwrap();
+        let cursor = |editor: &Editor| editor.status().unwrap().cursor;
+
+        let mut editor = Editor::new(Size { cols: 20, rows: 10 }, Config::new());
+        assert!(editor.open(&filename));
+        editor.goto(GotoTarget::Line {
+            line: 4,
+            col: Some(2),
+        });
+        editor.kill_buffer(editor.buffer_id().unwrap());
+        assert!(editor.status().is_none());
+
+        assert!(editor.open(&filename));
+        assert_eq!(cursor(&editor), Point { x: 1, y: 3 });
+        editor.kill_buffer(editor.buffer_id().unwrap());
+
+        // The position is ignored once the file gets shorter
+        fs::write(&filename, "1\n2\n3\n").unwrap();
+        assert!(editor.open(&filename));
+        assert_eq!(cursor(&editor), Point { x: 0, y: 0 });
+    }
+}
@@ -0,0 +1,464 @@
+//! Support for [EditorConfig](https://editorconfig.org/) files.
+
+use crate::{
+    file::{Charset, FileFormat, LineEnding},
+    indent::IndentOverride,
+};
+use log::warn;
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const FILENAME: &str = ".editorconfig";
+
+/// Properties that apply to a file.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Properties {
+    map: HashMap<String, String>,
+}
+
+impl Properties {
+    /// Collects properties from the `.editorconfig` files in the directories containing
+    /// `filename`, from the nearest one up to the one marked as `root = true`.
+    pub(crate) fn lookup(filename: impl AsRef<Path>) -> Self {
+        let filename = filename.as_ref();
+        let filename = if filename.is_absolute() {
+            filename.to_path_buf()
+        } else {
+            match env::current_dir() {
+                Ok(dir) => dir.join(filename),
+                Err(e) => {
+                    warn!("failed to get current directory: {}", e);
+                    return Self::default();
+                }
+            }
+        };
+
+        let mut configs = vec![];
+        for dir in filename.ancestors().skip(1) {
+            let path = dir.join(FILENAME);
+            if !path.is_file() {
+                continue;
+            }
+            let config = match fs::read_to_string(&path) {
+                Ok(s) => EditorConfig::parse(dir, &s),
+                Err(e) => {
+                    warn!("failed to read {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            let root = config.root;
+            configs.push(config);
+            if root {
+                break;
+            }
+        }
+
+        let mut props = Self::default();
+        for config in configs.iter().rev() {
+            config.apply(&filename, &mut props);
+        }
+        props
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.map.get(key).map(String::as_str)
+    }
+
+    fn get_width(&self, key: &str) -> Option<usize> {
+        self.get(key)?.parse().ok().filter(|&w| w > 0)
+    }
+
+    fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get(key)? {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn indent_override(&self) -> IndentOverride {
+        let expand_tab = match self.get("indent_style") {
+            Some("tab") => Some(false),
+            Some("space") => Some(true),
+            _ => None,
+        };
+        // `tab_width` defaults to `indent_size`, and `indent_size = tab` means `tab_width`
+        let indent_size = self.get_width("indent_size");
+        let tab_width = self.get_width("tab_width").or(indent_size);
+        let indent_width = match self.get("indent_size") {
+            Some("tab") => tab_width,
+            _ => indent_size,
+        };
+        IndentOverride {
+            tab_width,
+            indent_width,
+            expand_tab,
+        }
+    }
+
+    pub(crate) fn apply_file_format(&self, format: &mut FileFormat) {
+        match self.get("end_of_line") {
+            Some("lf") => format.line_ending = LineEnding::Lf,
+            Some("crlf") => format.line_ending = LineEnding::CrLf,
+            Some("cr") => format.line_ending = LineEnding::Cr,
+            _ => {}
+        }
+        match self.get("charset") {
+            Some("latin1") => format.charset = Charset::Latin1,
+            Some("utf-8") => format.charset = Charset::Utf8,
+            Some("utf-8-bom") => format.charset = Charset::Utf8Bom,
+            Some("utf-16be") => format.charset = Charset::Utf16Be,
+            Some("utf-16le") => format.charset = Charset::Utf16Le,
+            _ => {}
+        }
+        if let Some(trim) = self.get_bool("trim_trailing_whitespace") {
+            format.trim_trailing_whitespace = trim;
+        }
+        // `false` leaves the ending of the file as it is
+        if self.get_bool("insert_final_newline") == Some(true) {
+            format.final_newline = true;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EditorConfig {
+    dir: PathBuf,
+    root: bool,
+    sections: Vec<(Glob, Vec<(String, String)>)>,
+}
+
+impl EditorConfig {
+    fn parse(dir: &Path, s: &str) -> Self {
+        let mut config = EditorConfig {
+            dir: dir.to_path_buf(),
+            root: false,
+            sections: vec![],
+        };
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let glob = Glob::new(&line[1..line.len() - 1]);
+                config.sections.push((glob, vec![]));
+                continue;
+            }
+
+            let mut it = line.splitn(2, '=');
+            let (key, value) = match (it.next(), it.next()) {
+                (Some(key), Some(value)) => (key.trim().to_lowercase(), value.trim()),
+                _ => {
+                    warn!("invalid line in {}: {:?}", FILENAME, line);
+                    continue;
+                }
+            };
+            let value = value.to_lowercase();
+            match config.sections.last_mut() {
+                Some((_, props)) => props.push((key, value)),
+                None if key == "root" => config.root = value == "true",
+                None => {}
+            }
+        }
+        config
+    }
+
+    fn apply(&self, filename: &Path, props: &mut Properties) {
+        let rel = match filename.strip_prefix(&self.dir) {
+            Ok(rel) => rel,
+            Err(_) => return,
+        };
+        let rel = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        for (glob, section) in &self.sections {
+            if !glob.is_match(&rel) {
+                continue;
+            }
+            for (key, value) in section {
+                if value == "unset" {
+                    props.map.remove(key);
+                } else {
+                    props.map.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Char(char),
+    // `*`
+    Any,
+    // `**`
+    AnyRecursive,
+    // `?`
+    One,
+    // `[...]` or `[!...]`
+    Class(bool, Vec<(char, char)>),
+    // `{s1,s2,...}`
+    Alt(Vec<Vec<Token>>),
+    // `{num1..num2}`
+    Range(i64, i64),
+}
+
+/// A section name of an EditorConfig file.
+#[derive(Debug, Clone)]
+struct Glob {
+    tokens: Vec<Token>,
+    // A glob without `/` matches the file name in any directory
+    match_name: bool,
+}
+
+impl Glob {
+    fn new(pat: &str) -> Self {
+        let match_name = !pat.contains('/');
+        let pat = pat.strip_prefix('/').unwrap_or(pat);
+        let chars = pat.chars().collect::<Vec<_>>();
+        let mut pos = 0;
+        let tokens = parse_tokens(&chars, &mut pos, false);
+        Glob { tokens, match_name }
+    }
+
+    fn is_match(&self, path: &str) -> bool {
+        let path = if self.match_name {
+            path.rsplit('/').next().unwrap_or(path)
+        } else {
+            path
+        };
+        match_tokens(&self.tokens, path)
+    }
+}
+
+fn parse_tokens(chars: &[char], pos: &mut usize, in_alt: bool) -> Vec<Token> {
+    let mut tokens = vec![];
+    while let Some(&ch) = chars.get(*pos) {
+        match ch {
+            ',' | '}' if in_alt => break,
+            '\\' => {
+                *pos += 1;
+                if let Some(&ch) = chars.get(*pos) {
+                    tokens.push(Token::Char(ch));
+                    *pos += 1;
+                }
+            }
+            '*' => {
+                if chars.get(*pos + 1) == Some(&'*') {
+                    tokens.push(Token::AnyRecursive);
+                    *pos += 2;
+                } else {
+                    tokens.push(Token::Any);
+                    *pos += 1;
+                }
+            }
+            '?' => {
+                tokens.push(Token::One);
+                *pos += 1;
+            }
+            '[' => match parse_class(chars, *pos + 1) {
+                Some((token, end)) => {
+                    tokens.push(token);
+                    *pos = end;
+                }
+                None => {
+                    tokens.push(Token::Char('['));
+                    *pos += 1;
+                }
+            },
+            '{' => match parse_brace(chars, *pos + 1) {
+                Some((token, end)) => {
+                    tokens.push(token);
+                    *pos = end;
+                }
+                None => {
+                    tokens.push(Token::Char('{'));
+                    *pos += 1;
+                }
+            },
+            ch => {
+                tokens.push(Token::Char(ch));
+                *pos += 1;
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_class(chars: &[char], mut pos: usize) -> Option<(Token, usize)> {
+    let negate = chars.get(pos) == Some(&'!');
+    if negate {
+        pos += 1;
+    }
+    let mut ranges = vec![];
+    let mut first = true;
+    loop {
+        let ch = *chars.get(pos)?;
+        if ch == ']' && !first {
+            return Some((Token::Class(negate, ranges), pos + 1));
+        }
+        if ch == '/' {
+            return None;
+        }
+        first = false;
+        if chars.get(pos + 1) == Some(&'-') && chars.get(pos + 2).is_some_and(|&c| c != ']') {
+            ranges.push((ch, chars[pos + 2]));
+            pos += 3;
+        } else {
+            ranges.push((ch, ch));
+            pos += 1;
+        }
+    }
+}
+
+fn parse_brace(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let end = start + chars[start..].iter().position(|&c| c == '}')?;
+    let body = chars[start..end].iter().collect::<String>();
+    let mut it = body.splitn(2, "..");
+    if let (Some(lo), Some(hi)) = (it.next(), it.next()) {
+        if let (Ok(lo), Ok(hi)) = (lo.parse(), hi.parse()) {
+            return Some((Token::Range(lo, hi), end + 1));
+        }
+    }
+
+    let mut pos = start;
+    let mut alts = vec![];
+    loop {
+        alts.push(parse_tokens(chars, &mut pos, true));
+        match chars.get(pos)? {
+            ',' => pos += 1,
+            '}' => break,
+            _ => return None,
+        }
+    }
+    // `{single}` is not an alternation
+    if alts.len() < 2 {
+        return None;
+    }
+    Some((Token::Alt(alts), pos + 1))
+}
+
+fn match_tokens(tokens: &[Token], s: &str) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(t) => t,
+        None => return s.is_empty(),
+    };
+    match token {
+        Token::Char(ch) => s.strip_prefix(*ch).is_some_and(|s| match_tokens(rest, s)),
+        Token::One => match s.chars().next() {
+            Some(ch) if ch != '/' => match_tokens(rest, &s[ch.len_utf8()..]),
+            _ => false,
+        },
+        Token::Class(negate, ranges) => match s.chars().next() {
+            Some(ch) if ch != '/' => {
+                let found = ranges.iter().any(|&(lo, hi)| lo <= ch && ch <= hi);
+                found != *negate && match_tokens(rest, &s[ch.len_utf8()..])
+            }
+            _ => false,
+        },
+        Token::Any | Token::AnyRecursive => {
+            let limit = if *token == Token::Any {
+                s.find('/').unwrap_or(s.len())
+            } else {
+                s.len()
+            };
+            (0..=limit)
+                .filter(|&idx| s.is_char_boundary(idx))
+                .any(|idx| match_tokens(rest, &s[idx..]))
+        }
+        Token::Alt(alts) => alts.iter().any(|alt| {
+            let tokens = alt.iter().chain(rest).cloned().collect::<Vec<_>>();
+            match_tokens(&tokens, s)
+        }),
+        Token::Range(lo, hi) => {
+            let sign = if s.starts_with('-') { 1 } else { 0 };
+            let digits = s[sign..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or_else(|| s.len() - sign);
+            (sign + 1..=sign + digits).any(|len| {
+                s[..len].parse::<i64>().is_ok_and(|n| *lo <= n && n <= *hi)
+                    && match_tokens(rest, &s[len..])
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        fn check(pat: &str, path: &str) -> bool {
+            Glob::new(pat).is_match(path)
+        }
+        assert!(check("*", "foo.rs"));
+        assert!(check("*", "src/foo.rs"));
+        assert!(check("*.rs", "src/foo.rs"));
+        assert!(!check("*.rs", "src/foo.c"));
+        assert!(check("*.{c,h}", "foo.h"));
+        assert!(!check("*.{c,h}", "foo.rs"));
+        assert!(check("Makefile", "sub/Makefile"));
+        assert!(check("src/*.rs", "src/foo.rs"));
+        assert!(!check("src/*.rs", "src/sub/foo.rs"));
+        assert!(check("src/**.rs", "src/sub/foo.rs"));
+        assert!(check("/src/**/*.rs", "src/sub/foo.rs"));
+        assert!(!check("/src/*.rs", "lib/src/foo.rs"));
+        assert!(check("file?.txt", "file1.txt"));
+        assert!(!check("file?.txt", "file10.txt"));
+        assert!(check("file[0-9].txt", "file1.txt"));
+        assert!(!check("file[!0-9].txt", "file1.txt"));
+        assert!(check("file{1..12}.txt", "file10.txt"));
+        assert!(!check("file{1..12}.txt", "file13.txt"));
+        assert!(check("{foo,bar{1,2}}.md", "bar2.md"));
+        assert!(check("{single}.md", "{single}.md"));
+        assert!(check("\\*.md", "*.md"));
+        assert!(!check("\\*.md", "a.md"));
+    }
+
+    #[test]
+    fn properties() {
+        let dir = Path::new("/project");
+        let root = EditorConfig::parse(
+            dir,
+            "
+root = true
+
+[*]
+indent_style = space
+indent_size = 4
+end_of_line = crlf
+insert_final_newline = true
+
+# Makefiles require tabs
+[Makefile]
+indent_style = tab
+indent_size = unset
+",
+        );
+        assert!(root.root);
+
+        let mut props = Properties::default();
+        root.apply(Path::new("/project/src/main.rs"), &mut props);
+        let indent = props.indent_override();
+        assert_eq!(indent.expand_tab, Some(true));
+        assert_eq!(indent.indent_width, Some(4));
+        assert_eq!(indent.tab_width, Some(4));
+        let mut format = FileFormat::default();
+        props.apply_file_format(&mut format);
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.final_newline);
+
+        let mut props = Properties::default();
+        root.apply(Path::new("/project/Makefile"), &mut props);
+        let indent = props.indent_override();
+        assert_eq!(indent.expand_tab, Some(false));
+        assert_eq!(indent.indent_width, None);
+    }
+}
@@ -1,12 +1,17 @@
+use log::warn;
 use nix::{
     errno::Errno,
     unistd::{self, AccessFlags},
 };
-use snafu::{Backtrace, IntoError, ResultExt, Snafu};
+use snafu::{Backtrace, IntoError, OptionExt, ResultExt, Snafu};
 use std::{
-    fs::File,
-    io::{self, BufRead, BufReader, BufWriter, Write as _},
+    convert::TryFrom,
+    env,
+    ffi::OsString,
+    fs::{self, File, Metadata, OpenOptions},
+    io::{self, BufWriter, Write as _},
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 #[derive(Debug, Snafu)]
@@ -29,6 +34,43 @@ pub(crate) enum Error {
         source: io::Error,
         backtrace: Backtrace,
     },
+    #[snafu(display("Could not decode file {} as {}", filename.display(), charset.name()))]
+    Decode {
+        filename: PathBuf,
+        charset: Charset,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not encode file {} as {}", filename.display(), charset.name()))]
+    Encode {
+        filename: PathBuf,
+        charset: Charset,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not read directory {}: {}", filename.display(), source))]
+    ReadDir {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not create {}: {}", filename.display(), source))]
+    Create {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not rename {} to {}: {}", from.display(), to.display(), source))]
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not delete {}: {}", filename.display(), source))]
+    Remove {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
     #[snafu(display("Could not get metadata of file {}: {}", filename.display(), source))]
     GetMetadata {
         filename: PathBuf,
@@ -39,11 +81,230 @@ pub(crate) enum Error {
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The name used in messages for the standard input.
+pub(crate) const STDIN_NAME: &str = "<stdin>";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Charset {
+    Latin1,
+    Utf8,
+    Utf8Bom,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Charset {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::Latin1 => "latin1",
+            Self::Utf8 => "utf-8",
+            Self::Utf8Bom => "utf-8-bom",
+            Self::Utf16Be => "utf-16be",
+            Self::Utf16Le => "utf-16le",
+        }
+    }
+
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Self::Latin1 => Some(bytes.iter().map(|&b| b as char).collect()),
+            Self::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Self::Utf8Bom => {
+                let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec()).ok()
+            }
+            Self::Utf16Be | Self::Utf16Le => {
+                let chunks = bytes.chunks_exact(2);
+                if !chunks.remainder().is_empty() {
+                    return None;
+                }
+                let units = chunks.map(|b| {
+                    if self == Self::Utf16Be {
+                        u16::from_be_bytes([b[0], b[1]])
+                    } else {
+                        u16::from_le_bytes([b[0], b[1]])
+                    }
+                });
+                let s = std::char::decode_utf16(units)
+                    .collect::<std::result::Result<String, _>>()
+                    .ok()?;
+                Some(s.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(s))
+            }
+        }
+    }
+
+    fn encode(self, s: &str, buf: &mut Vec<u8>) -> Option<()> {
+        match self {
+            Self::Latin1 => {
+                for ch in s.chars() {
+                    buf.push(u8::try_from(u32::from(ch)).ok()?);
+                }
+            }
+            Self::Utf8 | Self::Utf8Bom => buf.extend_from_slice(s.as_bytes()),
+            Self::Utf16Be => buf.extend(s.encode_utf16().flat_map(u16::to_be_bytes)),
+            Self::Utf16Le => buf.extend(s.encode_utf16().flat_map(u16::to_le_bytes)),
+        }
+        Some(())
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Self::Latin1 | Self::Utf8 => b"",
+            Self::Utf8Bom => b"\xef\xbb\xbf",
+            Self::Utf16Be => b"\xfe\xff",
+            Self::Utf16Le => b"\xff\xfe",
+        }
+    }
+}
+
+/// How the contents of a buffer are stored in a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct FileFormat {
+    pub(crate) line_ending: LineEnding,
+    pub(crate) charset: Charset,
+    pub(crate) trim_trailing_whitespace: bool,
+    /// Whether a line ending follows the last line, as read from the file or required by
+    /// EditorConfig.
+    pub(crate) final_newline: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        FileFormat {
+            line_ending: LineEnding::Lf,
+            charset: Charset::Utf8,
+            trim_trailing_whitespace: false,
+            final_newline: false,
+        }
+    }
+}
+
 pub(crate) fn exists(filename: impl AsRef<Path>) -> bool {
     let filename = filename.as_ref();
     filename.exists()
 }
 
+pub(crate) fn is_dir(filename: impl AsRef<Path>) -> bool {
+    filename.as_ref().is_dir()
+}
+
+/// Returns the absolute path of the file with symbolic links resolved, which is the same for all
+/// the names of a file. The file itself does not need to exist.
+pub(crate) fn canonical_path(filename: impl AsRef<Path>) -> PathBuf {
+    let filename = filename.as_ref();
+    if let Ok(path) = fs::canonicalize(filename) {
+        return path;
+    }
+    let path = match env::current_dir() {
+        Ok(dir) => dir.join(filename),
+        Err(_) => filename.to_path_buf(),
+    };
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => match fs::canonicalize(dir) {
+            Ok(dir) => dir.join(name),
+            Err(_) => path,
+        },
+        _ => path,
+    }
+}
+
+/// Returns the modification time of the file, or `None` if it cannot be read.
+pub(crate) fn modified_time(filename: impl AsRef<Path>) -> Option<SystemTime> {
+    fs::metadata(filename).and_then(|m| m.modified()).ok()
+}
+
+/// Reads the names of the files in a directory with their metadata, and `..` unless the directory
+/// is the root. Symbolic links are followed unless they are broken. Files whose metadata cannot be
+/// read are left out.
+pub(crate) fn read_dir(dirname: impl AsRef<Path>) -> Result<Vec<(OsString, Metadata)>> {
+    let dirname = dirname.as_ref();
+    let metadata = |path: &Path| match fs::metadata(path).or_else(|_| fs::symlink_metadata(path)) {
+        Ok(metadata) => Some(metadata),
+        Err(e) => {
+            warn!("read_dir: {}: {}", path.display(), e);
+            None
+        }
+    };
+    let mut entries = vec![];
+    if dirname.parent().is_some() {
+        if let Some(metadata) = metadata(&dirname.join("..")) {
+            entries.push(("..".into(), metadata));
+        }
+    }
+    let dir = fs::read_dir(dirname).with_context(|| ReadDir {
+        filename: dirname.to_path_buf(),
+    })?;
+    for entry in dir {
+        let entry = entry.with_context(|| ReadDir {
+            filename: dirname.to_path_buf(),
+        })?;
+        if let Some(metadata) = metadata(&entry.path()) {
+            entries.push((entry.file_name(), metadata));
+        }
+    }
+    Ok(entries)
+}
+
+/// Creates an empty file, which must not exist yet.
+pub(crate) fn create(filename: impl AsRef<Path>) -> Result<()> {
+    let filename = filename.as_ref();
+    let _ = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(filename)
+        .with_context(|| Create {
+            filename: filename.to_path_buf(),
+        })?;
+    Ok(())
+}
+
+pub(crate) fn create_dir(dirname: impl AsRef<Path>) -> Result<()> {
+    let dirname = dirname.as_ref();
+    fs::create_dir(dirname).with_context(|| Create {
+        filename: dirname.to_path_buf(),
+    })
+}
+
+pub(crate) fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    fs::rename(from, to).with_context(|| Rename {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+    })
+}
+
+/// Deletes a file or an empty directory.
+pub(crate) fn remove(filename: impl AsRef<Path>) -> Result<()> {
+    let filename = filename.as_ref();
+    let res = if fs::symlink_metadata(filename)
+        .map(|m| m.is_dir())
+        .unwrap_or(false)
+    {
+        fs::remove_dir(filename)
+    } else {
+        fs::remove_file(filename)
+    };
+    res.with_context(|| Remove {
+        filename: filename.to_path_buf(),
+    })
+}
+
 pub(crate) fn writable(filename: impl AsRef<Path>) -> Result<bool> {
     let filename = filename.as_ref();
     match unistd::access(filename, AccessFlags::W_OK) {
@@ -60,54 +321,170 @@ pub(crate) fn writable(filename: impl AsRef<Path>) -> Result<bool> {
     }
 }
 
-pub(crate) fn open(filename: impl AsRef<Path>) -> Result<Vec<String>> {
+/// Reads all lines from a file, and records in `format` if it ends with a line ending.
+pub(crate) fn open(filename: impl AsRef<Path>, format: &mut FileFormat) -> Result<Vec<String>> {
     let filename = filename.as_ref();
-    let file = File::open(filename).with_context(|| Open {
+    let bytes = fs::read(filename).with_context(|| Read {
         filename: filename.to_path_buf(),
     })?;
+    let (lines, final_newline) = decode_lines(filename, &bytes, format)?;
+    format.final_newline |= final_newline;
+    Ok(lines)
+}
 
-    let mut buf = vec![];
+/// Reads all lines from the standard input, or another reader standing for it, and records in
+/// `format` if it ends with a line ending.
+pub(crate) fn read_stdin(
+    mut reader: impl io::Read,
+    format: &mut FileFormat,
+) -> Result<Vec<String>> {
+    let filename = Path::new(STDIN_NAME);
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes).with_context(|| Read {
+        filename: filename.to_path_buf(),
+    })?;
+    let (lines, final_newline) = decode_lines(filename, &bytes, format)?;
+    format.final_newline |= final_newline;
+    Ok(lines)
+}
 
-    let reader = BufReader::new(&file);
-    for line in reader.lines() {
-        let line = line.with_context(|| Read {
-            filename: filename.to_path_buf(),
-        })?;
-        buf.push(line);
+// Returns the lines, and whether the last one ends with a line ending
+fn decode_lines(filename: &Path, bytes: &[u8], format: &FileFormat) -> Result<(Vec<String>, bool)> {
+    let s = format.charset.decode(bytes).with_context(|| Decode {
+        filename: filename.to_path_buf(),
+        charset: format.charset,
+    })?;
+
+    let mut buf = vec![];
+    let sep = if format.line_ending == LineEnding::Cr {
+        '\r'
+    } else {
+        '\n'
+    };
+    let final_newline = s.ends_with(sep);
+    let mut s = s.as_str();
+    while !s.is_empty() {
+        let (line, rest) = match s.find(sep) {
+            Some(idx) => (&s[..idx], &s[idx + 1..]),
+            None => (s, ""),
+        };
+        buf.push(line.strip_suffix('\r').unwrap_or(line).to_string());
+        s = rest;
     }
 
-    Ok(buf)
+    Ok((buf, final_newline))
 }
 
 pub(crate) fn save(
     filename: impl AsRef<Path>,
     lines: impl IntoIterator<Item = impl AsRef<str>>,
+    format: &FileFormat,
 ) -> Result<usize> {
     let filename = filename.as_ref();
+    let buf = encode_lines(filename, lines, format)?;
 
     let file = File::create(filename).with_context(|| Open {
         filename: filename.to_path_buf(),
     })?;
     let mut writer = BufWriter::new(file);
+    writer.write_all(&buf).with_context(|| Write {
+        filename: filename.to_path_buf(),
+    })?;
+    writer.flush().with_context(|| Write {
+        filename: filename.to_path_buf(),
+    })?;
+
+    Ok(buf.len())
+}
+
+/// Encodes lines into the bytes that `save` would write to a file.
+pub(crate) fn encode_lines(
+    filename: impl AsRef<Path>,
+    lines: impl IntoIterator<Item = impl AsRef<str>>,
+    format: &FileFormat,
+) -> Result<Vec<u8>> {
+    let filename = filename.as_ref();
 
-    let mut bytes = 0;
+    let mut s = String::new();
     for (idx, line) in lines.into_iter().enumerate() {
         if idx != 0 {
-            writeln!(&mut writer).with_context(|| Write {
-                filename: filename.to_path_buf(),
-            })?;
-            bytes += 1;
+            s.push_str(format.line_ending.as_str());
         }
-        let line = line.as_ref();
-        write!(&mut writer, "{}", line).with_context(|| Write {
+        let mut line = line.as_ref();
+        if format.trim_trailing_whitespace {
+            line = line.trim_end();
+        }
+        s.push_str(line);
+    }
+    if format.final_newline {
+        s.push_str(format.line_ending.as_str());
+    }
+
+    let mut buf = format.charset.bom().to_vec();
+    format
+        .charset
+        .encode(&s, &mut buf)
+        .with_context(|| Encode {
             filename: filename.to_path_buf(),
+            charset: format.charset,
         })?;
-        bytes += line.len();
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn charset_roundtrip() {
+        fn check(charset: Charset, s: &str) {
+            let mut buf = charset.bom().to_vec();
+            charset.encode(s, &mut buf).unwrap();
+            assert_eq!(charset.decode(&buf).unwrap(), s);
+        }
+        for &charset in &[
+            Charset::Utf8,
+            Charset::Utf8Bom,
+            Charset::Utf16Be,
+            Charset::Utf16Le,
+        ] {
+            check(charset, "abc\nあいう🦀");
+        }
+        check(Charset::Latin1, "abc\ncafé");
+
+        let mut buf = vec![];
+        assert!(Charset::Latin1.encode("あ", &mut buf).is_none());
     }
 
-    writer.flush().with_context(|| Write {
-        filename: filename.to_path_buf(),
-    })?;
+    #[test]
+    fn final_newline() {
+        let temp = TempDir::new("final-newline");
+        let filename = temp.path().join("a.txt");
+        for &s in &["a\nb\n", "a\nb", "a\n\n", "\n", ""] {
+            fs::write(&filename, s).unwrap();
+            let mut format = FileFormat::default();
+            let lines = open(&filename, &mut format).unwrap();
+            save(&filename, &lines, &format).unwrap();
+            assert_eq!(fs::read_to_string(&filename).unwrap(), s);
+        }
 
-    Ok(bytes)
+        let format = FileFormat {
+            final_newline: true,
+            ..FileFormat::default()
+        };
+        assert_eq!(encode_lines("a.txt", ["a"], &format).unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn canonical() {
+        let temp = TempDir::new("canonical");
+        let dir = temp.path();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "").unwrap();
+
+        assert_eq!(canonical_path(dir.join("sub/../a.txt")), dir.join("a.txt"));
+        // Files not created yet
+        assert_eq!(canonical_path(dir.join("sub/../b.txt")), dir.join("b.txt"));
+    }
 }
@@ -2,11 +2,11 @@ use crate::{
     decode::Decoder,
     editor::Editor,
     keypress::{self, PromptCommand},
-    terminal::RawTerminal,
+    terminal::Terminal,
 };
 
 pub(crate) fn find(
-    term: &mut RawTerminal,
+    term: &mut impl Terminal,
     decoder: &mut Decoder,
     editor: &mut Editor,
 ) -> keypress::Result<()> {
@@ -29,6 +29,7 @@ pub(crate) fn find(
                 SearchForward => find.search_forward(editor, query),
                 Execute => find.execute(editor, query),
                 Cancel => find.cancel(editor, query),
+                Complete => {}
             }
         },
     )?;
@@ -1,16 +1,50 @@
 use crate::{
-    geom::{Point, Segment, Size},
-    row::Row,
+    geom::{Point, Size},
+    row::RenderRow,
     text_buffer::TextBuffer,
     text_buffer_view::TextBufferView,
 };
-use std::{cell::Ref, mem, ops::Range};
+use std::{mem, ops::Range};
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum SplitOrientation {
     Vertical,
 }
 
+/// The shape of a frame tree with a value for each leaf, used to save and restore the frames.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum Layout<T> {
+    Empty,
+    Leaf(T),
+    Split {
+        frames: [Box<Layout<T>>; 2],
+        focus_idx: usize,
+        orientation: SplitOrientation,
+    },
+}
+
+impl<T> Layout<T> {
+    /// Maps the values of the leaves, which become empty where `f` returns `None`.
+    pub(crate) fn filter_map<U>(self, f: &mut impl FnMut(T) -> Option<U>) -> Layout<U> {
+        match self {
+            Self::Empty => Layout::Empty,
+            Self::Leaf(value) => f(value).map_or(Layout::Empty, Layout::Leaf),
+            Self::Split {
+                frames: [frame1, frame2],
+                focus_idx,
+                orientation,
+            } => Layout::Split {
+                frames: [
+                    Box::new(frame1.filter_map(f)),
+                    Box::new(frame2.filter_map(f)),
+                ],
+                focus_idx,
+                orientation,
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) enum Frame {
     Empty {
@@ -33,6 +67,49 @@ impl Frame {
         Frame::Empty { render_size }
     }
 
+    /// Builds the frames of the layout, sharing `render_size` as splitting does.
+    pub(crate) fn from_layout(layout: Layout<TextBufferView>, render_size: Size) -> Self {
+        let mut frame = match layout {
+            Layout::Empty => Self::Empty { render_size },
+            Layout::Leaf(buffer_view) => Self::Leaf {
+                buffer_view,
+                render_size,
+            },
+            Layout::Split {
+                frames: [frame1, frame2],
+                focus_idx,
+                orientation,
+            } => Self::Split {
+                frames: [
+                    Box::new(Self::from_layout(*frame1, render_size)),
+                    Box::new(Self::from_layout(*frame2, render_size)),
+                ],
+                focus_idx,
+                orientation,
+                render_size,
+            },
+        };
+        frame.set_render_size(render_size);
+        frame
+    }
+
+    pub(crate) fn layout(&self) -> Layout<&TextBufferView> {
+        match self {
+            Self::Empty { .. } => Layout::Empty,
+            Self::Leaf { buffer_view, .. } => Layout::Leaf(buffer_view),
+            Self::Split {
+                frames: [frame1, frame2],
+                focus_idx,
+                orientation,
+                ..
+            } => Layout::Split {
+                frames: [Box::new(frame1.layout()), Box::new(frame2.layout())],
+                focus_idx: *focus_idx,
+                orientation: *orientation,
+            },
+        }
+    }
+
     pub(crate) fn dirty(&self) -> bool {
         match self {
             Self::Empty { .. } => false,
@@ -109,6 +186,27 @@ impl Frame {
         }
     }
 
+    /// Returns the buffer views in all frames.
+    pub(crate) fn buffer_views(&self) -> Vec<&TextBufferView> {
+        match self {
+            Self::Empty { .. } => vec![],
+            Self::Leaf { buffer_view, .. } => vec![buffer_view],
+            Self::Split { frames, .. } => frames.iter().flat_map(|f| f.buffer_views()).collect(),
+        }
+    }
+
+    /// Returns the buffer views in all frames.
+    pub(crate) fn buffer_views_mut(&mut self) -> Vec<&mut TextBufferView> {
+        match self {
+            Self::Empty { .. } => vec![],
+            Self::Leaf { buffer_view, .. } => vec![buffer_view],
+            Self::Split { frames, .. } => frames
+                .iter_mut()
+                .flat_map(|f| f.buffer_views_mut())
+                .collect(),
+        }
+    }
+
     pub(crate) fn buffer_view_or_create(&mut self) -> &mut TextBufferView {
         if let Self::Empty { render_size } = self {
             *self = Self::Leaf {
@@ -196,6 +294,73 @@ impl Frame {
         }
     }
 
+    /// Finds the buffer view shown at the screen position, and returns it with the position
+    /// relative to the view. If `focus` is set, the view gets focused.
+    pub(crate) fn buffer_view_at(
+        &mut self,
+        pos: Point,
+        focus: bool,
+    ) -> Option<(&mut TextBufferView, Point)> {
+        match self {
+            Self::Empty { .. } => None,
+            Self::Leaf {
+                buffer_view,
+                render_size,
+            } => {
+                if pos.y >= render_size.rows || pos.x >= render_size.cols {
+                    return None;
+                }
+                Some((buffer_view, pos))
+            }
+            Self::Split {
+                frames,
+                focus_idx,
+                orientation: SplitOrientation::Vertical,
+                ..
+            } => {
+                let mut cur_y = 0;
+                for (idx, frame) in frames.iter_mut().enumerate() {
+                    let rows = frame.render_size().rows;
+                    if cur_y <= pos.y && pos.y < cur_y + rows {
+                        if focus {
+                            *focus_idx = idx;
+                        }
+                        let pos = Point {
+                            x: pos.x,
+                            y: pos.y - cur_y,
+                        };
+                        return frame.buffer_view_at(pos, focus);
+                    }
+                    cur_y += rows;
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns the screen position of the top-left corner of the focused frame.
+    pub(crate) fn focus_origin(&self) -> Point {
+        match self {
+            Self::Empty { .. } | Self::Leaf { .. } => Point::default(),
+            Self::Split {
+                frames,
+                focus_idx,
+                orientation: SplitOrientation::Vertical,
+                ..
+            } => {
+                let y = frames[..*focus_idx]
+                    .iter()
+                    .map(|f| f.render_size().rows)
+                    .sum::<usize>();
+                let origin = frames[*focus_idx].focus_origin();
+                Point {
+                    x: origin.x,
+                    y: origin.y + y,
+                }
+            }
+        }
+    }
+
     pub(crate) fn render_rows(&self) -> RenderRows {
         RenderRows {
             frame: self,
@@ -248,7 +413,7 @@ impl Frame {
         }
     }
 
-    fn push_render_rows_at<'a>(&'a self, ry: usize, rows: &mut Vec<(Segment, Ref<'a, Row>)>) {
+    fn push_render_rows_at<'a>(&'a self, ry: usize, rows: &mut Vec<RenderRow<'a>>) {
         match self {
             Self::Empty { .. } => {}
             Self::Leaf { buffer_view, .. } => rows.push(buffer_view.render_row_at(ry)),
@@ -276,7 +441,7 @@ pub(crate) struct RenderRows<'a> {
 }
 
 impl<'a> Iterator for RenderRows<'a> {
-    type Item = Vec<(Segment, Ref<'a, Row>)>;
+    type Item = Vec<RenderRow<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let ry = self.ry.next()?;
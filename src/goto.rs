@@ -0,0 +1,157 @@
+use crate::{decode::Decoder, editor::Editor, keypress, terminal::Terminal};
+use std::{convert::TryFrom, str::FromStr};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum GotoTarget {
+    /// `N` or `N:C`, both 1-based
+    Line { line: usize, col: Option<usize> },
+    /// `+N` or `-N`
+    Relative(isize),
+    /// `N%`
+    Percent(usize),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct ParseGotoTargetError;
+
+impl FromStr for GotoTarget {
+    type Err = ParseGotoTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| ParseGotoTargetError);
+        let parse_offset = |s: &str| isize::try_from(parse(s)?).map_err(|_| ParseGotoTargetError);
+
+        if let Some(s) = s.strip_suffix('%') {
+            let percent = parse(s)?;
+            if percent > 100 {
+                return Err(ParseGotoTargetError);
+            }
+            return Ok(GotoTarget::Percent(percent));
+        }
+        if let Some(s) = s.strip_prefix('+') {
+            return Ok(GotoTarget::Relative(parse_offset(s)?));
+        }
+        if let Some(s) = s.strip_prefix('-') {
+            return Ok(GotoTarget::Relative(-parse_offset(s)?));
+        }
+
+        let mut it = s.splitn(2, ':');
+        let line = parse(it.next().unwrap())?;
+        let col = it.next().map(parse).transpose()?;
+        Ok(GotoTarget::Line { line, col })
+    }
+}
+
+impl GotoTarget {
+    /// Resolves the target to a 0-based line and an optional 0-based column (in chars), clamping
+    /// the line to `lines`.
+    pub(crate) fn resolve(self, cur_line: usize, lines: usize) -> (usize, Option<usize>) {
+        let max_line = lines.saturating_sub(1);
+        let (line, col) = match self {
+            GotoTarget::Line { line, col } => {
+                (line.saturating_sub(1), col.map(|c| c.saturating_sub(1)))
+            }
+            GotoTarget::Relative(n) => {
+                if n < 0 {
+                    (cur_line.saturating_sub(n.unsigned_abs()), None)
+                } else {
+                    (cur_line.saturating_add(n as usize), None)
+                }
+            }
+            GotoTarget::Percent(p) => (max_line * p / 100, None),
+        };
+        (line.min(max_line), col)
+    }
+}
+
+pub(crate) fn goto_line(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let input = match keypress::prompt(
+        term,
+        decoder,
+        editor,
+        "goto-line",
+        "Goto line: {} (N, N:C, +N, -N or N%, ESC to cancel)",
+    )? {
+        Some(input) => input,
+        None => {
+            editor.set_status_message("Goto aborted");
+            return Ok(());
+        }
+    };
+
+    match input.parse::<GotoTarget>() {
+        Ok(target) => editor.goto(target),
+        Err(ParseGotoTargetError) => {
+            editor.set_status_message(format!("Invalid line: {}", input.trim()))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        fn check(s: &str) -> Option<GotoTarget> {
+            s.parse().ok()
+        }
+        use GotoTarget::*;
+        assert_eq!(
+            check("12"),
+            Some(Line {
+                line: 12,
+                col: None
+            })
+        );
+        assert_eq!(
+            check(" 12:5 "),
+            Some(Line {
+                line: 12,
+                col: Some(5)
+            })
+        );
+        assert_eq!(check("+3"), Some(Relative(3)));
+        assert_eq!(check("-3"), Some(Relative(-3)));
+        assert_eq!(check(&format!("+{}", usize::MAX)), None);
+        assert_eq!(check(&format!("-{}", usize::MAX)), None);
+        assert_eq!(check("50%"), Some(Percent(50)));
+        assert_eq!(check("101%"), None);
+        assert_eq!(check("12:"), None);
+        assert_eq!(check("a"), None);
+        assert_eq!(check(""), None);
+    }
+
+    #[test]
+    fn resolve() {
+        use GotoTarget::*;
+        assert_eq!(Line { line: 1, col: None }.resolve(5, 10), (0, None));
+        assert_eq!(
+            Line {
+                line: 0,
+                col: Some(0)
+            }
+            .resolve(5, 10),
+            (0, Some(0))
+        );
+        assert_eq!(
+            Line {
+                line: 20,
+                col: Some(3)
+            }
+            .resolve(5, 10),
+            (9, Some(2))
+        );
+        assert_eq!(Relative(3).resolve(5, 10), (8, None));
+        assert_eq!(Relative(30).resolve(5, 10), (9, None));
+        assert_eq!(Relative(-30).resolve(5, 10), (0, None));
+        assert_eq!(Percent(50).resolve(5, 11), (5, None));
+        assert_eq!(Percent(100).resolve(5, 11), (10, None));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{
+    command::{Command, Commands},
+    decode::Decoder,
+    editor::Editor,
+    input::Input,
+    keypress,
+    terminal::Terminal,
+    text_buffer::TextBuffer,
+};
+use itertools::Itertools;
+use std::{collections::HashMap, rc::Rc};
+use unicode_width::UnicodeWidthStr;
+
+pub(crate) const HELP_BUFFER_NAME: &str = "*Help*";
+
+/// Reads a key sequence and shows which command it runs.
+pub(crate) fn describe_key<T: Terminal>(
+    term: &mut T,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+    commands: &Commands<T>,
+) -> keypress::Result<()> {
+    const PROMPT: &str = "Describe key: ";
+    editor.set_prompt_message(PROMPT);
+    keypress::refresh(term, editor)?;
+    let input = match keypress::read_input(term, decoder, editor)? {
+        Some(input) => input,
+        None => return Ok(()),
+    };
+    let (inputs, cmd) =
+        match keypress::read_key_sequence(term, decoder, editor, commands, PROMPT, input)? {
+            Some(seq) => seq,
+            None => return Ok(()),
+        };
+
+    let keys = inputs.iter().join(" ");
+    let msg = match (cmd, keypress::self_insert_char(&inputs)) {
+        (Some(cmd), _) => format!("{} runs {}: {}", keys, cmd.name(), cmd.description()),
+        (None, Some(_)) => format!("{} inserts itself", keys),
+        (None, None) => format!("{} is undefined", keys),
+    };
+    editor.set_status_message(msg);
+    Ok(())
+}
+
+/// Shows all the key bindings in the help buffer.
+pub(crate) fn describe_bindings<T>(editor: &mut Editor, commands: &Commands<T>) {
+    let text = bindings_text(commands);
+    editor.show_buffer(TextBuffer::from_text(HELP_BUFFER_NAME, &text));
+}
+
+// Lists the bindings grouped by their prefixes, which are all the inputs but the last
+fn bindings_text<T>(commands: &Commands<T>) -> String {
+    let mut groups = HashMap::<Vec<Input>, Vec<(String, Rc<Command<T>>)>>::new();
+    for (mut inputs, cmd) in commands.keymap().bindings() {
+        let last = inputs.pop().unwrap();
+        groups
+            .entry(inputs)
+            .or_default()
+            .push((last.to_string(), cmd));
+    }
+
+    let mut text = String::new();
+    // Shorter prefixes first
+    let groups = groups
+        .into_iter()
+        .sorted_by_key(|(prefix, _)| (prefix.len(), prefix.iter().join(" ")));
+    for (prefix, bindings) in groups {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        let prefix = prefix.iter().map(|input| format!("{} ", input)).join("");
+        if prefix.is_empty() {
+            text.push_str("Global bindings:\n\n");
+        } else {
+            text.push_str(&format!(
+                "Bindings starting with {}:\n\n",
+                prefix.trim_end()
+            ));
+        }
+        push_bindings(&mut text, &prefix, bindings);
+    }
+
+    let bindings = commands
+        .directory_keymap()
+        .bindings()
+        .into_iter()
+        .map(|(inputs, cmd)| (inputs.iter().join(" "), cmd))
+        .collect::<Vec<_>>();
+    if !bindings.is_empty() {
+        text.push_str("\nBindings in directory listings:\n\n");
+        push_bindings(&mut text, "", bindings);
+    }
+    text
+}
+
+// Appends a line for each of the keys following the prefix, with the name and the description of
+// the command
+fn push_bindings<T>(text: &mut String, prefix: &str, mut bindings: Vec<(String, Rc<Command<T>>)>) {
+    bindings.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let key_width = bindings
+        .iter()
+        .map(|(key, _)| prefix.width() + key.width())
+        .max()
+        .unwrap_or(0);
+    let name_width = bindings
+        .iter()
+        .map(|(_, cmd)| cmd.name().width())
+        .max()
+        .unwrap_or(0);
+    for (key, cmd) in bindings {
+        let keys = format!("{}{}", prefix, key);
+        let line = format!(
+            "{:kw$}  {:nw$}  {}",
+            keys,
+            cmd.name(),
+            cmd.description(),
+            kw = key_width,
+            nw = name_width,
+        );
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_terminal::VirtualTerminal;
+
+    #[test]
+    fn bindings() {
+        let mut commands = Commands::<VirtualTerminal>::new();
+        commands.bind("C-X C-S", "save-buffer").unwrap();
+        commands.bind("C-X 4 2", "split-window-below").unwrap();
+        let text = bindings_text(&commands);
+        let lines = text.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines[0], "Global bindings:");
+        assert!(lines.iter().any(|line| line.starts_with("C-S ")
+            && line.contains(" save-buffer ")
+            && line.ends_with("Save the current buffer to its file")));
+        // `C-X` is a prefix instead of `next-buffer` now
+        assert!(!lines.iter().any(|line| line.contains(" next-buffer ")));
+
+        let idx = lines
+            .iter()
+            .position(|line| *line == "Bindings starting with C-X:")
+            .unwrap();
+        assert!(lines[idx + 2].starts_with("C-X C-S  save-buffer  "));
+        assert_eq!(lines[idx + 3], "");
+        assert_eq!(lines[idx + 4], "Bindings starting with C-X 4:");
+        assert!(lines[idx + 6].starts_with("C-X 4 2  split-window-below  "));
+        assert_eq!(lines[idx + 7], "");
+        assert_eq!(lines[idx + 8], "Bindings in directory listings:");
+        assert!(lines[idx + 10].starts_with("C-M  open-entry  "));
+    }
+}
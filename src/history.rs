@@ -0,0 +1,62 @@
+use std::collections::BTreeMap;
+
+// Inputs kept for each prompt
+const MAX_ENTRIES: usize = 100;
+
+/// The inputs entered in prompts, the oldest first. Prompts with the same history name, such as
+/// `goto-line`, share their inputs.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Histories {
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+impl Histories {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn get(&self, name: &str) -> &[String] {
+        self.entries.get(name).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Adds an input as the newest, removing the same one entered before.
+    pub(crate) fn push(&mut self, name: &str, input: impl Into<String>) {
+        let input = input.into();
+        let entries = self.entries.entry(name.to_string()).or_default();
+        entries.retain(|entry| *entry != input);
+        entries.push(input);
+        if entries.len() > MAX_ENTRIES {
+            let _ = entries.drain(..entries.len() - MAX_ENTRIES);
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.entries
+            .iter()
+            .map(|(name, entries)| (name.as_str(), entries.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push() {
+        let mut histories = Histories::new();
+        histories.push("goto-line", "10");
+        histories.push("goto-line", "20");
+        histories.push("goto-line", "10");
+        histories.push("open-file", "a.rs");
+        assert_eq!(histories.get("goto-line"), &["20", "10"]);
+        assert_eq!(histories.get("open-file"), &["a.rs"]);
+        assert!(histories.get("save-as").is_empty());
+
+        for i in 0..MAX_ENTRIES + 1 {
+            histories.push("goto-line", i.to_string());
+        }
+        let entries = histories.get("goto-line");
+        assert_eq!(entries.len(), MAX_ENTRIES);
+        assert_eq!(entries[0], "1");
+    }
+}
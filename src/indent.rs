@@ -0,0 +1,182 @@
+use crate::render::{RenderStrExt, TAB_STOP};
+use snafu::{OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("unknown indent setting: {}", key))]
+    UnknownKey { key: String },
+    #[snafu(display("invalid value for {}: {}", key, value))]
+    InvalidValue { key: String, value: String },
+    #[snafu(display("invalid indent setting: {}", s))]
+    InvalidSetting { s: String },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct IndentSettings {
+    pub(crate) tab_width: usize,
+    pub(crate) indent_width: usize,
+    pub(crate) expand_tab: bool,
+}
+
+impl Default for IndentSettings {
+    fn default() -> Self {
+        IndentSettings {
+            tab_width: TAB_STOP,
+            indent_width: TAB_STOP,
+            expand_tab: false,
+        }
+    }
+}
+
+impl IndentSettings {
+    pub(crate) fn apply(&mut self, ovr: &IndentOverride) {
+        if let Some(tab_width) = ovr.tab_width {
+            self.tab_width = tab_width;
+        }
+        if let Some(indent_width) = ovr.indent_width {
+            self.indent_width = indent_width;
+        }
+        if let Some(expand_tab) = ovr.expand_tab {
+            self.expand_tab = expand_tab;
+        }
+    }
+
+    /// Builds whitespace that indents a line by `width` columns.
+    pub(crate) fn whitespace(&self, width: usize) -> String {
+        if self.expand_tab {
+            " ".repeat(width)
+        } else {
+            let mut s = "\t".repeat(width / self.tab_width);
+            s.push_str(&" ".repeat(width % self.tab_width));
+            s
+        }
+    }
+
+    pub(crate) fn increase(&self, leading: &str) -> String {
+        let width = leading.render_width(0, self.tab_width);
+        self.whitespace(width + self.indent_width)
+    }
+
+    pub(crate) fn decrease(&self, leading: &str) -> String {
+        let width = leading.render_width(0, self.tab_width);
+        // Snap to the previous indent level
+        let width = width.saturating_sub(1) / self.indent_width * self.indent_width;
+        self.whitespace(width)
+    }
+}
+
+/// Returns the leading whitespace of the line.
+pub(crate) fn leading_whitespace(s: &str) -> &str {
+    &s[..s.len() - s.trim_start().len()]
+}
+
+/// A partial set of indent settings that overrides another one.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub(crate) struct IndentOverride {
+    pub(crate) tab_width: Option<usize>,
+    pub(crate) indent_width: Option<usize>,
+    pub(crate) expand_tab: Option<bool>,
+}
+
+impl IndentOverride {
+    pub(crate) const NONE: Self = IndentOverride {
+        tab_width: None,
+        indent_width: None,
+        expand_tab: None,
+    };
+
+    /// Parses whitespace separated `key=value` pairs.
+    pub(crate) fn parse(s: &str) -> Result<Self> {
+        let mut ovr = Self::default();
+        for kv in s.split_whitespace() {
+            let mut it = kv.splitn(2, '=');
+            match (it.next(), it.next()) {
+                (Some(key), Some(value)) => ovr.set(key, value)?,
+                _ => return InvalidSetting { s: kv }.fail(),
+            }
+        }
+        Ok(ovr)
+    }
+
+    pub(crate) fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = InvalidValue { key, value };
+        match key {
+            "tab_width" => self.tab_width = Some(parse_width(value).context(invalid)?),
+            "indent_width" => self.indent_width = Some(parse_width(value).context(invalid)?),
+            "expand_tab" => self.expand_tab = Some(parse_bool(value).context(invalid)?),
+            _ => return UnknownKey { key }.fail(),
+        }
+        Ok(())
+    }
+}
+
+fn parse_width(s: &str) -> Option<usize> {
+    s.parse().ok().filter(|&w| w > 0)
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "true" | "yes" | "on" => Some(true),
+        "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_override() {
+        let ovr = IndentOverride::parse("tab_width=4 expand_tab=yes").unwrap();
+        assert_eq!(
+            ovr,
+            IndentOverride {
+                tab_width: Some(4),
+                indent_width: None,
+                expand_tab: Some(true),
+            }
+        );
+
+        assert!(IndentOverride::parse("tab_width=0").is_err());
+        assert!(IndentOverride::parse("tab_width").is_err());
+        assert!(IndentOverride::parse("expand_tab=2").is_err());
+        assert!(IndentOverride::parse("tabwidth=2").is_err());
+    }
+
+    #[test]
+    fn change_level() {
+        let spaces = IndentSettings {
+            tab_width: 8,
+            indent_width: 4,
+            expand_tab: true,
+        };
+        assert_eq!(spaces.increase(""), "    ");
+        assert_eq!(spaces.increase("  "), "      ");
+        assert_eq!(spaces.decrease("        "), "    ");
+        assert_eq!(spaces.decrease("      "), "    ");
+        assert_eq!(spaces.decrease(""), "");
+
+        let tabs = IndentSettings {
+            expand_tab: false,
+            ..spaces
+        };
+        assert_eq!(tabs.increase("    "), "\t");
+        assert_eq!(tabs.increase("\t"), "\t    ");
+        assert_eq!(tabs.decrease("\t"), "    ");
+    }
+
+    #[test]
+    fn apply_override() {
+        let mut settings = IndentSettings::default();
+        settings.apply(&IndentOverride {
+            indent_width: Some(4),
+            ..IndentOverride::NONE
+        });
+        assert_eq!(settings.tab_width, TAB_STOP);
+        assert_eq!(settings.indent_width, 4);
+        assert!(!settings.expand_tab);
+    }
+}
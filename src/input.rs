@@ -1,3 +1,4 @@
+use crate::geom::Point;
 use matches::matches;
 use snafu::Snafu;
 use std::{
@@ -5,7 +6,7 @@ use std::{
     str::{self, FromStr},
 };
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub(crate) enum Key {
     Char(char),
     ArrowLeft,
@@ -17,6 +18,84 @@ pub(crate) enum Key {
     End,
     PageUp,
     PageDown,
+    Insert,
+    BackTab,
+    /// Function key F1-F12
+    F(u8),
+    /// Text pasted in bracketed paste mode
+    Paste(String),
+    Mouse(Mouse),
+}
+
+/// A mouse event at a 0-based position on the screen.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct Mouse {
+    pub(crate) kind: MouseKind,
+    pub(crate) pos: Point,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) enum MouseKind {
+    Press(MouseButton),
+    Release(MouseButton),
+    Drag(MouseButton),
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+impl Display for MouseButton {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            MouseButton::Left => f.write_str("left"),
+            MouseButton::Middle => f.write_str("middle"),
+            MouseButton::Right => f.write_str("right"),
+        }
+    }
+}
+
+impl Display for MouseKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            MouseKind::Press(button) => write!(f, "{} press", button),
+            MouseKind::Release(button) => write!(f, "{} release", button),
+            MouseKind::Drag(button) => write!(f, "{} drag", button),
+            MouseKind::WheelUp => f.write_str("wheel up"),
+            MouseKind::WheelDown => f.write_str("wheel down"),
+        }
+    }
+}
+
+impl FromStr for MouseKind {
+    type Err = ParseKeyError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kind = match s {
+            "wheel up" => MouseKind::WheelUp,
+            "wheel down" => MouseKind::WheelDown,
+            _ => {
+                let mut it = s.splitn(2, ' ');
+                let button = match it.next() {
+                    Some("left") => MouseButton::Left,
+                    Some("middle") => MouseButton::Middle,
+                    Some("right") => MouseButton::Right,
+                    _ => return Err(ParseKeyError),
+                };
+                match it.next() {
+                    Some("press") => MouseKind::Press(button),
+                    Some("release") => MouseKind::Release(button),
+                    Some("drag") => MouseKind::Drag(button),
+                    _ => return Err(ParseKeyError),
+                }
+            }
+        };
+        Ok(kind)
+    }
 }
 
 impl Debug for Key {
@@ -29,6 +108,7 @@ impl Display for Key {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         use Key::*;
         match self {
+            Char(' ') => f.write_str("space"),
             Char(ch) => f.write_char(*ch),
             ArrowLeft => f.write_str("left"),
             ArrowRight => f.write_str("right"),
@@ -39,6 +119,11 @@ impl Display for Key {
             End => f.write_str("end"),
             PageUp => f.write_str("page up"),
             PageDown => f.write_str("page down"),
+            Insert => f.write_str("insert"),
+            BackTab => f.write_str("backtab"),
+            F(n) => write!(f, "f{}", n),
+            Paste(text) => write!(f, "paste {:?}", text),
+            Mouse(mouse) => write!(f, "{} {},{}", mouse.kind, mouse.pos.x, mouse.pos.y),
         }
     }
 }
@@ -65,6 +150,28 @@ impl FromStr for Key {
             "end" => Self::End,
             "page up" => Self::PageUp,
             "page down" => Self::PageDown,
+            "insert" => Self::Insert,
+            "backtab" => Self::BackTab,
+            "space" => Self::Char(' '),
+            _ if s.starts_with('f') && s.len() > 1 => match s[1..].parse() {
+                Ok(n @ 1..=12) => Self::F(n),
+                _ => return Err(ParseKeyError),
+            },
+            _ if s.starts_with("paste ") => {
+                Self::Paste(unquote(&s["paste ".len()..]).ok_or(ParseKeyError)?)
+            }
+            _ if s.contains(' ') => {
+                // `<kind> <x>,<y>`
+                let (kind, pos) = s.rsplit_once(' ').ok_or(ParseKeyError)?;
+                let (x, y) = pos.split_once(',').ok_or(ParseKeyError)?;
+                Self::Mouse(Mouse {
+                    kind: kind.parse()?,
+                    pos: Point {
+                        x: x.parse().map_err(|_| ParseKeyError)?,
+                        y: y.parse().map_err(|_| ParseKeyError)?,
+                    },
+                })
+            }
             _ => {
                 let mut cs = s.chars();
                 match (cs.next(), cs.next()) {
@@ -77,17 +184,62 @@ impl FromStr for Key {
     }
 }
 
+// Parses a string quoted as by `Debug`
+fn unquote(s: &str) -> Option<String> {
+    let mut cs = s.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut out = String::new();
+    while let Some(ch) = cs.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        let ch = match cs.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'u' => {
+                let rest = cs.as_str().strip_prefix('{')?;
+                let (hex, rest) = rest.split_once('}')?;
+                cs = rest.chars();
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            }
+            ch @ ('\\' | '"' | '\'') => ch,
+            _ => return None,
+        };
+        out.push(ch);
+    }
+    Some(out)
+}
+
+// Returns the length of a string quoted as by `Debug` at the start of `s`
+fn quoted_len(s: &str) -> Option<usize> {
+    let mut cs = s.strip_prefix('"')?.char_indices();
+    while let Some((idx, ch)) = cs.next() {
+        match ch {
+            '\\' => {
+                cs.next();
+            }
+            '"' => return Some(idx + 2),
+            _ => {}
+        }
+    }
+    None
+}
+
 impl Key {
     fn need_angle_bracket(&self) -> bool {
-        !matches!(self, Key::Char(_))
+        // A space would be taken as a separator of inputs
+        !matches!(self, Key::Char(ch) if *ch != ' ')
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Clone, Eq, PartialEq, Hash)]
 pub(crate) struct Input {
     pub(crate) key: Key,
     pub(crate) ctrl: bool,
     pub(crate) alt: bool,
+    pub(crate) shift: bool,
 }
 
 impl Debug for Input {
@@ -108,6 +260,9 @@ impl Display for Input {
         if self.alt {
             write!(f, "M-")?;
         }
+        if self.shift {
+            write!(f, "S-")?;
+        }
         write!(f, "{}", self.key)?;
         if need_angle_bracket {
             write!(f, ">")?;
@@ -135,6 +290,7 @@ impl FromStr for Input {
         }
         let mut ctrl = false;
         let mut alt = false;
+        let mut shift = false;
         loop {
             if s.starts_with("C-") {
                 ctrl = true;
@@ -146,6 +302,11 @@ impl FromStr for Input {
                 s = &s[2..];
                 continue;
             }
+            if s.starts_with("S-") {
+                shift = true;
+                s = &s[2..];
+                continue;
+            }
             break;
         }
         let key = Key::from_str(s).map_err(|_| ParseInputError::InvalidKey)?;
@@ -156,7 +317,12 @@ impl FromStr for Input {
                 return Err(ParseInputError::NoAngleBracket);
             }
         }
-        Ok(Input { ctrl, alt, key })
+        Ok(Input {
+            ctrl,
+            alt,
+            shift,
+            key,
+        })
     }
 }
 
@@ -166,6 +332,7 @@ impl Input {
             key,
             ctrl: false,
             alt: false,
+            shift: false,
         }
     }
     pub(crate) fn ctrl(key: Key) -> Self {
@@ -173,6 +340,15 @@ impl Input {
             key,
             ctrl: true,
             alt: false,
+            shift: false,
+        }
+    }
+    pub(crate) fn alt(key: Key) -> Self {
+        Input {
+            key,
+            ctrl: false,
+            alt: true,
+            shift: false,
         }
     }
 }
@@ -196,6 +372,22 @@ pub(crate) struct Inputs<'a> {
     s: &'a str,
 }
 
+// Returns the length of the first input in `s`, which does not start with whitespace
+fn first_input_len(s: &str) -> usize {
+    // Pasted text may contain whitespace and `>`
+    if let Some(len) = s.strip_prefix("<paste ").and_then(quoted_len) {
+        let len = "<paste ".len() + len;
+        return len + s[len..].starts_with('>') as usize;
+    }
+    // A lone `<` is the key itself
+    if s.starts_with('<') && !s[1..].starts_with(char::is_whitespace) {
+        s.find('>').map(|idx| idx + 1)
+    } else {
+        s.find(char::is_whitespace)
+    }
+    .unwrap_or_else(|| s.len())
+}
+
 impl<'a> Iterator for Inputs<'a> {
     type Item = Result<Input, ParseInputError>;
     fn next(&mut self) -> Option<Self::Item> {
@@ -204,13 +396,7 @@ impl<'a> Iterator for Inputs<'a> {
             return None;
         }
 
-        let len = if self.s.starts_with('<') {
-            self.s.find('>').map(|idx| idx + 1)
-        } else {
-            self.s.find(char::is_whitespace)
-        }
-        .unwrap_or_else(|| self.s.len());
-
+        let len = first_input_len(self.s);
         let input = self.s[..len].parse();
         self.s = &self.s[len..].trim_start();
         Some(input)
@@ -219,19 +405,24 @@ impl<'a> Iterator for Inputs<'a> {
 
 impl<'a> DoubleEndedIterator for Inputs<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        debug_assert!(!self.s.ends_with(char::is_whitespace));
         if self.s.is_empty() {
             return None;
         }
 
-        let start = if self.s.ends_with('>') {
-            self.s.rfind('<')
-        } else {
-            self.s.rfind(char::is_whitespace)
+        // Inputs are split from the start, since whether `<` starts a key name depends on what
+        // follows it
+        let mut start = 0;
+        let mut last = self.s;
+        loop {
+            let rest = last[first_input_len(last)..].trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            start += last.len() - rest.len();
+            last = rest;
         }
-        .unwrap_or(0);
 
-        let input = self.s[start..].parse();
+        let input = last[..first_input_len(last)].parse();
         self.s = &self.s[..start].trim_end();
         Some(input)
     }
@@ -259,11 +450,28 @@ mod tests {
         check(Key::End);
         check(Key::PageUp);
         check(Key::PageDown);
+        check(Key::Insert);
+        check(Key::BackTab);
+        check(Key::F(1));
+        check(Key::F(12));
+        check(Key::Paste(String::new()));
+        check(Key::Paste("a \"b\"\\ >\r\n\t\x1bあ".into()));
+        check(Key::Mouse(Mouse {
+            kind: MouseKind::Press(MouseButton::Left),
+            pos: Point { x: 3, y: 10 },
+        }));
+        check(Key::Mouse(Mouse {
+            kind: MouseKind::WheelDown,
+            pos: Point { x: 0, y: 0 },
+        }));
     }
 
     #[test]
     fn parse_key() {
         assert!(Key::from_str("aaa").is_err());
+        assert!(Key::from_str("f0").is_err());
+        assert!(Key::from_str("f13").is_err());
+        assert_eq!(Key::from_str("f"), Ok(Key::Char('f')));
     }
 
     #[test]
@@ -280,6 +488,13 @@ mod tests {
         check("<C-page up>");
         check("<M-page up>");
         check("<C-M-page up>");
+        check("<S-up>");
+        check("<C-M-S-f5>");
+        check("<insert>");
+        check("<backtab>");
+        check("<C-right drag 1,2>");
+        check("<space>");
+        check("<C-space>");
     }
 
     #[test]
@@ -304,5 +519,26 @@ mod tests {
             "<page up>".parse()
         ]));
         assert!("    ".inputs().eq(vec![]));
+        assert!("< <space> >"
+            .inputs()
+            .eq(vec!["<".parse(), "<space>".parse(), ">".parse()]));
+        assert!("< >".inputs().rev().eq(vec![">".parse(), "<".parse()]));
+        assert!("<a b>  b c <page up> ".inputs().rev().eq(vec![
+            "<page up>".parse(),
+            "c".parse(),
+            "b".parse(),
+            "<a b>".parse()
+        ]));
+        assert_eq!("<space>".parse(), Ok(Input::new(Key::Char(' '))));
+
+        let paste = Ok(Input::new(Key::Paste("a\"> <b>\\".into())));
+        let s = r#"a <paste "a\"> <b>\\"> b"#;
+        assert!(s.inputs().eq(vec!["a".parse(), paste.clone(), "b".parse()]));
+        assert!(s.inputs().rev().eq(vec!["b".parse(), paste, "a".parse()]));
+        assert!(r#"<paste "a\u{1b}\n">"#
+            .inputs()
+            .eq(vec![Ok(Input::new(Key::Paste("a\x1b\n".into())))]));
+        assert!("<paste \"a>".parse::<Input>().is_err());
+        assert!(r#"<paste "a\q">"#.parse::<Input>().is_err());
     }
 }
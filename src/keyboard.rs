@@ -0,0 +1,116 @@
+/// Keyboard encoding negotiated with the terminal.
+///
+/// Terminals supporting the kitty keyboard protocol or xterm's `modifyOtherKeys` send keys like
+/// `C-i` or `C-S-x` as CSI sequences, so they can be told apart from Tab or `C-x`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum KeyboardProtocol {
+    Legacy,
+    ModifyOtherKeys,
+    Kitty,
+}
+
+/// Asks for the current kitty keyboard flags and the `modifyOtherKeys` level, followed by the
+/// primary device attributes which every terminal answers.
+pub(crate) const QUERY: &str = "\x1b[?u\x1b[?4m\x1b[c";
+
+impl KeyboardProtocol {
+    pub(crate) fn enable_seq(self) -> &'static str {
+        match self {
+            Self::Legacy => "",
+            Self::ModifyOtherKeys => "\x1b[>4;2m",
+            // Push "disambiguate escape codes" onto the flags stack
+            Self::Kitty => "\x1b[>1u",
+        }
+    }
+
+    pub(crate) fn disable_seq(self) -> &'static str {
+        match self {
+            Self::Legacy => "",
+            Self::ModifyOtherKeys => "\x1b[>4m",
+            Self::Kitty => "\x1b[<u",
+        }
+    }
+}
+
+/// Replies to `QUERY` read from the terminal.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct QueryResponse {
+    kitty: bool,
+    modify_other_keys: bool,
+    /// Whether the reply to the device attributes query, which comes last, has been read
+    pub(crate) complete: bool,
+    /// Bytes that are not replies, such as keys typed before the terminal answered
+    pub(crate) other: Vec<u8>,
+}
+
+impl QueryResponse {
+    pub(crate) fn protocol(&self) -> KeyboardProtocol {
+        if self.kitty {
+            KeyboardProtocol::Kitty
+        } else if self.modify_other_keys {
+            KeyboardProtocol::ModifyOtherKeys
+        } else {
+            KeyboardProtocol::Legacy
+        }
+    }
+
+    pub(crate) fn parse(bytes: &[u8]) -> Self {
+        let mut res = Self::default();
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match parse_reply(rest) {
+                Some((len, marker, final_byte)) => {
+                    match (marker, final_byte) {
+                        (b'?', b'u') => res.kitty = true,
+                        (b'>', b'm') => res.modify_other_keys = true,
+                        (b'?', b'c') => res.complete = true,
+                        _ => res.other.extend_from_slice(&rest[..len]),
+                    }
+                    rest = &rest[len..];
+                }
+                None => {
+                    res.other.push(rest[0]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+        res
+    }
+}
+
+// Parses `ESC [ <marker> <digits and ;> <final>` and returns its length, the marker and the final
+// byte
+fn parse_reply(bytes: &[u8]) -> Option<(usize, u8, u8)> {
+    if !bytes.starts_with(b"\x1b[") {
+        return None;
+    }
+    let marker = *bytes.get(2).filter(|b| b"?>".contains(b))?;
+    let params = bytes[3..]
+        .iter()
+        .take_while(|b| b.is_ascii_digit() || **b == b';')
+        .count();
+    let final_byte = *bytes.get(3 + params).filter(|b| b.is_ascii_alphabetic())?;
+    Some((4 + params, marker, final_byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_response() {
+        let res = QueryResponse::parse(b"\x1b[?0u\x1b[>4;0m\x1b[?62;22c");
+        assert_eq!(res.protocol(), KeyboardProtocol::Kitty);
+        assert!(res.complete);
+        assert!(res.other.is_empty());
+
+        let res = QueryResponse::parse(b"a\x1b[>4;1m\x1b[A\x1b[?1;2");
+        assert_eq!(res.protocol(), KeyboardProtocol::ModifyOtherKeys);
+        assert!(!res.complete);
+        assert_eq!(res.other, b"a\x1b[A\x1b[?1;2");
+
+        let res = QueryResponse::parse(b"\x1b[?6c");
+        assert_eq!(res.protocol(), KeyboardProtocol::Legacy);
+        assert!(res.complete);
+    }
+}
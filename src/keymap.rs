@@ -6,51 +6,75 @@ use std::{
     rc::Rc,
 };
 
+/// What an input is bound to: a function of type `F`, or a keymap for the inputs following it.
 #[derive(Derivative)]
 #[derivative(Debug(bound = ""), Clone(bound = ""))]
-pub(crate) enum Action<T, U> {
-    Func(#[derivative(Debug = "ignore")] Rc<dyn FnMut(T) -> U>),
-    KeyMap(Rc<RefCell<KeyMap<T, U>>>),
+pub(crate) enum Action<F: ?Sized> {
+    Func(#[derivative(Debug = "ignore")] Rc<F>),
+    KeyMap(Rc<RefCell<KeyMap<F>>>),
 }
 
 #[derive(Derivative)]
 #[derivative(Debug(bound = ""), Clone(bound = ""), Default(bound = ""))]
-pub(crate) struct KeyMap<T, U> {
-    map: HashMap<Input, Action<T, U>>,
+pub(crate) struct KeyMap<F: ?Sized> {
+    map: HashMap<Input, Action<F>>,
 }
 
-impl<T, U> KeyMap<T, U> {
+impl<F: ?Sized> KeyMap<F> {
     pub(crate) fn new() -> Self {
         Self::default()
     }
 
-    pub(crate) fn get(&self, input: &Input) -> Option<Action<T, U>> {
+    pub(crate) fn get(&self, input: &Input) -> Option<Action<F>> {
         self.map.get(input).cloned()
     }
 
+    /// Returns every input sequence bound to a function, with the function.
+    pub(crate) fn bindings(&self) -> Vec<(Vec<Input>, Rc<F>)> {
+        let mut bindings = vec![];
+        for (input, act) in &self.map {
+            match act {
+                Action::Func(f) => bindings.push((vec![input.clone()], f.clone())),
+                Action::KeyMap(km) => {
+                    for (mut inputs, f) in km.borrow().bindings() {
+                        inputs.insert(0, input.clone());
+                        bindings.push((inputs, f));
+                    }
+                }
+            }
+        }
+        bindings
+    }
+
     pub(crate) fn insert(
         &mut self,
         mut inputs: impl Iterator<Item = Input> + Clone,
-        act: Rc<dyn FnMut(T) -> U>,
-    ) -> Option<(VecDeque<Input>, Action<T, U>)> {
+        act: Rc<F>,
+    ) -> Option<(VecDeque<Input>, Action<F>)> {
         let input = inputs.next().unwrap();
 
         if inputs.clone().next().is_none() {
-            return self.map.insert(input, Action::Func(act)).map(|old| {
-                let mut is = VecDeque::new();
-                is.push_front(input);
-                (is, old)
-            });
+            return self
+                .map
+                .insert(input.clone(), Action::Func(act))
+                .map(|old| {
+                    let mut is = VecDeque::new();
+                    is.push_front(input);
+                    (is, old)
+                });
         }
 
-        match self.map.entry(input) {
+        match self.map.entry(input.clone()) {
             Entry::Occupied(mut e) => match e.get_mut() {
                 Action::KeyMap(km) => km.borrow_mut().insert(inputs, act).map(|(mut is, old)| {
                     is.push_front(input);
                     (is, old)
                 }),
                 Action::Func(..) => {
-                    let old = e.insert(Action::Func(act));
+                    let mut km = KeyMap::new();
+                    let inserted = km.insert(inputs, act);
+                    assert!(inserted.is_none());
+                    let old = e.insert(Action::KeyMap(Rc::new(RefCell::new(km))));
                     let mut is = VecDeque::new();
                     is.push_front(input);
                     Some((is, old))
@@ -75,7 +99,7 @@ mod tests {
 
     #[test]
     fn insert() {
-        let mut km = KeyMap::new();
+        let mut km = KeyMap::<dyn Fn(())>::new();
         assert!(km
             .insert("C-x C-x C-x".inputs().map(|i| i.unwrap()), Rc::new(|()| ()),)
             .is_none());
@@ -103,5 +127,6 @@ mod tests {
             .unwrap();
         assert!(is.into_iter().eq("C-x C-x".inputs().map(|i| i.unwrap())));
         assert_matches!(act, Action::Func(..));
+        assert_matches!(km.get(&"C-x".parse().unwrap()), Some(Action::KeyMap(..)));
     }
 }
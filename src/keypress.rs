@@ -1,191 +1,160 @@
 use crate::{
+    command::{Command, Commands},
     decode::{self, Decoder},
-    editor::{CursorMove, Editor},
-    find,
-    frame::SplitOrientation,
-    input::{Input, InputStrExt, Key},
-    keymap::KeyMap,
+    editor::Editor,
+    input::{Input, Key},
+    keymap::Action,
     output,
-    terminal::RawTerminal,
+    terminal::{self, TerminalEvent},
 };
+use itertools::Itertools;
 use snafu::{ResultExt, Snafu};
 use std::rc::Rc;
 
 #[derive(Debug, Snafu)]
+#[allow(clippy::enum_variant_names)]
 pub(crate) enum Error {
     #[snafu(display("{}", source))]
     DecodeError { source: decode::Error },
     #[snafu(display("{}", source))]
     OutputError { source: output::Error },
+    #[snafu(display("{}", source))]
+    TerminalError { source: terminal::Error },
 }
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub(crate) fn process_keypress(
-    term: &mut RawTerminal,
+pub(crate) fn process_keypress<T: terminal::Terminal>(
+    term: &mut T,
     decoder: &mut Decoder,
     editor: &mut Editor,
+    commands: &Commands<T>,
 ) -> Result<bool> {
-    use Key::*;
-
-    if let Some(input) = decoder.read_input(term).context(DecodeError)? {
-        match input {
-            Input {
-                key,
-                ctrl: true,
-                alt: false,
-            } => match key {
-                Char('M') => editor.insert_newline(),   // Ctrl-M : \r
-                Char('I') => editor.insert_char('\t'),  // Ctrl-I : \t
-                Char('?') => editor.delete_back_char(), // Ctrl-? : Backspace
-                Char('Q') => {
-                    if editor.quit(term, decoder)? {
-                        return Ok(true);
-                    }
-                }
-                Char('P') => editor.move_cursor(CursorMove::Up),
-                Char('N') => editor.move_cursor(CursorMove::Down),
-                Char('B') => editor.move_cursor(CursorMove::Left),
-                Char('F') => editor.move_cursor(CursorMove::Right),
-                Char('A') => editor.move_cursor(CursorMove::Home),
-                Char('E') => editor.move_cursor(CursorMove::End),
-                Char('V') => editor.move_cursor(CursorMove::PageDown),
-                Char('O') => editor.open_prompt(term, decoder)?,
-                Char('S') => editor.save(term, decoder)?,
-                Char('G') => find::find(term, decoder, editor)?,
-                Char('H') => editor.delete_back_char(),
-                Char('X') => editor.next_buffer(),
-                Char('C') => editor.close_buffer(term, decoder)?,
-                _ => editor.set_status_message(format!("{} is undefined", input)),
-            },
-            Input {
-                key,
-                ctrl: false,
-                alt: true,
-            } => match key {
-                Char('v') => editor.move_cursor(CursorMove::PageUp),
-                Char('<') => editor.move_cursor(CursorMove::BufferHome),
-                Char('>') => editor.move_cursor(CursorMove::BufferEnd),
-                Char('X') => editor.prev_buffer(),
-                Char('2') => editor.split_frame(SplitOrientation::Vertical),
-                _ => editor.set_status_message(format!("{} is undefined", input)),
-            },
-            Input {
-                key,
-                ctrl: false,
-                alt: false,
-            } => match key {
-                ArrowUp => editor.move_cursor(CursorMove::Up),
-                ArrowDown => editor.move_cursor(CursorMove::Down),
-                ArrowLeft => editor.move_cursor(CursorMove::Left),
-                ArrowRight => editor.move_cursor(CursorMove::Right),
-                Home => editor.move_cursor(CursorMove::Home),
-                End => editor.move_cursor(CursorMove::End),
-                PageUp => editor.move_cursor(CursorMove::PageUp),
-                PageDown => editor.move_cursor(CursorMove::PageDown),
-                Delete => editor.delete_char(),
-                Char(ch) => editor.insert_char(ch),
-            },
-            _ => editor.set_status_message(format!("{} is undefined", input)),
-        }
+    let input = match read_input(term, decoder, editor)? {
+        Some(input) => input,
+        None => return Ok(false),
+    };
+    if let Key::Mouse(mouse) = input.key {
+        editor.mouse(mouse);
+        return Ok(false);
+    }
+    editor.clear_selection();
+    if let Key::Paste(text) = &input.key {
+        editor.paste(text);
+        return Ok(false);
     }
 
+    let (inputs, cmd) = match read_key_sequence(term, decoder, editor, commands, "", input)? {
+        Some(seq) => seq,
+        None => return Ok(false),
+    };
+    if let Some(cmd) = cmd {
+        return cmd.run(term, decoder, editor, commands);
+    }
+    match self_insert_char(&inputs) {
+        Some(ch) => editor.insert_char(ch),
+        None => editor.set_status_message(format!("{} is undefined", inputs.iter().join(" "))),
+    }
     Ok(false)
 }
 
-pub(crate) fn default_keymap<'a>(
-) -> KeyMap<(&'a mut RawTerminal, &'a mut Decoder, &'a mut Editor), Result<bool>> {
-    fn insert(
-        km: &mut KeyMap<(&mut RawTerminal, &mut Decoder, &mut Editor), Result<bool>>,
-        key: &str,
-        act: impl FnMut((&mut RawTerminal, &mut Decoder, &mut Editor)) -> Result<bool> + 'static,
-    ) {
-        km.insert(key.inputs().map(|i| i.unwrap()), Rc::new(act));
+/// Inputs and the command bound to them, or `None` if they are not bound.
+pub(crate) type KeySequence<T> = (Vec<Input>, Option<Rc<Command<T>>>);
+
+/// Reads inputs following `first` until they make a sequence bound to a command or to nothing,
+/// and returns the inputs with the command. While a prefix is read, it is shown after `prompt`.
+/// Returns `None` if there is no more input.
+pub(crate) fn read_key_sequence<T: terminal::Terminal>(
+    term: &mut T,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+    commands: &Commands<T>,
+    prompt: &str,
+    first: Input,
+) -> Result<Option<KeySequence<T>>> {
+    let mut input = first;
+    let mut inputs = vec![];
+    let mut action = commands.action(editor, &input);
+    loop {
+        inputs.push(input);
+        match action {
+            Some(Action::Func(cmd)) => return Ok(Some((inputs, Some(cmd)))),
+            Some(Action::KeyMap(km)) => {
+                // Show the prefix typed so far while waiting for the rest
+                editor.set_prompt_message(format!("{}{} -", prompt, inputs.iter().join(" ")));
+                refresh(term, editor)?;
+                input = match read_input(term, decoder, editor)? {
+                    Some(input) => input,
+                    None => return Ok(None),
+                };
+                editor.set_status_message("");
+                action = km.borrow().get(&input);
+            }
+            None => return Ok(Some((inputs, None))),
+        }
     }
+}
 
-    let mut km = KeyMap::new();
-    insert(&mut km, "C-M", |(_, _, editor)| {
-        editor.insert_newline();
-        Ok(false)
-    });
-    insert(&mut km, "C-I", |(_, _, editor)| {
-        editor.insert_char('\t');
-        Ok(false)
-    });
-    insert(&mut km, "C-?", |(_, _, editor)| {
-        editor.delete_back_char();
-        Ok(false)
-    });
-    insert(&mut km, "C-Q", |(term, decoder, editor)| {
-        Ok(editor.quit(term, decoder)?)
-    });
-
-    let move_cursor = &[
-        ("C-P", CursorMove::Up),
-        ("C-N", CursorMove::Down),
-        ("C-B", CursorMove::Left),
-        ("C-F", CursorMove::Right),
-        ("C-A", CursorMove::Home),
-        ("C-E", CursorMove::End),
-        ("C-V", CursorMove::PageDown),
-        ("M-v", CursorMove::PageUp),
-        ("M-<", CursorMove::BufferHome),
-        ("M->", CursorMove::BufferEnd),
-        ("<up>", CursorMove::Up),
-        ("<down>", CursorMove::Down),
-        ("<left>", CursorMove::Left),
-        ("<right>", CursorMove::Right),
-        ("<home>", CursorMove::Home),
-        ("<end>", CursorMove::End),
-        ("<page up>", CursorMove::PageUp),
-        ("<page down>", CursorMove::PageDown),
-    ];
-    for (key, mov) in move_cursor {
-        let mov = *mov;
-        insert(&mut km, key, move |(_, _, editor)| {
-            editor.move_cursor(mov);
-            Ok(false)
-        });
+/// Returns the character inserted by an input sequence not bound to any command.
+pub(crate) fn self_insert_char(inputs: &[Input]) -> Option<char> {
+    match inputs {
+        [Input {
+            key: Key::Char(ch),
+            ctrl: false,
+            alt: false,
+            shift: false,
+        }] => Some(*ch),
+        _ => None,
     }
+}
 
-    insert(&mut km, "C-O", |(term, decoder, editor)| {
-        editor.open_prompt(term, decoder)?;
-        Ok(false)
-    });
-    insert(&mut km, "C-S", |(term, decoder, editor)| {
-        editor.save(term, decoder)?;
-        Ok(false)
-    });
-    insert(&mut km, "C-G", |(term, decoder, editor)| {
-        find::find(term, decoder, editor)?;
-        Ok(false)
-    });
-    insert(&mut km, "C-H", |(_, _, editor)| {
-        editor.delete_back_char();
-        Ok(false)
-    });
-    insert(&mut km, "C-X", |(_, _, editor)| {
-        editor.next_buffer();
-        Ok(false)
-    });
-    insert(&mut km, "M-X", |(_, _, editor)| {
-        editor.prev_buffer();
-        Ok(false)
-    });
-    insert(&mut km, "M-2", |(_, _, editor)| {
-        editor.split_frame(SplitOrientation::Vertical);
-        Ok(false)
-    });
-    insert(&mut km, "C-C", |(term, decoder, editor)| {
-        editor.close_buffer(term, decoder)?;
-        Ok(false)
-    });
-    insert(&mut km, "<delete>", |(_, _, editor)| {
-        editor.delete_char();
-        Ok(false)
-    });
+/// Refreshes the screen and flushes it to the terminal.
+pub(crate) fn refresh(term: &mut impl terminal::Terminal, editor: &mut Editor) -> Result<()> {
+    output::refresh_screen(term, editor).context(OutputError)?;
+    output::flush(term).context(OutputError)
+}
+
+/// Waits for input and decodes it.
+pub(crate) fn read_input(
+    term: &mut impl terminal::Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> Result<Option<Input>> {
+    wait_input(term, decoder, editor)?;
+    let input = decoder.read_input(term).context(DecodeError)?;
+    report_interrupted(decoder, editor);
+    Ok(input)
+}
 
-    km
+fn report_interrupted(decoder: &mut Decoder, editor: &mut Editor) {
+    if decoder.take_interrupted() {
+        editor.set_status_message("Keyboard macro interrupted");
+    }
+}
+
+pub(crate) fn suspend(term: &mut impl terminal::Terminal) -> Result<()> {
+    term.suspend().context(TerminalError)
+}
+
+/// Waits until there is input to decode. Signals and timers are handled and the screen is
+/// refreshed meanwhile.
+fn wait_input(
+    term: &mut impl terminal::Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> Result<()> {
+    while !decoder.has_buffered_input() {
+        match term
+            .wait_event(editor.next_timeout())
+            .context(TerminalError)?
+        {
+            TerminalEvent::Input => break,
+            TerminalEvent::Signal => {}
+            TerminalEvent::Timeout => editor.process_timers(),
+        }
+        refresh(term, editor)?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -193,58 +162,115 @@ pub(crate) enum PromptCommand {
     Input,
     SearchBackward,
     SearchForward,
+    Complete,
     Execute,
     Cancel,
 }
 
 pub(crate) fn prompt_confirm(
-    term: &mut RawTerminal,
+    term: &mut impl terminal::Terminal,
     decoder: &mut Decoder,
     editor: &mut Editor,
     prompt: &str,
 ) -> Result<bool> {
-    if let Some(s) = self::prompt(term, decoder, editor, prompt)? {
+    if let Some(s) = prompt_with_callback(term, decoder, editor, prompt, |_, _, _| {})? {
         Ok(s.to_lowercase().starts_with('y'))
     } else {
         Ok(false)
     }
 }
 
+/// Prompts for a line of input. The arrow keys recall the inputs entered before in the prompts
+/// sharing the `history` name, such as `goto-line`.
 pub(crate) fn prompt(
-    term: &mut RawTerminal,
+    term: &mut impl terminal::Terminal,
     decoder: &mut Decoder,
     editor: &mut Editor,
+    history: &'static str,
     prompt: &str,
 ) -> Result<Option<String>> {
-    prompt_with_callback(term, decoder, editor, prompt, |_, _, _| {})
+    // How many inputs back from the newest is shown, and what was typed before recalling them
+    let mut recalled: Option<usize> = None;
+    let mut typed = String::new();
+    let input = prompt_with_callback(term, decoder, editor, prompt, |editor, buf, cmd| {
+        let history = editor.histories().get(history);
+        match cmd {
+            PromptCommand::SearchBackward => {
+                let back = recalled.map_or(0, |back| back + 1);
+                if back < history.len() {
+                    if recalled.is_none() {
+                        typed = buf.clone();
+                    }
+                    recalled = Some(back);
+                    *buf = history[history.len() - 1 - back].clone();
+                }
+            }
+            PromptCommand::SearchForward => match recalled {
+                Some(0) => {
+                    recalled = None;
+                    *buf = typed.clone();
+                }
+                Some(back) => {
+                    recalled = Some(back - 1);
+                    *buf = history[history.len() - back].clone();
+                }
+                None => {}
+            },
+            PromptCommand::Input => recalled = None,
+            _ => {}
+        }
+    })?;
+    if let Some(input) = &input {
+        editor.histories_mut().push(history, input.as_str());
+    }
+    Ok(input)
 }
 
 pub(crate) fn prompt_with_callback(
-    term: &mut RawTerminal,
+    term: &mut impl terminal::Terminal,
     decoder: &mut Decoder,
     editor: &mut Editor,
     prompt: &str,
+    callback: impl FnMut(&mut Editor, &mut String, PromptCommand),
+) -> Result<Option<String>> {
+    prompt_with_message(
+        term,
+        decoder,
+        editor,
+        |buf| prompt.replace("{}", buf),
+        callback,
+    )
+}
+
+/// Like `prompt_with_callback`, but the prompt shown is built from the input by `message`.
+pub(crate) fn prompt_with_message(
+    term: &mut impl terminal::Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+    mut message: impl FnMut(&str) -> String,
     mut callback: impl FnMut(&mut Editor, &mut String, PromptCommand),
 ) -> Result<Option<String>> {
     use Key::*;
 
     let mut buf = String::new();
     loop {
-        let prompt = prompt.replace("{}", &buf);
-        editor.set_status_message(prompt);
-        output::refresh_screen(term, editor).context(OutputError)?;
+        editor.set_prompt_message(message(&buf));
+        refresh(term, editor)?;
 
+        wait_input(term, decoder, editor)?;
         while let Some(input) = decoder.read_input(term).context(DecodeError)? {
             let cmd = match input {
                 Input {
                     key,
                     ctrl: true,
                     alt: false,
+                    shift: false,
                 } => match key {
                     Char('H') | Char('?') => {
                         let _ = buf.pop();
                         Some(PromptCommand::Input)
                     }
+                    Char('I') => Some(PromptCommand::Complete),
                     Char('M') => {
                         if !buf.is_empty() {
                             editor.set_status_message("");
@@ -263,6 +289,7 @@ pub(crate) fn prompt_with_callback(
                     key,
                     ctrl: false,
                     alt: false,
+                    shift: false,
                 } => match key {
                     Delete => {
                         let _ = buf.pop();
@@ -274,6 +301,12 @@ pub(crate) fn prompt_with_callback(
                         buf.push(ch);
                         Some(PromptCommand::Input)
                     }
+                    Paste(text) => {
+                        // Prompts take a single line
+                        let line = text.lines().next().unwrap_or("");
+                        buf.extend(line.chars().filter(|ch| !ch.is_control()));
+                        Some(PromptCommand::Input)
+                    }
                     _ => None,
                 },
                 _ => None,
@@ -288,5 +321,445 @@ pub(crate) fn prompt_with_callback(
                 }
             }
         }
+        // The prompt was entered by the aborted macro
+        if decoder.take_interrupted() {
+            callback(editor, &mut buf, PromptCommand::Cancel);
+            editor.set_status_message("Keyboard macro interrupted");
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        geom::{Point, Size},
+        temp_dir::TempDir,
+        text_buffer::TextBuffer,
+        virtual_terminal::VirtualTerminal,
+    };
+    use std::{fs, process};
+
+    const SCREEN_SIZE: Size = Size { cols: 50, rows: 6 };
+
+    fn setup() -> (VirtualTerminal, Decoder, Editor) {
+        let term = VirtualTerminal::new(SCREEN_SIZE);
+        let render_size = Size {
+            rows: SCREEN_SIZE.rows - 2,
+            ..SCREEN_SIZE
+        };
+        let mut editor = Editor::new(render_size, Config::new());
+        editor.set_status_message("HELP");
+        (term, Decoder::new(), editor)
+    }
+
+    // Feeds `keys` and processes them like the main loop, and returns whether the editor quit
+    fn type_keys(
+        term: &mut VirtualTerminal,
+        decoder: &mut Decoder,
+        editor: &mut Editor,
+        keys: &str,
+    ) -> bool {
+        type_keys_with(term, decoder, editor, &Commands::new(), keys)
+    }
+
+    fn type_keys_with(
+        term: &mut VirtualTerminal,
+        decoder: &mut Decoder,
+        editor: &mut Editor,
+        commands: &Commands<VirtualTerminal>,
+        keys: &str,
+    ) -> bool {
+        term.feed(keys);
+        loop {
+            output::refresh_screen(term, editor).unwrap();
+            if !term.has_input() && !decoder.has_buffered_input() {
+                return false;
+            }
+            if process_keypress(term, decoder, editor, commands).unwrap() {
+                return true;
+            }
+        }
+    }
+
+    fn buffer_lines(editor: &Editor) -> Vec<String> {
+        let buffer = editor.buffer().unwrap();
+        buffer
+            .rows()
+            .iter()
+            .map(|row| row.chars().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn welcome() {
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(!type_keys(&mut term, &mut decoder, &mut editor, ""));
+        assert_eq!(term.line(0), "~");
+        assert!(term.line(1).contains("version"));
+        assert_eq!(term.line(4), "*welcome*");
+        assert_eq!(term.line(5), "HELP");
+    }
+
+    #[test]
+    fn edit_buffer() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "hello\rworlx\x7fd");
+        assert_eq!(buffer_lines(&editor), &["hello", "world"]);
+        assert_eq!(
+            term.lines(),
+            &[
+                "hello",
+                "world",
+                "~",
+                "~",
+                "[No Name] - 2 lines (modified)         no ft | 2/2",
+                "HELP",
+            ]
+        );
+        assert_eq!(term.cursor(), Point { x: 5, y: 1 });
+        assert!(term.cursor_visible());
+
+        // C-A, C-P
+        type_keys(&mut term, &mut decoder, &mut editor, "\x01\x10>");
+        assert_eq!(buffer_lines(&editor), &[">hello", "world"]);
+        assert_eq!(term.line(0), ">hello");
+        assert_eq!(term.cursor(), Point { x: 1, y: 0 });
+    }
+
+    #[test]
+    fn prompt() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "a\rb\rc\r");
+
+        // M-g
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bg2\r");
+        assert_eq!(term.line(5), "");
+        assert_eq!(term.cursor(), Point { x: 0, y: 1 });
+
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bgx\r");
+        assert_eq!(term.line(5), "Invalid line: x");
+
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bg3\x1b");
+        assert_eq!(term.line(5), "Goto aborted");
+        assert_eq!(term.cursor(), Point { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn prompt_history() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "a\rb\rc\r");
+
+        // M-g, then recall the inputs with the arrow keys
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bg2\r\x1bg3\r");
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bg\x1b[A\x1b[A\r");
+        assert_eq!(term.cursor(), Point { x: 0, y: 1 });
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bg1\x1b[A\x1b[B\r");
+        assert_eq!(term.cursor(), Point { x: 0, y: 0 });
+        assert_eq!(editor.histories().get("goto-line"), &["3", "2", "1"]);
+    }
+
+    #[test]
+    fn quit() {
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(type_keys(&mut term, &mut decoder, &mut editor, "\x11"));
+
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(!type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            "a\x11no\r"
+        ));
+        assert!(type_keys(&mut term, &mut decoder, &mut editor, "\x11yes\r"));
+    }
+
+    #[test]
+    fn keyboard_macro() {
+        const F3: &str = "\x1bOR";
+        const F4: &str = "\x1bOS";
+        const M_F4: &str = "\x1b[1;3S";
+
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "1\r2\r3");
+
+        // Inputs read by prompts are recorded too
+        let keys = format!("{}\x1bg1\r\x05a{}", F3, F4);
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert_eq!(buffer_lines(&editor), &["1a", "2", "3"]);
+        assert_eq!(term.line(5), "Keyboard macro defined");
+        assert_eq!(
+            editor.macros_mut().last().unwrap().to_string(),
+            "M-g 1 C-M C-E a"
+        );
+
+        type_keys(&mut term, &mut decoder, &mut editor, "\x0e");
+        type_keys(&mut term, &mut decoder, &mut editor, F4);
+        assert_eq!(buffer_lines(&editor), &["1aa", "2", "3"]);
+
+        let keys = format!("{}2\r", M_F4);
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert_eq!(buffer_lines(&editor), &["1aaaa", "2", "3"]);
+
+        // F5, then C-X a
+        let mut commands = Commands::new();
+        let mac = "<down> b".parse().unwrap();
+        commands.bind_macro("<f5>", "down-b", mac).unwrap();
+        let mac = "c".parse().unwrap();
+        commands.bind_macro("C-X a", "c", mac).unwrap();
+        type_keys_with(&mut term, &mut decoder, &mut editor, &commands, "\x1b[15~");
+        assert_eq!(buffer_lines(&editor), &["1aaaa", "2b", "3"]);
+        type_keys_with(&mut term, &mut decoder, &mut editor, &commands, "\x18a");
+        assert_eq!(buffer_lines(&editor), &["1aaaa", "2bc", "3"]);
+        type_keys_with(&mut term, &mut decoder, &mut editor, &commands, "\x18b");
+        assert_eq!(term.line(5), "C-X b is undefined");
+
+        // Macros running themselves stop, or abort by C-G
+        decoder.set_max_replay_inputs(1000);
+        let mac = "d <f6>".parse().unwrap();
+        commands.bind_macro("<f6>", "loop", mac).unwrap();
+        let keys = format!("{}\x07", "\x1b[17~");
+        type_keys_with(&mut term, &mut decoder, &mut editor, &commands, &keys);
+        assert_eq!(buffer_lines(&editor), &["1aaaa", "2bc", "3"]);
+        assert_eq!(term.line(5), "Keyboard macro interrupted");
+        type_keys_with(&mut term, &mut decoder, &mut editor, &commands, "\x1b[17~");
+        assert_eq!(term.line(5), "Keyboard macro is too long");
+
+        let keys = format!("{}{}1\r{}", F3, M_F4, F4);
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert_eq!(
+            editor.macros_mut().last().unwrap().to_string(),
+            "<M-f4> 1 C-M"
+        );
+        type_keys(&mut term, &mut decoder, &mut editor, F4);
+        assert_eq!(term.line(5), "Keyboard macro is too long");
+    }
+
+    #[test]
+    fn paste_in_macro() {
+        const F3: &str = "\x1bOR";
+        const F4: &str = "\x1bOS";
+        let paste = |s: &str| format!("\x1b[200~{}\x1b[201~", s);
+
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "ab\rcd");
+
+        // Pastes into the buffer and prompts are recorded with their text
+        let keys = format!(
+            "{}{}\x07{}\r{}{}",
+            F3,
+            paste("x >"),
+            paste("a"),
+            paste("y"),
+            F4
+        );
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert_eq!(buffer_lines(&editor), &["yab", "cdx >"]);
+        assert_eq!(
+            editor.macros_mut().last().unwrap().to_string(),
+            r#"<paste "x >"> C-G <paste "a"> C-M <paste "y">"#
+        );
+
+        type_keys(&mut term, &mut decoder, &mut editor, F4);
+        assert_eq!(buffer_lines(&editor), &["yx >yab", "cdx >"]);
+    }
+
+    #[test]
+    fn execute_command() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "a\rb\rc");
+
+        // M-x, then Tab completes to the best match
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bxgol\t\r2\r");
+        assert_eq!(term.cursor(), Point { x: 0, y: 1 });
+        // The selected match runs without completing
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bxgol\r3\r");
+        assert_eq!(term.cursor(), Point { x: 0, y: 2 });
+
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bxsave-bufferx\r");
+        assert_eq!(term.line(5), "Unknown command: save-bufferx");
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bxsb\x1b");
+        assert_eq!(term.line(5), "Aborted");
+    }
+
+    #[test]
+    fn describe_key() {
+        const F1: &str = "\x1bOP";
+        let (mut term, mut decoder, mut editor) = setup();
+
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            &format!("{}k\x13", F1),
+        );
+        assert_eq!(
+            term.line(5),
+            "C-S runs save-buffer: Save the current buffer to i"
+        );
+        type_keys(&mut term, &mut decoder, &mut editor, &format!("{}ka", F1));
+        assert_eq!(term.line(5), "a inserts itself");
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            &format!("{}k\x1b[5;5~", F1),
+        );
+        assert_eq!(term.line(5), "<C-page up> is undefined");
+    }
+
+    #[test]
+    fn describe_bindings() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bOPb");
+        assert_eq!(term.line(0), "Global bindings:");
+        assert!(term.line(4).starts_with("*Help* - "));
+        assert!(term.line(4).contains("(readonly)"));
+
+        type_keys(&mut term, &mut decoder, &mut editor, "x");
+        assert_eq!(term.line(5), "Buffer is readonly");
+    }
+
+    #[test]
+    fn buffers() {
+        let temp = TempDir::new("buffers");
+        let dir = temp.path();
+        fs::write(dir.join("a.txt"), "a\n").unwrap();
+        fs::write(dir.join("b.txt"), "b\n").unwrap();
+
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(editor.open(dir.join("a.txt")));
+        assert!(editor.open(dir.join("b.txt")));
+
+        // M-b
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bba.t\r");
+        assert!(term.line(4).starts_with("a.txt - 1 lines"));
+
+        // M-l, then open the second one
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1bln\r");
+        assert!(term.line(4).starts_with("b.txt - 1 lines"));
+
+        // Kill the second one, then quit the list
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1blnkq");
+        let names = editor
+            .buffer_list()
+            .into_iter()
+            .map(|info| info.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, &["b.txt"]);
+        assert!(term.line(4).starts_with("b.txt - 1 lines"));
+    }
+
+    #[test]
+    fn open_same_file() {
+        let temp = TempDir::new("same-file");
+        let dir = temp.path();
+        for sub in &["x", "y"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+            fs::write(dir.join(sub).join("mod.rs"), "\n").unwrap();
+        }
+
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(editor.open(dir.join("x/mod.rs")));
+        assert!(editor.open(dir.join("y/mod.rs")));
+        type_keys(&mut term, &mut decoder, &mut editor, "");
+        assert!(term.line(4).starts_with("mod.rs<y> - 1 lines"));
+
+        // C-O
+        let keys = format!("\x0f{}\r", dir.join("y/../x/mod.rs").display());
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert!(term.line(4).starts_with("mod.rs<x> - 1 lines"));
+        let names = editor
+            .buffer_list()
+            .into_iter()
+            .map(|info| info.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, &["mod.rs<x>", "mod.rs<y>"]);
+
+        // Saving a new buffer as an open file is refused
+        editor.show_buffer(TextBuffer::new());
+        let path = dir.join("y/../x/mod.rs");
+        let keys = format!("a\x13{}\r", path.display());
+        type_keys(&mut term, &mut decoder, &mut editor, &keys);
+        assert!(term.line(5).starts_with("Can't save! "));
+        assert!(term.line(4).starts_with("[No Name] - 1 lines (modified)"));
+        assert_eq!(fs::read_to_string(dir.join("x/mod.rs")).unwrap(), "\n");
+    }
+
+    #[test]
+    fn directory() {
+        let temp = TempDir::new("dir");
+        let dir = temp.path();
+        fs::create_dir(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "a\n").unwrap();
+        let name = format!("mirri-dir-{}/", process::id());
+
+        let (mut term, mut decoder, mut editor) = setup();
+        assert!(editor.open(dir));
+        type_keys(&mut term, &mut decoder, &mut editor, "");
+        assert!(term
+            .line(4)
+            .starts_with(&format!("{} - 5 lines (readonly)", name)));
+
+        // Descend into `sub/`
+        type_keys(&mut term, &mut decoder, &mut editor, "\x0e\x0e\x0e\r");
+        assert!(term.line(4).starts_with("sub/ - 3 lines"));
+
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            "\x1bxcreate-file\rnew.txt\ry\r",
+        );
+        assert!(dir.join("sub/new.txt").exists());
+        assert!(term.line(2).ends_with("  new.txt"));
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            "\x1bxrename-file\rb.txt\ry\r",
+        );
+        assert!(!dir.join("sub/new.txt").exists());
+        assert!(dir.join("sub/b.txt").exists());
+        // Not confirmed
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            "\x1bxdelete-file\rn\r",
+        );
+        assert_eq!(term.line(5), "Delete aborted");
+        type_keys(
+            &mut term,
+            &mut decoder,
+            &mut editor,
+            "\x1bxdelete-file\ry\r",
+        );
+        assert!(!dir.join("sub/b.txt").exists());
+        assert!(term.line(4).starts_with("sub/ - 3 lines"));
+
+        // Back to the listing of the parent, then open `a.txt`
+        type_keys(&mut term, &mut decoder, &mut editor, "\x1b<\x0e\x0e\r");
+        assert!(term.line(4).starts_with(&name));
+        type_keys(&mut term, &mut decoder, &mut editor, "\x0e\x0e\r");
+        assert!(term.line(4).starts_with("a.txt - 1 lines"));
+        assert_eq!(editor.buffer_list().len(), 3);
+    }
+
+    #[test]
+    fn redraw() {
+        let (mut term, mut decoder, mut editor) = setup();
+        type_keys(&mut term, &mut decoder, &mut editor, "abc");
+        let lines = term.lines();
+
+        // C-L
+        type_keys(&mut term, &mut decoder, &mut editor, "\x0c");
+        assert_eq!(term.lines(), lines);
+
+        term.resize(Size { cols: 20, rows: 4 });
+        type_keys(&mut term, &mut decoder, &mut editor, "");
+        assert_eq!(term.lines(), &["abc", "~", "[No Name] - 1 lines", "HELP"]);
     }
 }
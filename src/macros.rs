@@ -0,0 +1,302 @@
+use crate::{
+    config,
+    decode::Decoder,
+    editor::Editor,
+    input::{Input, InputStrExt, ParseInputError},
+    keypress,
+    terminal::Terminal,
+};
+use snafu::{Backtrace, OptionExt, ResultExt, Snafu};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Invalid macro name: {:?}", name))]
+    InvalidName { name: String },
+    #[snafu(display(
+        "Could not find the macro directory: neither XDG_CONFIG_HOME nor HOME is set"
+    ))]
+    NoDirectory,
+    #[snafu(display("Could not read macro file {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not write macro file {}: {}", filename.display(), source))]
+    Write {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not parse macro file {}: {}", filename.display(), source))]
+    Parse {
+        filename: PathBuf,
+        source: ParseInputError,
+    },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A keyboard macro, the inputs recorded to be replayed later.
+///
+/// A macro is written as its inputs separated by spaces, such as `C-a <down> x`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub(crate) struct Macro {
+    inputs: Vec<Input>,
+}
+
+impl Macro {
+    pub(crate) fn new(inputs: Vec<Input>) -> Self {
+        Macro { inputs }
+    }
+
+    pub(crate) fn inputs(&self) -> &[Input] {
+        &self.inputs
+    }
+
+    pub(crate) fn load(filename: &Path) -> Result<Self> {
+        let s = fs::read_to_string(filename).context(Read { filename })?;
+        s.parse().context(Parse { filename })
+    }
+
+    pub(crate) fn save(&self, filename: &Path) -> Result<()> {
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir).context(Write { filename })?;
+        }
+        fs::write(filename, format!("{}\n", self)).context(Write { filename })
+    }
+}
+
+impl Display for Macro {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, input) in self.inputs.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", input)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Macro {
+    type Err = ParseInputError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inputs = s.trim().inputs().collect::<Result<_, _>>()?;
+        Ok(Macro { inputs })
+    }
+}
+
+/// The last recorded macro and the named macros, which are saved in
+/// `$XDG_CONFIG_HOME/mirri/macros/<name>`.
+#[derive(Debug, Default)]
+pub(crate) struct Macros {
+    dir: Option<PathBuf>,
+    last: Option<Macro>,
+    named: HashMap<String, Macro>,
+}
+
+impl Macros {
+    pub(crate) fn new() -> Self {
+        Self::with_dir(config::config_dir().map(|dir| dir.join("macros")))
+    }
+
+    fn with_dir(dir: Option<PathBuf>) -> Self {
+        Macros {
+            dir,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<&Macro> {
+        self.last.as_ref()
+    }
+
+    pub(crate) fn set_last(&mut self, mac: Macro) {
+        self.last = Some(mac);
+    }
+
+    fn filename(&self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(&['/', ' '][..]) {
+            return InvalidName { name }.fail();
+        }
+        Ok(self.dir.as_ref().context(NoDirectory)?.join(name))
+    }
+
+    /// Names a macro and saves it to its file.
+    pub(crate) fn save(&mut self, name: &str, mac: Macro) -> Result<PathBuf> {
+        let filename = self.filename(name)?;
+        mac.save(&filename)?;
+        self.named.insert(name.to_string(), mac);
+        Ok(filename)
+    }
+
+    /// Returns the named macro, loading it from its file if it is not loaded yet.
+    pub(crate) fn get(&mut self, name: &str) -> Result<Macro> {
+        if let Some(mac) = self.named.get(name) {
+            return Ok(mac.clone());
+        }
+        let mac = Macro::load(&self.filename(name)?)?;
+        self.named.insert(name.to_string(), mac.clone());
+        Ok(mac)
+    }
+}
+
+pub(crate) fn start_recording(decoder: &mut Decoder, editor: &mut Editor) {
+    if decoder.is_recording() {
+        editor.set_status_message("Already defining keyboard macro");
+        return;
+    }
+    decoder.start_recording();
+    editor.set_status_message("Defining keyboard macro...");
+}
+
+/// Ends the definition of a macro if recording, or replays the last macro otherwise.
+pub(crate) fn stop_recording_or_replay(decoder: &mut Decoder, editor: &mut Editor) {
+    if let Some(mac) = decoder.stop_recording() {
+        if mac.inputs().is_empty() {
+            editor.set_status_message("Ignoring empty keyboard macro");
+        } else {
+            editor.macros_mut().set_last(mac);
+            editor.set_status_message("Keyboard macro defined");
+        }
+        return;
+    }
+    match editor.macros_mut().last().cloned() {
+        Some(mac) => replay(decoder, editor, &mac, 1),
+        None => editor.set_status_message("No keyboard macro defined"),
+    }
+}
+
+fn replay(decoder: &mut Decoder, editor: &mut Editor, mac: &Macro, count: usize) {
+    if !decoder.replay(mac, count) {
+        editor.set_status_message("Keyboard macro is too long");
+    }
+}
+
+pub(crate) fn save_prompt(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let mac = match editor.macros_mut().last().cloned() {
+        Some(mac) => mac,
+        None => {
+            editor.set_status_message("No keyboard macro defined");
+            return Ok(());
+        }
+    };
+    let name = match keypress::prompt(
+        term,
+        decoder,
+        editor,
+        "save-macro",
+        "Save macro as: {} (ESC to cancel)",
+    )? {
+        Some(name) => name,
+        None => {
+            editor.set_status_message("Save aborted");
+            return Ok(());
+        }
+    };
+    match editor.macros_mut().save(name.trim(), mac) {
+        Ok(filename) => editor.set_status_message(format!("Macro saved to {}", filename.display())),
+        Err(e) => editor.set_status_message(format!("{}", e)),
+    }
+    Ok(())
+}
+
+/// Replays the last or a named macro as many times as given in the prompt.
+pub(crate) fn run_prompt(
+    term: &mut impl Terminal,
+    decoder: &mut Decoder,
+    editor: &mut Editor,
+) -> keypress::Result<()> {
+    let input = match keypress::prompt(
+        term,
+        decoder,
+        editor,
+        "run-macro",
+        "Run macro: {} ([NAME] [COUNT], ESC to cancel)",
+    )? {
+        Some(input) => input,
+        None => {
+            editor.set_status_message("Run aborted");
+            return Ok(());
+        }
+    };
+
+    let mut words = input.split_whitespace().collect::<Vec<_>>();
+    let count = match words.last().map(|w| w.parse::<usize>()) {
+        Some(Ok(count)) => {
+            let _ = words.pop();
+            count
+        }
+        _ => 1,
+    };
+    let mac = match words.as_slice() {
+        [] => match editor.macros_mut().last().cloned() {
+            Some(mac) => mac,
+            None => {
+                editor.set_status_message("No keyboard macro defined");
+                return Ok(());
+            }
+        },
+        [name] => match editor.macros_mut().get(name) {
+            Ok(mac) => mac,
+            Err(e) => {
+                editor.set_status_message(format!("{}", e));
+                return Ok(());
+            }
+        },
+        _ => {
+            editor.set_status_message(format!("Invalid input: {}", input.trim()));
+            return Ok(());
+        }
+    };
+    replay(decoder, editor, &mac, count);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn convert() {
+        let s = "C-a <down> x <space> < <M-f4>";
+        let mac = s.parse::<Macro>().unwrap();
+        assert_eq!(mac.inputs().len(), 6);
+        assert_eq!(mac.to_string(), s);
+        assert_eq!(" \n".parse::<Macro>(), Ok(Macro::default()));
+        assert!("<nokey>".parse::<Macro>().is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let temp = TempDir::new("macros");
+        let dir = temp.path().join("macros");
+        let mac = "a b C-M".parse::<Macro>().unwrap();
+
+        let mut macros = Macros::with_dir(Some(dir.clone()));
+        let filename = macros.save("abc", mac.clone()).unwrap();
+        assert_eq!(filename, dir.join("abc"));
+        assert_eq!(fs::read_to_string(&filename).unwrap(), "a b C-M\n");
+
+        let mut macros = Macros::with_dir(Some(dir.clone()));
+        assert_eq!(macros.get("abc").unwrap(), mac);
+        assert!(matches!(macros.get("none"), Err(Error::Read { .. })));
+        assert!(matches!(
+            macros.get("../abc"),
+            Err(Error::InvalidName { .. })
+        ));
+    }
+}
@@ -1,28 +1,65 @@
-use crate::{decode::Decoder, editor::Editor, terminal::RawTerminal};
+use crate::{
+    command::Commands,
+    config::Config,
+    decode::Decoder,
+    editor::Editor,
+    goto::GotoTarget,
+    positions::Positions,
+    session::Session,
+    terminal::{RawTerminal, Terminal as _},
+};
 use log::{info, warn};
+use nix::unistd;
 use snafu::{ErrorCompat, ResultExt, Snafu};
-use std::{path::PathBuf, process};
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Write as _},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    process,
+};
 use structopt::StructOpt;
 
+mod buffer_list;
+mod command;
+mod completion;
+mod config;
 mod decode;
+mod directory;
 mod editor;
+mod editorconfig;
 mod file;
 mod find;
 mod frame;
 mod geom;
+mod goto;
+mod help;
+mod history;
+mod indent;
 mod input;
+mod keyboard;
 mod keymap;
 mod keypress;
+mod macros;
 mod output;
+mod positions;
 mod render;
 mod row;
+mod screen;
+mod session;
 mod signal;
 mod status_message;
 mod syntax;
+#[cfg(test)]
+mod temp_dir;
 mod terminal;
 mod text_buffer;
 mod text_buffer_view;
+mod timer;
 mod util;
+#[cfg(test)]
+mod virtual_terminal;
 mod welcome;
 
 #[derive(Debug, Snafu)]
@@ -33,44 +70,241 @@ enum Error {
     Keypress { source: keypress::Error },
     #[snafu(display("{}", source))]
     Output { source: output::Error },
+    #[snafu(display("Could not write to stdout: {}", source))]
+    WriteStdout { source: std::io::Error },
 }
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
-    /// File to process
+    /// Open files as readonly
+    #[structopt(short = "R", long)]
+    readonly: bool,
+    /// Restore the session from SESSION_FILE and save it there on exit. Without it, the session
+    /// of the current directory is restored and saved when no files are given
+    #[structopt(long, name = "SESSION_FILE", parse(from_os_str))]
+    session: Option<PathBuf>,
+    /// Files to process. `+N` moves the cursor of the next file to line N, and `FILE:N:C` opens
+    /// FILE at line N and column C
     #[structopt(name = "FILE", parse(from_os_str))]
-    file: Option<PathBuf>,
+    files: Vec<OsString>,
+    /// Files to process, even if they start with `-` or `+`
+    #[structopt(name = "LITERAL_FILE", last = true, parse(from_os_str))]
+    literal_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct FileArg {
+    path: PathBuf,
+    /// `-` reads the buffer from stdin
+    stdin: bool,
+    target: Option<GotoTarget>,
+}
+
+fn parse_file_args(files: &[OsString], literal_files: &[PathBuf]) -> Result<Vec<FileArg>, String> {
+    let mut args = vec![];
+    let mut next_target = None;
+    for file in files {
+        if let Some(pos) = file.to_str().and_then(|s| s.strip_prefix('+')) {
+            let target = if pos.is_empty() {
+                GotoTarget::Percent(100)
+            } else {
+                pos.parse()
+                    .map_err(|_| format!("invalid position: +{}", pos))?
+            };
+            next_target = Some(target);
+            continue;
+        }
+
+        let path = PathBuf::from(file);
+        let arg = match file.to_str() {
+            Some("-") => None,
+            Some(s) if !path.exists() => split_position(s),
+            _ => None,
+        }
+        .unwrap_or(FileArg {
+            stdin: file == "-",
+            path,
+            target: None,
+        });
+        args.push(FileArg {
+            target: next_target.take().or(arg.target),
+            ..arg
+        });
+    }
+    if next_target.is_some() && literal_files.is_empty() {
+        return Err("+N must be followed by a file".into());
+    }
+    for path in literal_files {
+        args.push(FileArg {
+            path: path.clone(),
+            stdin: false,
+            target: next_target.take(),
+        });
+    }
+    Ok(args)
+}
+
+// Splits `FILE:N:C` or `FILE:N` as printed by compilers, with an optional trailing colon
+fn split_position(s: &str) -> Option<FileArg> {
+    let s = s.strip_suffix(':').unwrap_or(s);
+    let (rest, last) = s.rsplit_once(':')?;
+    let last = last.parse::<usize>().ok()?;
+    let (path, line, col) = match rest.rsplit_once(':') {
+        Some((path, line)) if line.parse::<usize>().is_ok() => {
+            (path, line.parse().unwrap(), Some(last))
+        }
+        _ => (rest, last, None),
+    };
+    if path.is_empty() {
+        return None;
+    }
+    Some(FileArg {
+        path: path.into(),
+        stdin: false,
+        target: Some(GotoTarget::Line { line, col }),
+    })
 }
 
 fn run() -> Result<()> {
     let opt = Opt::from_args();
+    let file_args = match parse_file_args(&opt.files, &opt.literal_files) {
+        Ok(file_args) => file_args,
+        Err(e) => {
+            structopt::clap::Error::with_description(&e, structopt::clap::ErrorKind::InvalidValue)
+                .exit()
+        }
+    };
+
+    let (config, config_error) = match Config::load() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::new(), Some(e)),
+    };
+
+    let escape_delay = config.escape_delay();
+    let bindings = config.bindings().to_vec();
+    let macro_bindings = config.macro_bindings().to_vec();
 
     let mut term = RawTerminal::new().context(Terminal)?;
-    let mut render_size = term.screen_size;
+    let mut render_size = term.screen_size();
     render_size.rows -= 2;
-    let mut editor = Editor::new(render_size);
+    let mut editor = Editor::new(render_size, config);
 
-    editor.set_status_message("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-G = find");
+    if let Some(e) = config_error {
+        editor.set_status_message(format!("{}", e));
+    } else {
+        editor.set_status_message(
+            "HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-G = find | F1 b = bindings",
+        );
+    }
 
-    if let Some(file) = &opt.file {
-        editor.open(file);
+    // In filter mode, saving the buffer read from stdin writes it to stdout on exit
+    let to_stdout = !unistd::isatty(io::stdout().as_raw_fd()).unwrap_or(false);
+
+    match Positions::load() {
+        Ok(positions) => editor.set_positions(positions),
+        Err(e) => editor.set_status_message(format!("{}", e)),
+    }
+
+    // The session of the current directory is neither restored nor overwritten when files are
+    // given
+    let session_file = opt.session.clone().or_else(|| {
+        if !file_args.is_empty() {
+            return None;
+        }
+        let dir = env::current_dir().ok()?;
+        session::session_file(&dir)
+    });
+    if let Some(filename) = &session_file {
+        if filename.exists() {
+            match Session::load(filename) {
+                Ok(session) => editor.restore_session(&session),
+                Err(e) => editor.set_status_message(format!("{}", e)),
+            }
+        }
     }
 
-    let _km = keypress::default_keymap();
+    let mut opened = 0;
+    for arg in &file_args {
+        let ok = if !arg.stdin {
+            editor.open(&arg.path)
+        } else if unistd::isatty(io::stdin().as_raw_fd()).unwrap_or(false) {
+            editor.set_status_message("Standard input is a terminal");
+            false
+        } else {
+            editor.open_stdin(to_stdout)
+        };
+        if !ok {
+            continue;
+        }
+        opened += 1;
+        if let Some(target) = arg.target {
+            editor.goto(target);
+        }
+        if opt.readonly {
+            editor.set_readonly(true);
+        }
+    }
+    if opened > 1 {
+        // Show the first file
+        editor.next_buffer();
+    }
+
+    let mut commands = Commands::<RawTerminal>::new();
+    for (keys, name) in &bindings {
+        match commands.bind(keys, name) {
+            Ok(Some(displaced)) => editor.set_status_message(displaced),
+            Ok(None) => {}
+            Err(e) => editor.set_status_message(format!("{}", e)),
+        }
+    }
+    for (keys, name) in &macro_bindings {
+        let res = match editor.macros_mut().get(name) {
+            Ok(mac) => commands
+                .bind_macro(keys, name, mac)
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match res {
+            Ok(Some(displaced)) => editor.set_status_message(displaced),
+            Ok(None) => {}
+            Err(e) => editor.set_status_message(e),
+        }
+    }
     let mut decoder = Decoder::new();
+    decoder.set_escape_delay(escape_delay);
     loop {
         output::refresh_screen(&mut term, &mut editor).context(Output)?;
         output::flush(&mut term).context(Output)?;
 
-        if keypress::process_keypress(&mut term, &mut decoder, &mut editor).context(Keypress)? {
+        if keypress::process_keypress(&mut term, &mut decoder, &mut editor, &commands)
+            .context(Keypress)?
+        {
             break;
         }
     }
 
-    output::clear_screen(&mut term).context(Output)?;
+    // Leaving the alternate screen restores the previous contents of the terminal
     output::flush(&mut term).context(Output)?;
+    drop(term);
+
+    if let Err(e) = editor.save_positions() {
+        warn!("{}", e);
+        eprintln!("{}", e);
+    }
+    if let Some(filename) = &session_file {
+        if let Err(e) = editor.session().save(filename) {
+            warn!("{}", e);
+            eprintln!("{}", e);
+        }
+    }
+
+    if let Some(bytes) = editor.take_stdout_output() {
+        let mut stdout = io::stdout();
+        stdout.write_all(&bytes).context(WriteStdout)?;
+        stdout.flush().context(WriteStdout)?;
+    }
 
     Ok(())
 }
@@ -90,3 +324,49 @@ fn main() {
     }
     info!("end");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_args() {
+        fn check(files: &[&str], literal_files: &[&str]) -> Result<Vec<FileArg>, String> {
+            let files = files.iter().map(OsString::from).collect::<Vec<_>>();
+            let literal_files = literal_files.iter().map(PathBuf::from).collect::<Vec<_>>();
+            parse_file_args(&files, &literal_files)
+        }
+        let arg = |path: &str, stdin, target| FileArg {
+            path: path.into(),
+            stdin,
+            target,
+        };
+        let line = |line, col| Some(GotoTarget::Line { line, col });
+
+        assert_eq!(
+            check(&["a.rs", "+10", "b.rs", "+", "c.rs"], &[]).unwrap(),
+            vec![
+                arg("a.rs", false, None),
+                arg("b.rs", false, line(10, None)),
+                arg("c.rs", false, Some(GotoTarget::Percent(100))),
+            ]
+        );
+        assert_eq!(
+            check(&["nonexistent/a.rs:10:5:", "nonexistent/b.rs:3"], &[]).unwrap(),
+            vec![
+                arg("nonexistent/a.rs", false, line(10, Some(5))),
+                arg("nonexistent/b.rs", false, line(3, None)),
+            ]
+        );
+        assert_eq!(
+            check(&["+2"], &["-a", "+b"]).unwrap(),
+            vec![arg("-a", false, line(2, None)), arg("+b", false, None)]
+        );
+        assert_eq!(
+            check(&["-"], &["-"]).unwrap(),
+            vec![arg("-", true, None), arg("-", false, None)]
+        );
+        assert!(check(&["a.rs", "+2"], &[]).is_err());
+        assert!(check(&["+x", "a.rs"], &[]).is_err());
+    }
+}
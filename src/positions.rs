@@ -0,0 +1,204 @@
+use crate::{config, geom::Point};
+use snafu::{Backtrace, ResultExt, Snafu};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Could not read position file {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not write position file {}: {}", filename.display(), source))]
+    Write {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Files whose positions are kept; the least recently closed ones are forgotten first
+const MAX_ENTRIES: usize = 100;
+
+/// Where the cursor was when a file was closed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) struct Position {
+    pub(crate) cursor: Point,
+    /// The first line shown
+    pub(crate) top: usize,
+    /// The lines of the file then, to tell whether the position is still valid
+    pub(crate) lines: usize,
+}
+
+/// The last cursor positions in files, saved in `$XDG_STATE_HOME/mirri/positions`.
+///
+/// The file has a line of tab-separated `<line> <column> <top line> <lines> <path>` for each
+/// file, the least recently closed first.
+#[derive(Debug, Default)]
+pub(crate) struct Positions {
+    filename: Option<PathBuf>,
+    // The least recently recorded first
+    entries: VecDeque<(PathBuf, Position)>,
+    // Recorded since loaded, to be merged with the file written by other editors meanwhile
+    recorded: Vec<(PathBuf, Position)>,
+}
+
+impl Positions {
+    /// Creates a store not backed by a file.
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the positions from the file in the state directory.
+    pub(crate) fn load() -> Result<Self> {
+        match config::state_dir() {
+            Some(dir) => Self::from_file(dir.join("positions")),
+            None => Ok(Self::new()),
+        }
+    }
+
+    fn from_file(filename: PathBuf) -> Result<Self> {
+        let entries = read_entries(&filename)?;
+        Ok(Positions {
+            filename: Some(filename),
+            entries,
+            recorded: vec![],
+        })
+    }
+
+    pub(crate) fn get(&self, path: &Path) -> Option<Position> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(p, _)| p == path)
+            .map(|(_, pos)| *pos)
+    }
+
+    pub(crate) fn record(&mut self, path: &Path, pos: Position) {
+        // A path written in the file cannot contain a newline
+        match path.to_str() {
+            Some(s) if !s.contains('\n') => {}
+            _ => return,
+        }
+        insert(&mut self.entries, path, pos);
+        self.recorded.push((path.to_path_buf(), pos));
+    }
+
+    /// Writes the positions recorded since loaded over the ones in the file.
+    pub(crate) fn save(&mut self) -> Result<()> {
+        let filename = match &self.filename {
+            Some(filename) => filename,
+            None => return Ok(()),
+        };
+        let mut entries = read_entries(filename)?;
+        for (path, pos) in self.recorded.drain(..) {
+            insert(&mut entries, &path, pos);
+        }
+        self.entries = entries;
+
+        let mut s = String::new();
+        for (path, pos) in &self.entries {
+            s.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                pos.cursor.y,
+                pos.cursor.x,
+                pos.top,
+                pos.lines,
+                path.display()
+            ));
+        }
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir).context(Write { filename })?;
+        }
+        fs::write(filename, s).context(Write { filename })
+    }
+}
+
+fn insert(entries: &mut VecDeque<(PathBuf, Position)>, path: &Path, pos: Position) {
+    entries.retain(|(p, _)| p != path);
+    entries.push_back((path.to_path_buf(), pos));
+    while entries.len() > MAX_ENTRIES {
+        let _ = entries.pop_front();
+    }
+}
+
+// Reads the entries in the file, skipping invalid lines since they only cost a cursor position
+fn read_entries(filename: &Path) -> Result<VecDeque<(PathBuf, Position)>> {
+    let s = match fs::read_to_string(filename) {
+        Ok(s) => s,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(VecDeque::new()),
+        Err(e) => return Err(e).context(Read { filename }),
+    };
+    let mut entries = VecDeque::new();
+    for line in s.lines() {
+        if let Some((path, pos)) = parse_entry(line) {
+            insert(&mut entries, &path, pos);
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_entry(line: &str) -> Option<(PathBuf, Position)> {
+    let mut fields = line.splitn(5, '\t');
+    let mut number = || fields.next()?.parse::<usize>().ok();
+    let (y, x, top, lines) = (number()?, number()?, number()?, number()?);
+    let path = fields.next().filter(|path| !path.is_empty())?;
+    let pos = Position {
+        cursor: Point { x, y },
+        top,
+        lines,
+    };
+    Some((path.into(), pos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn save() {
+        let temp = TempDir::new("positions");
+        let filename = temp.path().join("positions");
+        let pos = |y, lines| Position {
+            cursor: Point { x: 1, y },
+            top: 0,
+            lines,
+        };
+
+        let mut positions = Positions::from_file(filename.clone()).unwrap();
+        positions.record(Path::new("/a.rs"), pos(1, 10));
+        positions.record(Path::new("/b.rs"), pos(2, 10));
+        positions.record(Path::new("/a.rs"), pos(3, 10));
+        assert_eq!(positions.get(Path::new("/a.rs")), Some(pos(3, 10)));
+        assert_eq!(positions.get(Path::new("/c.rs")), None);
+
+        // Another editor saves in the meantime
+        let mut other = Positions::from_file(filename.clone()).unwrap();
+        other.record(Path::new("/c.rs"), pos(4, 10));
+        other.record(Path::new("/a.rs"), pos(5, 10));
+        other.save().unwrap();
+
+        positions.save().unwrap();
+        assert_eq!(
+            fs::read_to_string(&filename).unwrap(),
+            "4\t1\t0\t10\t/c.rs\n2\t1\t0\t10\t/b.rs\n3\t1\t0\t10\t/a.rs\n"
+        );
+
+        for i in 0..MAX_ENTRIES {
+            positions.record(&PathBuf::from(format!("/{}.rs", i)), pos(0, 1));
+        }
+        positions.save().unwrap();
+        let positions = Positions::from_file(filename).unwrap();
+        assert_eq!(positions.entries.len(), MAX_ENTRIES);
+        assert_eq!(positions.get(Path::new("/a.rs")), None);
+        assert_eq!(positions.get(Path::new("/0.rs")), Some(pos(0, 1)));
+    }
+}
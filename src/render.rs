@@ -6,36 +6,49 @@ use std::{
 };
 use unicode_width::UnicodeWidthChar;
 
-const TAB_STOP: usize = 8;
+pub(crate) const TAB_STOP: usize = 8;
 
 pub(crate) trait RenderStrExt {
-    fn render_width(&self, start_col: usize) -> usize;
-    fn cx_from_rx(&self, start_col: usize, rx: usize) -> usize;
+    fn render_width(&self, start_col: usize, tab_width: usize) -> usize;
+    fn cx_from_rx(&self, start_col: usize, tab_width: usize, rx: usize) -> usize;
+    fn wrap_cols(
+        &self,
+        start_col: usize,
+        tab_width: usize,
+        width: usize,
+        word_wrap: bool,
+    ) -> Vec<usize>;
 
-    fn render_within(&self, start_col: usize, render_segment: Segment) -> RenderWithin;
+    fn render_within(
+        &self,
+        start_col: usize,
+        tab_width: usize,
+        render_segment: Segment,
+    ) -> RenderWithin;
     fn render_indices_within(
         &self,
         start_col: usize,
+        tab_width: usize,
         render_segment: Segment,
     ) -> RenderIndicesWithin;
 }
 
 impl RenderStrExt for str {
-    fn render_width(&self, start_col: usize) -> usize {
+    fn render_width(&self, start_col: usize, tab_width: usize) -> usize {
         let mut cur_col = start_col;
         for ch in self.chars() {
-            let item = RenderItem::build(ch, cur_col);
+            let item = RenderItem::build(ch, cur_col, tab_width);
             cur_col += item.width();
         }
         cur_col
     }
-    fn cx_from_rx(&self, start_col: usize, rx: usize) -> usize {
+    fn cx_from_rx(&self, start_col: usize, tab_width: usize, rx: usize) -> usize {
         let mut cur_col = start_col;
         for (idx, ch) in self.char_indices() {
             if rx <= cur_col {
                 return idx;
             }
-            let item = RenderItem::build(ch, cur_col);
+            let item = RenderItem::build(ch, cur_col, tab_width);
             cur_col += item.width();
             if cur_col > rx {
                 return idx;
@@ -43,19 +56,66 @@ impl RenderStrExt for str {
         }
         self.len()
     }
+    fn wrap_cols(
+        &self,
+        start_col: usize,
+        tab_width: usize,
+        width: usize,
+        word_wrap: bool,
+    ) -> Vec<usize> {
+        let mut wraps = vec![start_col];
+        if width == 0 {
+            return wraps;
+        }
+
+        let mut line_start = start_col;
+        let mut word_break = None;
+        let mut cur_col = start_col;
+        for ch in self.chars() {
+            let item = RenderItem::build(ch, cur_col, tab_width);
+            let col_e = cur_col + item.width();
+            while col_e > line_start + width {
+                line_start = match word_break {
+                    Some(col) if word_wrap && col > line_start => col,
+                    _ if cur_col > line_start => cur_col,
+                    // The item is wider than the whole line, so split it
+                    _ => line_start + width,
+                };
+                word_break = None;
+                wraps.push(line_start);
+            }
+            cur_col = col_e;
+            if ch.is_whitespace() {
+                word_break = Some(cur_col);
+            }
+        }
+
+        // Reserve a cell for the cursor placed at the end of the row
+        if cur_col > line_start && cur_col >= line_start + width {
+            wraps.push(cur_col);
+        }
+        wraps
+    }
 
-    fn render_within(&self, start_col: usize, render_segment: Segment) -> RenderWithin {
+    fn render_within(
+        &self,
+        start_col: usize,
+        tab_width: usize,
+        render_segment: Segment,
+    ) -> RenderWithin {
         RenderWithin {
-            inner: self.render_indices_within(start_col, render_segment),
+            inner: self.render_indices_within(start_col, tab_width, render_segment),
         }
     }
     fn render_indices_within(
         &self,
         start_col: usize,
+        tab_width: usize,
         render_segment: Segment,
     ) -> RenderIndicesWithin {
         RenderIndicesWithin {
             cur_col: start_col,
+            tab_width,
             render_segment,
             char_indices: self.char_indices(),
         }
@@ -78,6 +138,7 @@ impl<'a> Iterator for RenderWithin<'a> {
 #[derive(Debug, Clone)]
 pub(crate) struct RenderIndicesWithin<'a> {
     cur_col: usize,
+    tab_width: usize,
     render_segment: Segment,
     char_indices: CharIndices<'a>,
 }
@@ -97,7 +158,7 @@ impl<'a> Iterator for RenderIndicesWithin<'a> {
                 break;
             }
 
-            let item = RenderItem::build(ch, col_s);
+            let item = RenderItem::build(ch, col_s, self.tab_width);
             self.cur_col += item.width();
             let col_e = self.cur_col;
             if col_e <= scr_s {
@@ -163,9 +224,9 @@ impl RenderItem {
         }
     }
 
-    pub(crate) fn build(ch: char, cur_col: usize) -> Self {
+    pub(crate) fn build(ch: char, cur_col: usize, tab_width: usize) -> Self {
         if ch == '\t' {
-            let width = TAB_STOP - cur_col % TAB_STOP;
+            let width = tab_width - cur_col % tab_width;
             return Self::padding(width);
         }
         if ch.is_ascii_control() {
@@ -210,3 +271,50 @@ impl Display for RenderItem {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_cols_char() {
+        assert_eq!("".wrap_cols(0, TAB_STOP, 4, false), &[0]);
+        assert_eq!("abc".wrap_cols(0, TAB_STOP, 4, false), &[0]);
+        assert_eq!("abcd".wrap_cols(0, TAB_STOP, 4, false), &[0, 4]);
+        assert_eq!("abcdefghij".wrap_cols(0, TAB_STOP, 4, false), &[0, 4, 8]);
+        assert_eq!("ab cd ef".wrap_cols(0, TAB_STOP, 4, false), &[0, 4, 8]);
+    }
+
+    #[test]
+    fn wrap_cols_word() {
+        assert_eq!("ab cd ef".wrap_cols(0, TAB_STOP, 4, true), &[0, 3, 6]);
+        assert_eq!("abcdefghij".wrap_cols(0, TAB_STOP, 4, true), &[0, 4, 8]);
+        assert_eq!("a bcdefg".wrap_cols(0, TAB_STOP, 4, true), &[0, 2, 6]);
+    }
+
+    #[test]
+    fn wrap_cols_wide() {
+        // A wide char never straddles the wrap point
+        assert_eq!("aあいう".wrap_cols(0, TAB_STOP, 4, false), &[0, 3, 7]);
+        assert_eq!("あいう".wrap_cols(0, TAB_STOP, 3, false), &[0, 2, 4]);
+        assert_eq!("あ".wrap_cols(0, TAB_STOP, 1, false), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn tab_width() {
+        assert_eq!("\t".render_width(0, 8), 8);
+        assert_eq!("\t".render_width(0, 4), 4);
+        assert_eq!("ab\tc".render_width(0, 4), 5);
+        assert_eq!("ab\tc".render_width(0, 2), 5);
+        assert_eq!("\tc".cx_from_rx(0, 4, 3), 0);
+        assert_eq!("\tc".cx_from_rx(0, 4, 4), 1);
+    }
+
+    #[test]
+    fn wrap_cols_tab() {
+        // A tab is moved to the next line as a whole if it does not fit
+        assert_eq!("ab\tc".wrap_cols(0, TAB_STOP, 6, false), &[0, 2, 8]);
+        // A tab wider than the line is split
+        assert_eq!("\tx".wrap_cols(0, TAB_STOP, 4, false), &[0, 4, 8]);
+    }
+}
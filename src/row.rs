@@ -3,7 +3,7 @@ use crate::{
     render::{RenderIndicesWithin, RenderItem, RenderStrExt},
     syntax::{Highlight, Syntax, SyntaxState},
 };
-use std::usize;
+use std::{cell::Ref, ops::Range, usize};
 
 #[derive(Debug, Clone)]
 pub(crate) struct Row {
@@ -25,6 +25,10 @@ impl Row {
         &self.chars
     }
 
+    pub(crate) fn syntax(&self) -> &SyntaxState {
+        &self.syntax_state
+    }
+
     pub(crate) fn syntax_mut(&mut self) -> &mut SyntaxState {
         &mut self.syntax_state
     }
@@ -47,23 +51,34 @@ impl Row {
         );
     }
 
-    pub(crate) fn render(&self, render_segment: Segment) -> RenderIndicesWithin {
-        self.chars.render_indices_within(0, render_segment)
+    pub(crate) fn render(&self, render_segment: Segment, tab_width: usize) -> RenderIndicesWithin {
+        self.chars
+            .render_indices_within(0, tab_width, render_segment)
     }
 
-    pub(crate) fn render_with_highlight(&self, render_segment: Segment) -> RenderWithHighlight {
+    pub(crate) fn render_with_highlight<'a>(
+        &'a self,
+        render_segment: Segment,
+        tab_width: usize,
+        overlays: &'a [(Range<usize>, Highlight)],
+    ) -> RenderWithHighlight<'a> {
         RenderWithHighlight {
-            render: self.render(render_segment),
+            render: self.render(render_segment, tab_width),
             row: self,
+            overlays,
         }
     }
 
-    pub(crate) fn get_rx_from_cx(&self, cx: usize) -> usize {
-        self.chars[..cx].render_width(0)
+    pub(crate) fn get_rx_from_cx(&self, cx: usize, tab_width: usize) -> usize {
+        self.chars[..cx].render_width(0, tab_width)
+    }
+
+    pub(crate) fn get_cx_from_rx(&self, rx: usize, tab_width: usize) -> usize {
+        self.chars.cx_from_rx(0, tab_width, rx)
     }
 
-    pub(crate) fn get_cx_from_rx(&self, rx: usize) -> usize {
-        self.chars.cx_from_rx(0, rx)
+    pub(crate) fn wrap_cols(&self, tab_width: usize, width: usize, word_wrap: bool) -> Vec<usize> {
+        self.chars.wrap_cols(0, tab_width, width, word_wrap)
     }
 
     pub(crate) fn insert_char(&mut self, at: usize, ch: char) {
@@ -71,6 +86,11 @@ impl Row {
         self.invalidate_syntax();
     }
 
+    pub(crate) fn replace_range(&mut self, range: Range<usize>, s: &str) {
+        self.chars.replace_range(range, s);
+        self.invalidate_syntax();
+    }
+
     pub(crate) fn delete_char(&mut self, at: usize) {
         self.chars.remove(at);
         self.invalidate_syntax();
@@ -90,10 +110,28 @@ impl Row {
     }
 }
 
+/// A row to be drawn on a line of the screen.
+#[derive(Debug)]
+pub(crate) struct RenderRow<'a> {
+    pub(crate) row: Ref<'a, Row>,
+    pub(crate) segment: Segment,
+    pub(crate) tab_width: usize,
+    /// Highlights drawn over the syntax highlight of the row, such as matching brackets.
+    pub(crate) overlays: Vec<(Range<usize>, Highlight)>,
+}
+
+impl<'a> RenderRow<'a> {
+    pub(crate) fn render_with_highlight(&self) -> RenderWithHighlight<'_> {
+        self.row
+            .render_with_highlight(self.segment, self.tab_width, &self.overlays)
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct RenderWithHighlight<'a> {
     render: RenderIndicesWithin<'a>,
     row: &'a Row,
+    overlays: &'a [(Range<usize>, Highlight)],
 }
 
 impl<'a> Iterator for RenderWithHighlight<'a> {
@@ -101,7 +139,12 @@ impl<'a> Iterator for RenderWithHighlight<'a> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let (idx, item) = self.render.next()?;
-        let hl = self.row.syntax_state.highlight_at(idx);
+        let hl = self
+            .overlays
+            .iter()
+            .find(|(range, _)| range.contains(&idx))
+            .map(|(_, hl)| *hl)
+            .unwrap_or_else(|| self.row.syntax_state.highlight_at(idx));
         Some((hl, item))
     }
 }
@@ -0,0 +1,392 @@
+use crate::geom::{Point, Size};
+use smallvec::SmallVec;
+use std::io::{self, Write};
+use unicode_width::UnicodeWidthChar;
+
+/// Graphic rendition of a screen cell.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum Attr {
+    Default,
+    Color(u32, u32),
+    Reverse,
+}
+
+impl Attr {
+    fn write_sgr(self, out: &mut impl Write) -> io::Result<()> {
+        match self {
+            Attr::Default => write!(out, "\x1b[m"),
+            Attr::Color(fg, bg) => write!(out, "\x1b[0;{};{}m", fg, bg),
+            Attr::Reverse => write!(out, "\x1b[0;7m"),
+        }
+    }
+}
+
+/// A character cell on the screen.
+///
+/// The right half of a wide character is a cell with no text and zero width.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Cell {
+    // A base character followed by its combining characters
+    text: SmallVec<[char; 1]>,
+    width: usize,
+    attr: Attr,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Cell {
+            text: SmallVec::from_buf([' ']),
+            width: 1,
+            attr: Attr::Default,
+        }
+    }
+}
+
+impl Cell {
+    fn continuation(attr: Attr) -> Self {
+        Cell {
+            text: SmallVec::new(),
+            width: 0,
+            attr,
+        }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.width == 0
+    }
+}
+
+/// The contents of the terminal screen, built for every frame and compared against the previous
+/// one so that only the changed cells are written out.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Screen {
+    size: Size,
+    cells: Vec<Cell>,
+    pub(crate) cursor: Point,
+}
+
+impl Screen {
+    pub(crate) fn new(size: Size) -> Self {
+        Screen {
+            size,
+            cells: vec![Cell::default(); size.cols * size.rows],
+            cursor: Point::default(),
+        }
+    }
+
+    pub(crate) fn size(&self) -> Size {
+        self.size
+    }
+
+    fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.size.cols..(y + 1) * self.size.cols]
+    }
+
+    /// Writes `s` starting at `pos`, clipped at the end of the line, and returns the column after
+    /// the last written cell.
+    pub(crate) fn put_str(&mut self, pos: Point, s: &str, attr: Attr) -> usize {
+        let mut x = pos.x;
+        for ch in s.chars() {
+            x = self.put_char(Point { x, y: pos.y }, ch, attr);
+        }
+        x
+    }
+
+    fn put_char(&mut self, pos: Point, ch: char, attr: Attr) -> usize {
+        let Point { x, y } = pos;
+        if y >= self.size.rows {
+            return x;
+        }
+        let cols = self.size.cols;
+        let idx = y * cols + x;
+        let width = ch.width().unwrap_or(0);
+
+        if width == 0 {
+            // Combine with the character on the left
+            if let Some(cell) = self.cells[y * cols..idx]
+                .iter_mut()
+                .rev()
+                .find(|cell| !cell.is_continuation())
+            {
+                cell.text.push(ch);
+            }
+            return x;
+        }
+        if x + width > cols {
+            // A wide character that does not fit is replaced by padding
+            for cell in &mut self.cells[idx..(y + 1) * cols] {
+                *cell = Cell {
+                    attr,
+                    ..Cell::default()
+                };
+            }
+            return cols;
+        }
+
+        // Do not leave half of a wide character that is partially overwritten
+        if self.cells[idx].is_continuation() {
+            self.cells[idx - 1] = Cell {
+                attr: self.cells[idx - 1].attr,
+                ..Cell::default()
+            };
+        }
+        if let Some(next) = self.cells.get_mut(idx + width) {
+            if x + width < cols && next.is_continuation() {
+                *next = Cell {
+                    attr: next.attr,
+                    ..Cell::default()
+                };
+            }
+        }
+
+        self.cells[idx] = Cell {
+            text: SmallVec::from_buf([ch]),
+            width,
+            attr,
+        };
+        for cell in &mut self.cells[idx + 1..idx + width] {
+            *cell = Cell::continuation(attr);
+        }
+        x + width
+    }
+
+    /// Fills the cells from `pos` to the end of the line with blanks.
+    pub(crate) fn fill_line(&mut self, pos: Point, attr: Attr) {
+        let cols = self.size.cols;
+        let mut x = pos.x;
+        while x < cols {
+            x = self.put_char(Point { x, y: pos.y }, ' ', attr);
+        }
+    }
+
+    /// Writes the escape sequences which update the terminal showing `prev` to show this screen.
+    ///
+    /// If `prev` is `None` or has a different size, the whole screen is redrawn.
+    pub(crate) fn write_diff(&self, prev: Option<&Screen>, out: &mut impl Write) -> io::Result<()> {
+        let blank;
+        let (prev, pos) = match prev {
+            // The cursor was left where the previous screen put it
+            Some(prev) if prev.size == self.size => (prev, Some(prev.cursor)),
+            _ => {
+                // ED - Erase In Display
+                //   <esc> [ 2 J : clear the entire screen
+                out.write_all(b"\x1b[m\x1b[2J")?;
+                blank = Screen::new(self.size);
+                (&blank, None)
+            }
+        };
+
+        let mut cursor = DiffCursor {
+            pos,
+            attr: Attr::Default,
+        };
+        for y in 0..self.size.rows {
+            let row = self.row(y);
+            let prev_row = prev.row(y);
+            if row == prev_row {
+                continue;
+            }
+
+            // Blank cells at the end of the line are erased at once
+            let tail = row
+                .iter()
+                .rposition(|cell| *cell != Cell::default())
+                .map_or(0, |x| x + 1);
+
+            let mut x = 0;
+            let mut force = false;
+            while x < tail {
+                let cell = &row[x];
+                if cell.is_continuation() || (!force && *cell == prev_row[x]) {
+                    x += 1;
+                    continue;
+                }
+                cursor.move_to(Point { x, y }, row, out)?;
+                cursor.set_attr(cell.attr, out)?;
+                for ch in &cell.text {
+                    write!(out, "{}", ch)?;
+                }
+                cursor.advance(cell.width, self.size.cols);
+
+                // Overwriting the left half of a wide character erases the right half too
+                force = cell.width < prev_row[x].width;
+                x += cell.width;
+            }
+
+            if row[tail..] != prev_row[tail..] {
+                cursor.move_to(Point { x: tail, y }, row, out)?;
+                cursor.set_attr(Attr::Default, out)?;
+                // EL - Erase In Line
+                //  <esc> [ K : erase from active position to the end of the line
+                out.write_all(b"\x1b[K")?;
+            }
+        }
+
+        cursor.set_attr(Attr::Default, out)?;
+        if cursor.pos != Some(self.cursor) {
+            write_cup(self.cursor, out)?;
+        }
+        Ok(())
+    }
+}
+
+// The cursor of the terminal while writing a diff
+struct DiffCursor {
+    // `None` if unknown, such as just after writing to the last column
+    pos: Option<Point>,
+    attr: Attr,
+}
+
+impl DiffCursor {
+    // Moves to `dest` with the shortest sequence; rewriting a few unchanged cells in the current
+    // attributes is shorter than moving over them
+    fn move_to(&mut self, dest: Point, row: &[Cell], out: &mut impl Write) -> io::Result<()> {
+        match self.pos {
+            Some(pos) if pos == dest => {}
+            Some(pos) if pos.y == dest.y && pos.x < dest.x => {
+                let gap = &row[pos.x..dest.x];
+                let rewrite = gap.len() <= 3
+                    && gap.iter().all(|cell| {
+                        cell.attr == self.attr && cell.width == 1 && cell.text.len() == 1
+                    });
+                if rewrite {
+                    for cell in gap {
+                        write!(out, "{}", cell.text[0])?;
+                    }
+                } else {
+                    // CUF - Cursor Forward
+                    write!(out, "\x1b[{}C", dest.x - pos.x)?;
+                }
+            }
+            Some(pos) if dest.x == 0 && pos.y == dest.y => out.write_all(b"\r")?,
+            Some(pos) if dest.x == 0 && pos.y + 1 == dest.y => out.write_all(b"\r\n")?,
+            _ => write_cup(dest, out)?,
+        }
+        self.pos = Some(dest);
+        Ok(())
+    }
+
+    fn set_attr(&mut self, attr: Attr, out: &mut impl Write) -> io::Result<()> {
+        if self.attr != attr {
+            attr.write_sgr(out)?;
+            self.attr = attr;
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, width: usize, cols: usize) {
+        self.pos = self.pos.and_then(|pos| {
+            let x = pos.x + width;
+            // The position after writing to the last column depends on the terminal
+            if x < cols {
+                Some(Point { x, y: pos.y })
+            } else {
+                None
+            }
+        });
+    }
+}
+
+// CUP - Cursor Position
+//   <esc> [ <row> ; <col> H
+fn write_cup(pos: Point, out: &mut impl Write) -> io::Result<()> {
+    match (pos.y, pos.x) {
+        (0, 0) => out.write_all(b"\x1b[H"),
+        (y, 0) => write!(out, "\x1b[{}H", y + 1),
+        (y, x) => write!(out, "\x1b[{};{}H", y + 1, x + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(lines: &[&str]) -> Screen {
+        let cols = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+        let mut screen = Screen::new(Size {
+            cols,
+            rows: lines.len(),
+        });
+        for (y, line) in lines.iter().enumerate() {
+            screen.put_str(Point { x: 0, y }, line, Attr::Default);
+        }
+        screen
+    }
+
+    fn diff(screen: &Screen, prev: Option<&Screen>) -> String {
+        let mut out = vec![];
+        screen.write_diff(prev, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn full_redraw() {
+        let s = screen(&["ab  ", "    ", "c   "]);
+        assert_eq!(diff(&s, None), "\x1b[m\x1b[2J\x1b[Hab\x1b[3Hc\x1b[H");
+
+        let small = screen(&["ab"]);
+        assert!(diff(&s, Some(&small)).starts_with("\x1b[m\x1b[2J"));
+    }
+
+    #[test]
+    fn unchanged() {
+        let s = screen(&["abc", "def"]);
+        assert_eq!(diff(&s, Some(&s.clone())), "");
+
+        let mut moved = s.clone();
+        moved.cursor = Point { x: 1, y: 1 };
+        assert_eq!(diff(&moved, Some(&s)), "\x1b[2;2H");
+    }
+
+    #[test]
+    fn changed_spans() {
+        let prev = screen(&["abcdefghij", "0123456789"]);
+
+        // Short gaps are rewritten, long gaps are skipped
+        let s = screen(&["xbcyefghiz", "0123456789"]);
+        assert_eq!(diff(&s, Some(&prev)), "xbcy\x1b[5Cz\x1b[H");
+
+        let s = screen(&["abcdefghij", "0x23456789"]);
+        assert_eq!(diff(&s, Some(&prev)), "\x1b[2;2Hx\x1b[H");
+
+        // Blank tails are erased
+        let s = screen(&["abc", "0123456789"]);
+        assert_eq!(diff(&s, Some(&prev)), "abc\x1b[K\x1b[H");
+    }
+
+    #[test]
+    fn attributes() {
+        let prev = screen(&["abcd"]);
+        let mut s = prev.clone();
+        s.put_str(Point { x: 1, y: 0 }, "bc", Attr::Color(31, 40));
+        assert_eq!(diff(&s, Some(&prev)), "a\x1b[0;31;40mbc\x1b[m\x1b[H");
+
+        let mut s = prev.clone();
+        s.fill_line(Point { x: 2, y: 0 }, Attr::Reverse);
+        assert_eq!(diff(&s, Some(&prev)), "ab\x1b[0;7m  \x1b[m\x1b[H");
+    }
+
+    #[test]
+    fn wide_chars() {
+        let mut s = Screen::new(Size { cols: 5, rows: 1 });
+        assert_eq!(s.put_str(Point::default(), "aあい", Attr::Default), 5);
+        let prev = s.clone();
+
+        // Half of a wide character is replaced by a blank
+        s.put_str(Point { x: 2, y: 0 }, "b", Attr::Default);
+        let mut expected = Screen::new(Size { cols: 5, rows: 1 });
+        expected.put_str(Point::default(), "a bい", Attr::Default);
+        assert_eq!(s, expected);
+        assert_eq!(diff(&s, Some(&prev)), "a b\x1b[H");
+
+        // Wide characters which do not fit are padded
+        let mut s = Screen::new(Size { cols: 3, rows: 1 });
+        assert_eq!(s.put_str(Point::default(), "aaあ", Attr::Default), 3);
+        assert_eq!(diff(&s, None), "\x1b[m\x1b[2J\x1b[Haa\x1b[H");
+
+        // Combining characters are kept with their base
+        let mut s = Screen::new(Size { cols: 3, rows: 1 });
+        assert_eq!(s.put_str(Point::default(), "e\u{301}x", Attr::Default), 2);
+        assert_eq!(diff(&s, None), "\x1b[m\x1b[2J\x1b[He\u{301}x\x1b[H");
+    }
+}
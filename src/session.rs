@@ -0,0 +1,297 @@
+use crate::{
+    config,
+    frame::{Layout, SplitOrientation},
+    geom::Point,
+    history::Histories,
+};
+use snafu::{Backtrace, OptionExt, ResultExt, Snafu};
+use std::{
+    fmt::{self, Display, Formatter},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Snafu)]
+pub(crate) enum Error {
+    #[snafu(display("Could not read session file {}: {}", filename.display(), source))]
+    Read {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not write session file {}: {}", filename.display(), source))]
+    Write {
+        filename: PathBuf,
+        source: io::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("{}:{}: invalid line", filename.display(), line))]
+    InvalidLine { filename: PathBuf, line: usize },
+    #[snafu(display("{}: incomplete frames", filename.display()))]
+    IncompleteFrames { filename: PathBuf },
+}
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A buffer view to restore: the file, the cursor and the first line shown.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct ViewState {
+    pub(crate) path: PathBuf,
+    pub(crate) cursor: Point,
+    pub(crate) top: usize,
+}
+
+/// The open files, the frames showing them and the prompt histories, saved on exit to be
+/// restored on the next start.
+///
+/// The file consists of tab-separated records, one per line. The frame tree is written first
+/// in preorder, as `split`, `view` and `empty` records, followed by the `hidden` views of the
+/// buffers not shown and the `history` entries, the oldest first.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Session {
+    pub(crate) frames: Layout<ViewState>,
+    pub(crate) hidden: Vec<ViewState>,
+    pub(crate) histories: Histories,
+}
+
+impl Session {
+    pub(crate) fn load(filename: impl AsRef<Path>) -> Result<Self> {
+        let filename = filename.as_ref();
+        let s = fs::read_to_string(filename).with_context(|| Read {
+            filename: filename.to_path_buf(),
+        })?;
+        Self::parse(filename, &s)
+    }
+
+    pub(crate) fn save(&self, filename: impl AsRef<Path>) -> Result<()> {
+        let filename = filename.as_ref();
+        if let Some(dir) = filename.parent() {
+            fs::create_dir_all(dir).context(Write { filename })?;
+        }
+        fs::write(filename, self.to_string()).context(Write { filename })
+    }
+
+    fn parse(filename: &Path, s: &str) -> Result<Self> {
+        let mut frames = vec![];
+        let mut hidden = vec![];
+        let mut histories = Histories::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line_no = idx + 1;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || InvalidLine {
+                filename,
+                line: line_no,
+            };
+            let mut fields = line.splitn(2, '\t');
+            let (kind, rest) = (fields.next().unwrap(), fields.next().unwrap_or(""));
+            match kind {
+                "split" => {
+                    let mut fields = rest.split('\t');
+                    let orientation = match fields.next() {
+                        Some("vertical") => SplitOrientation::Vertical,
+                        _ => return invalid().fail(),
+                    };
+                    let focus_idx = fields
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .filter(|&idx| idx < 2)
+                        .with_context(invalid)?;
+                    frames.push(Node::Split {
+                        orientation,
+                        focus_idx,
+                    });
+                }
+                "view" => frames.push(Node::View(parse_view(rest).with_context(invalid)?)),
+                "empty" => frames.push(Node::Empty),
+                "hidden" => hidden.push(parse_view(rest).with_context(invalid)?),
+                "history" => {
+                    let mut fields = rest.splitn(2, '\t');
+                    match (fields.next(), fields.next()) {
+                        (Some(name), Some(input)) => histories.push(name, input),
+                        _ => return invalid().fail(),
+                    }
+                }
+                _ => return invalid().fail(),
+            }
+        }
+
+        let frames = if frames.is_empty() {
+            Layout::Empty
+        } else {
+            let mut nodes = frames.into_iter();
+            match build_layout(&mut nodes) {
+                Some(frames) if nodes.next().is_none() => frames,
+                _ => return IncompleteFrames { filename }.fail(),
+            }
+        };
+        Ok(Session {
+            frames,
+            hidden,
+            histories,
+        })
+    }
+}
+
+// A record of the frame tree
+#[derive(Debug)]
+enum Node {
+    Split {
+        orientation: SplitOrientation,
+        focus_idx: usize,
+    },
+    View(ViewState),
+    Empty,
+}
+
+// Builds the subtree from the records in preorder, or returns `None` if they run out
+fn build_layout(nodes: &mut impl Iterator<Item = Node>) -> Option<Layout<ViewState>> {
+    let layout = match nodes.next()? {
+        Node::Split {
+            orientation,
+            focus_idx,
+        } => {
+            let frame1 = build_layout(nodes)?;
+            let frame2 = build_layout(nodes)?;
+            Layout::Split {
+                frames: [Box::new(frame1), Box::new(frame2)],
+                focus_idx,
+                orientation,
+            }
+        }
+        Node::View(view) => Layout::Leaf(view),
+        Node::Empty => Layout::Empty,
+    };
+    Some(layout)
+}
+
+// Parses `<line>\t<column>\t<top line>\t<path>`
+fn parse_view(s: &str) -> Option<ViewState> {
+    let mut fields = s.splitn(4, '\t');
+    let mut number = || fields.next()?.parse::<usize>().ok();
+    let (y, x, top) = (number()?, number()?, number()?);
+    let path = fields.next().filter(|path| !path.is_empty())?;
+    Some(ViewState {
+        path: path.into(),
+        cursor: Point { x, y },
+        top,
+    })
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        fn write_view(f: &mut Formatter, kind: &str, view: &ViewState) -> fmt::Result {
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}",
+                kind,
+                view.cursor.y,
+                view.cursor.x,
+                view.top,
+                view.path.display()
+            )
+        }
+        fn write_frames(f: &mut Formatter, layout: &Layout<ViewState>) -> fmt::Result {
+            match layout {
+                Layout::Empty => writeln!(f, "empty"),
+                Layout::Leaf(view) => write_view(f, "view", view),
+                Layout::Split {
+                    frames,
+                    focus_idx,
+                    orientation: SplitOrientation::Vertical,
+                } => {
+                    writeln!(f, "split\tvertical\t{}", focus_idx)?;
+                    for frame in frames {
+                        write_frames(f, frame)?;
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        writeln!(f, "# mirri session")?;
+        write_frames(f, &self.frames)?;
+        for view in &self.hidden {
+            write_view(f, "hidden", view)?;
+        }
+        for (name, inputs) in self.histories.iter() {
+            for input in inputs {
+                writeln!(f, "history\t{}\t{}", name, input)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the file of the session of the directory, which is restored when the editor starts
+/// there without files to open.
+pub(crate) fn session_file(dir: &Path) -> Option<PathBuf> {
+    let name = dir
+        .to_string_lossy()
+        .replace('%', "%25")
+        .replace('/', "%2F");
+    Some(config::state_dir()?.join("sessions").join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let view = |path: &str, y, x, top| ViewState {
+            path: path.into(),
+            cursor: Point { x, y },
+            top,
+        };
+        let mut histories = Histories::new();
+        histories.push("goto-line", "10");
+        histories.push("goto-line", "20");
+        let session = Session {
+            frames: Layout::Split {
+                frames: [
+                    Box::new(Layout::Leaf(view("/src/main.rs", 10, 4, 2))),
+                    Box::new(Layout::Split {
+                        frames: [
+                            Box::new(Layout::Empty),
+                            Box::new(Layout::Leaf(view("/src/a b.rs", 0, 0, 0))),
+                        ],
+                        focus_idx: 1,
+                        orientation: SplitOrientation::Vertical,
+                    }),
+                ],
+                focus_idx: 1,
+                orientation: SplitOrientation::Vertical,
+            },
+            hidden: vec![view("/README.md", 3, 1, 0)],
+            histories,
+        };
+        let s = session.to_string();
+        assert_eq!(
+            s,
+            "# mirri session
+split\tvertical\t1
+view\t10\t4\t2\t/src/main.rs
+split\tvertical\t1
+empty
+view\t0\t0\t0\t/src/a b.rs
+hidden\t3\t1\t0\t/README.md
+history\tgoto-line\t10
+history\tgoto-line\t20
+"
+        );
+        assert_eq!(Session::parse(Path::new("session"), &s).unwrap(), session);
+
+        let empty = Session::parse(Path::new("session"), "# mirri session\n").unwrap();
+        assert_eq!(empty.frames, Layout::Empty);
+
+        let parse = |s| Session::parse(Path::new("session"), s);
+        assert!(parse("split\tvertical\t0\nempty\n").is_err());
+        assert!(parse("empty\nempty\n").is_err());
+        assert!(parse("split\tvertical\t2\nempty\nempty\n").is_err());
+        assert!(parse("view\t1\tx\t0\t/a.rs\n").is_err());
+        assert!(parse("history\tgoto-line\n").is_err());
+        assert!(parse("frame\n").is_err());
+    }
+}
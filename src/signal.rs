@@ -1,6 +1,11 @@
-use signal_hook::{SigId, SIGWINCH};
+use nix::libc::SIGTSTP;
+use signal_hook::{SigId, SIGCONT, SIGWINCH};
 use std::{
-    io::Result,
+    io::{Read, Result},
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -24,6 +29,14 @@ impl SignalReceiver {
         Self::new(SIGWINCH)
     }
 
+    pub(crate) fn new_sigtstp() -> Result<Self> {
+        Self::new(SIGTSTP)
+    }
+
+    pub(crate) fn new_sigcont() -> Result<Self> {
+        Self::new(SIGCONT)
+    }
+
     pub(crate) fn received(&mut self) -> bool {
         self.received.swap(false, Ordering::Relaxed)
     }
@@ -34,3 +47,47 @@ impl Drop for SignalReceiver {
         let _ = signal_hook::unregister(self.sigid);
     }
 }
+
+/// A pipe written to by the handlers of signals, so that signals can be waited for with `poll`
+/// together with the terminal input.
+#[derive(Debug)]
+pub(crate) struct SignalPipe {
+    read: UnixStream,
+    sigids: Vec<SigId>,
+}
+
+impl SignalPipe {
+    pub(crate) fn new(signals: &[i32]) -> Result<Self> {
+        let (read, write) = UnixStream::pair()?;
+        read.set_nonblocking(true)?;
+        let sigids = signals
+            .iter()
+            .map(|&signal| signal_hook::pipe::register(signal, write.try_clone()?))
+            .collect::<Result<_>>()?;
+        Ok(SignalPipe { read, sigids })
+    }
+
+    /// Discards the bytes written by the signal handlers.
+    pub(crate) fn drain(&mut self) {
+        let mut buf = [0; 64];
+        while let Ok(n) = self.read.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl AsRawFd for SignalPipe {
+    fn as_raw_fd(&self) -> RawFd {
+        self.read.as_raw_fd()
+    }
+}
+
+impl Drop for SignalPipe {
+    fn drop(&mut self) {
+        for sigid in self.sigids.drain(..) {
+            let _ = signal_hook::unregister(sigid);
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use std::time::Instant;
+use std::time::Duration;
+
+/// How long a status message is shown.
+pub(crate) const STATUS_MESSAGE_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub(crate) struct StatusMessage {
-    message: Option<(Instant, String)>,
+    message: Option<String>,
 }
 
 impl StatusMessage {
@@ -11,19 +14,14 @@ impl StatusMessage {
     }
 
     pub(crate) fn message(&self) -> Option<&str> {
-        self.message.as_ref().map(|s| s.1.as_str())
+        self.message.as_deref()
     }
 
     pub(crate) fn set_message(&mut self, s: impl Into<String>) {
-        let now = Instant::now();
-        self.message = Some((now, s.into()));
+        self.message = Some(s.into());
     }
 
-    pub(crate) fn update(&mut self) {
-        if let Some((time, _msg)) = &mut self.message {
-            if time.elapsed().as_secs() >= 5 {
-                self.message = None;
-            }
-        }
+    pub(crate) fn clear(&mut self) {
+        self.message = None;
     }
 }
@@ -1,3 +1,5 @@
+use crate::indent::IndentOverride;
+use matches::matches;
 use std::{ffi::OsStr, iter, ops::Range, path::Path};
 
 #[derive(Debug, Clone)]
@@ -10,6 +12,11 @@ pub(crate) struct Syntax<'a> {
     pub(crate) string_literal: &'a [(&'a str, &'a str, bool)],
     pub(crate) keyword1: &'a [&'a str],
     pub(crate) keyword2: &'a [&'a str],
+    pub(crate) indent: IndentOverride,
+    /// Line endings after which the next line is indented one more level.
+    pub(crate) indent_start: &'a [&'a str],
+    /// Chars that remove one indent level when typed as the first non-blank char of a line.
+    pub(crate) indent_end: &'a [char],
 }
 
 const DEFAULT: Syntax = Syntax {
@@ -21,6 +28,9 @@ const DEFAULT: Syntax = Syntax {
     string_literal: &[],
     keyword1: &[],
     keyword2: &[],
+    indent: IndentOverride::NONE,
+    indent_start: &[],
+    indent_end: &[],
 };
 
 const HLDB: &[Syntax] = &[
@@ -48,6 +58,9 @@ const HLDB: &[Syntax] = &[
         keyword2: &[
             "int", "long", "double", "float", "char", "unsigned", "signed", "void",
         ],
+        indent: IndentOverride::NONE,
+        indent_start: &["{", "(", "["],
+        indent_end: &['}', ')', ']'],
     },
     Syntax {
         filetype: "rust",
@@ -76,6 +89,43 @@ const HLDB: &[Syntax] = &[
             "i8", "i16", "i32", "i64", "isize", "u8", "u16", "u32", "u64", "usize", "bool", "char",
             "f32", "f64",
         ],
+        indent: IndentOverride {
+            tab_width: None,
+            indent_width: Some(4),
+            expand_tab: Some(true),
+        },
+        indent_start: &["{", "(", "["],
+        indent_end: &['}', ')', ']'],
+    },
+    Syntax {
+        filetype: "python",
+        filematch: &[".py"],
+        number: true,
+        single_line_comment: &["#"],
+        multi_line_comment: &[],
+        string_literal: &[
+            ("\"\"\"", "\"\"\"", true),
+            ("'''", "'''", true),
+            ("\"", "\"", true),
+            ("'", "'", true),
+        ],
+        keyword1: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        keyword2: &[
+            "int", "float", "complex", "str", "bytes", "bool", "list", "tuple", "dict", "set",
+            "object",
+        ],
+        indent: IndentOverride {
+            tab_width: None,
+            indent_width: Some(4),
+            expand_tab: Some(true),
+        },
+        indent_start: &[":", "{", "(", "["],
+        indent_end: &['}', ')', ']'],
     },
 ];
 
@@ -283,6 +333,8 @@ pub(crate) enum Highlight {
     String,
     Number,
     Match,
+    MatchingBracket,
+    Selection,
     LineMarker,
 }
 
@@ -297,9 +349,18 @@ impl Highlight {
             Self::String => (35, 40),
             Self::Number => (31, 40),
             Self::Match => (34, 40),
+            Self::MatchingBracket => (30, 43),
+            Self::Selection => (30, 47),
             Self::LineMarker => (37, 40),
         }
     }
+
+    pub(crate) fn is_comment_or_string(self) -> bool {
+        matches!(
+            self,
+            Self::SingleLineComment | Self::MultiLineComment | Self::String
+        )
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -336,6 +397,12 @@ impl SyntaxState {
         self.highlight[at]
     }
 
+    /// Returns the highlight of the char at `at` as classified by the syntax, ignoring overlays.
+    pub(crate) fn syntax_highlight_at(&self, at: usize) -> Highlight {
+        assert!(self.updated);
+        self.highlight[at]
+    }
+
     pub(crate) fn set_overlay(&mut self, range: Range<usize>, hl: Highlight) {
         self.overlay = Some((range, hl));
     }
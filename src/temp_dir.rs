@@ -0,0 +1,33 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A directory for the files of a test, removed with them when dropped.
+#[derive(Debug)]
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates `mirri-<name>-<pid>` in the temporary directory. `name` must differ between tests,
+    /// which run in parallel.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("mirri-{}-{}", name, process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let path = fs::canonicalize(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Returns the path with symbolic links resolved.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
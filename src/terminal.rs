@@ -1,15 +1,42 @@
-use crate::{geom::Size, signal::SignalReceiver};
-use nix::sys::termios::{self, SetArg, Termios};
+use crate::{
+    decode::InputSource,
+    geom::Size,
+    keyboard::{self, KeyboardProtocol, QueryResponse},
+    signal::{SignalPipe, SignalReceiver},
+};
+use nix::libc::SIGTSTP;
+use nix::{
+    errno::Errno,
+    poll::{self, PollFd, PollFlags},
+    sys::{
+        signal::{self, Signal},
+        termios::{self, SetArg, Termios},
+    },
+    unistd,
+};
+use signal_hook::{SIGCONT, SIGWINCH};
 use snafu::{Backtrace, ResultExt, Snafu};
 use std::{
-    io::{self, Read, Stdin, Stdout, Write},
-    os::unix::io::AsRawFd,
-    panic, str,
+    cell::RefCell,
+    collections::VecDeque,
+    convert::TryFrom,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    panic,
+    rc::Rc,
+    str,
     sync::Mutex,
+    time::Duration,
 };
 
 #[derive(Debug, Snafu)]
 pub(crate) enum Error {
+    #[snafu(display("Could not open terminal {}: {}", TTY_PATH, source))]
+    OpenTerminal {
+        source: io::Error,
+        backtrace: Backtrace,
+    },
     #[snafu(display("Could not enter raw mode: {}", source))]
     EnterRawMode {
         source: nix::Error,
@@ -20,11 +47,33 @@ pub(crate) enum Error {
         source: io::Error,
         backtrace: Backtrace,
     },
+    #[snafu(display("Could not leave raw mode: {}", source))]
+    LeaveRawMode {
+        source: nix::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not suspend: {}", source))]
+    Suspend {
+        source: nix::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Could not read from terminal: {}", source))]
+    TerminalInput {
+        source: io::Error,
+        backtrace: Backtrace,
+    },
     #[snafu(display("Could not write to terminal: {}", source))]
     TerminalOutput {
         source: io::Error,
         backtrace: Backtrace,
     },
+    #[snafu(display("Could not wait for terminal input: {}", source))]
+    WaitEvent {
+        source: nix::Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Terminal hung up"))]
+    Hangup { backtrace: Backtrace },
     #[snafu(display("Could not get window size"))]
     GetWindowSize { backtrace: Backtrace },
     #[snafu(display("Unecptected escape sequence: {:?}", seq))]
@@ -33,23 +82,79 @@ pub(crate) enum Error {
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
 
+const TTY_PATH: &str = "/dev/tty";
+
+// Switches to the alternate screen buffer saving the cursor position, and enables bracketed paste
+// and SGR mouse reports of button presses and drags
+const ENTER_SCREEN_MODE: &str = "\x1b[?1049h\x1b[?2004h\x1b[?1002h\x1b[?1006h";
+// Disables the modes above, shows the cursor and switches back to the normal screen buffer
+const LEAVE_SCREEN_MODE: &str = "\x1b[?1006l\x1b[?1002l\x1b[?2004l\x1b[?25h\x1b[?1049l";
+
+// How many read timeouts to wait for the replies to the keyboard protocol query
+const QUERY_TIMEOUT_COUNT: usize = 10;
+
+/// A terminal the editor reads input from and draws to.
+pub(crate) trait Terminal: InputSource + Write {
+    fn screen_size(&self) -> Size;
+
+    /// Waits until input is available, a signal is received or the timeout expires. Waits
+    /// without a time limit if `timeout` is `None`.
+    fn wait_event(&mut self, timeout: Option<Duration>) -> Result<TerminalEvent>;
+
+    /// Handles pending window size changes and job control signals, and returns `true` if the
+    /// whole screen needs to be redrawn.
+    fn process_signals(&mut self) -> Result<bool>;
+
+    /// Restores the terminal and stops the process until it is continued.
+    fn suspend(&mut self) -> Result<()>;
+}
+
+/// What ended waiting in `Terminal::wait_event`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum TerminalEvent {
+    Input,
+    Signal,
+    Timeout,
+}
+
+nix::ioctl_read_bad!(get_winsize, nix::libc::TIOCGWINSZ, nix::libc::winsize);
+
+type TtyWriter = Rc<RefCell<BufWriter<File>>>;
+
+/// The controlling terminal in raw mode.
+///
+/// The terminal is opened from `/dev/tty` rather than using stdin and stdout, so that they can be
+/// redirected to pipes.
 #[derive(Debug)]
 pub(crate) struct RawTerminal {
-    stdin: Stdin,
-    stdout: Stdout,
-    pub(crate) screen_size: Size,
+    input: File,
+    output: TtyWriter,
+    // Input read while waiting for query replies
+    pending_input: VecDeque<u8>,
+    screen_size: Size,
+    keyboard_protocol: KeyboardProtocol,
     sigwinch_receiver: SignalReceiver,
+    sigtstp_receiver: SignalReceiver,
+    sigcont_receiver: SignalReceiver,
+    // Wakes up `wait_event` on the signals above
+    signal_pipe: SignalPipe,
     orig_termios: Termios,
+    raw_termios: Termios,
 }
 
 impl RawTerminal {
     pub(crate) fn new() -> Result<Self> {
         use termios::SpecialCharacterIndices::*;
 
-        let stdin = io::stdin();
-        let stdout = io::stdout();
+        let input = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(TTY_PATH)
+            .context(OpenTerminal)?;
+        let output = input.try_clone().context(OpenTerminal)?;
+        let output = Rc::new(RefCell::new(BufWriter::new(output)));
 
-        let fd = stdin.as_raw_fd();
+        let fd = input.as_raw_fd();
         let mut raw = termios::tcgetattr(fd).context(EnterRawMode)?;
         let orig_termios = raw.clone();
 
@@ -60,6 +165,13 @@ impl RawTerminal {
         raw.control_chars[VTIME as usize] = 1; // maximum amount of time to wait before `read()` returns
 
         termios::tcsetattr(fd, SetArg::TCSAFLUSH, &raw).context(EnterRawMode)?;
+        write_screen_mode(&output, ENTER_SCREEN_MODE)?;
+
+        let mut input = input;
+        let response = query_keyboard_protocol(&mut input, &output)?;
+        let keyboard_protocol = response.protocol();
+        write_screen_mode(&output, keyboard_protocol.enable_seq())?;
+        let leave_seq = leave_seq(keyboard_protocol);
 
         {
             let orig_termios = Mutex::new(orig_termios.clone());
@@ -68,6 +180,9 @@ impl RawTerminal {
                 match orig_termios.try_lock() {
                     Err(e) => eprintln!("failed to acquire lock: {}", e),
                     Ok(orig_termios) => {
+                        if let Err(e) = unistd::write(fd, leave_seq.as_bytes()) {
+                            eprintln!("failed to leave alternate screen: {}", e);
+                        }
                         if let Err(e) = termios::tcsetattr(fd, SetArg::TCSAFLUSH, &orig_termios) {
                             eprintln!("failed to reset terminal mode: {}", e);
                         }
@@ -78,13 +193,24 @@ impl RawTerminal {
         }
 
         let sigwinch_receiver = SignalReceiver::new_sigwinch().context(SignalReceiverInit)?;
+        // Catching SIGTSTP prevents the process from being stopped before the terminal is restored
+        let sigtstp_receiver = SignalReceiver::new_sigtstp().context(SignalReceiverInit)?;
+        let sigcont_receiver = SignalReceiver::new_sigcont().context(SignalReceiverInit)?;
+        let signal_pipe =
+            SignalPipe::new(&[SIGWINCH, SIGTSTP, SIGCONT]).context(SignalReceiverInit)?;
 
         let mut term = Self {
-            stdin,
-            stdout,
+            input,
+            output,
+            pending_input: response.other.into(),
             screen_size: Size::default(),
+            keyboard_protocol,
             sigwinch_receiver,
+            sigtstp_receiver,
+            sigcont_receiver,
+            signal_pipe,
             orig_termios,
+            raw_termios: raw,
         };
 
         term.update_screen_size()?;
@@ -92,16 +218,12 @@ impl RawTerminal {
         Ok(term)
     }
 
-    pub(crate) fn hide_cursor(&mut self) -> Result<HideCursor> {
-        HideCursor::new(io::stdout())
-    }
-
-    pub(crate) fn maybe_update_screen_size(&mut self) -> Result<bool> {
-        let need_update = self.sigwinch_receiver.received();
-        if need_update {
-            self.update_screen_size()?;
-        }
-        Ok(need_update)
+    fn resume(&mut self) -> Result<()> {
+        termios::tcsetattr(self.input.as_raw_fd(), SetArg::TCSAFLUSH, &self.raw_termios)
+            .context(EnterRawMode)?;
+        write_screen_mode(&self.output, ENTER_SCREEN_MODE)?;
+        write_screen_mode(&self.output, self.keyboard_protocol.enable_seq())?;
+        Ok(())
     }
 
     fn update_screen_size(&mut self) -> Result<()> {
@@ -110,6 +232,9 @@ impl RawTerminal {
     }
 
     fn get_window_size(&mut self) -> Result<Size> {
+        if let Some(size) = tty_size(self.input.as_raw_fd()) {
+            return Ok(size);
+        }
         if let Some((cols, rows)) = term_size::dimensions() {
             return Ok(Size { cols, rows });
         }
@@ -117,46 +242,175 @@ impl RawTerminal {
     }
 }
 
-impl Drop for RawTerminal {
-    fn drop(&mut self) {
-        let fd = self.stdin.as_raw_fd();
-        termios::tcsetattr(fd, SetArg::TCSAFLUSH, &self.orig_termios)
-            .expect("failed to restore terminal mode");
+fn tty_size(fd: RawFd) -> Option<Size> {
+    let mut ws = nix::libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    unsafe { get_winsize(fd, &mut ws) }.ok()?;
+    if ws.ws_row == 0 || ws.ws_col == 0 {
+        return None;
     }
+    Some(Size {
+        cols: usize::from(ws.ws_col),
+        rows: usize::from(ws.ws_row),
+    })
 }
 
-impl Read for RawTerminal {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.stdin.read(buf)
+fn leave_seq(keyboard_protocol: KeyboardProtocol) -> String {
+    [keyboard_protocol.disable_seq(), LEAVE_SCREEN_MODE].concat()
+}
+
+// Detects the keyboard protocol from the replies to `keyboard::QUERY`
+fn query_keyboard_protocol(input: &mut File, output: &TtyWriter) -> Result<QueryResponse> {
+    write_screen_mode(output, keyboard::QUERY)?;
+
+    let mut bytes = vec![];
+    let mut buf = [0; 64];
+    let mut timeouts = 0;
+    loop {
+        match input.read(&mut buf).context(TerminalInput)? {
+            0 if timeouts < QUERY_TIMEOUT_COUNT => timeouts += 1,
+            0 => break,
+            n => bytes.extend_from_slice(&buf[..n]),
+        }
+        if QueryResponse::parse(&bytes).complete {
+            break;
+        }
     }
+    Ok(QueryResponse::parse(&bytes))
 }
 
-impl Write for RawTerminal {
-    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stdout.write(buf)
+fn write_screen_mode(output: &TtyWriter, seq: &str) -> Result<()> {
+    let mut output = output.borrow_mut();
+    output.write_all(seq.as_bytes()).context(TerminalOutput)?;
+    output.flush().context(TerminalOutput)?;
+    Ok(())
+}
+
+impl Terminal for RawTerminal {
+    fn screen_size(&self) -> Size {
+        self.screen_size
     }
-    fn flush(&mut self) -> io::Result<()> {
-        self.stdout.flush()
+
+    fn wait_event(&mut self, timeout: Option<Duration>) -> Result<TerminalEvent> {
+        if !self.pending_input.is_empty() {
+            return Ok(TerminalEvent::Input);
+        }
+        let mut fds = [
+            PollFd::new(self.input.as_raw_fd(), PollFlags::POLLIN),
+            PollFd::new(self.signal_pipe.as_raw_fd(), PollFlags::POLLIN),
+        ];
+        let timeout = timeout.map_or(-1, |t| {
+            // Round up so that the timeout has expired when `poll` returns
+            let millis = t.as_millis() + u128::from(t.subsec_nanos() % 1_000_000 != 0);
+            i32::try_from(millis).unwrap_or(i32::MAX)
+        });
+        let n = loop {
+            match poll::poll(&mut fds, timeout) {
+                Ok(n) => break n,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(e).context(WaitEvent),
+            }
+        };
+        if n == 0 {
+            return Ok(TerminalEvent::Timeout);
+        }
+        let revents = |fd: &PollFd| fd.revents().unwrap_or_else(PollFlags::empty);
+        if !revents(&fds[1]).is_empty() {
+            self.signal_pipe.drain();
+            return Ok(TerminalEvent::Signal);
+        }
+        let tty = revents(&fds[0]);
+        if !tty.contains(PollFlags::POLLIN)
+            && tty.intersects(PollFlags::POLLHUP | PollFlags::POLLERR | PollFlags::POLLNVAL)
+        {
+            return Hangup.fail();
+        }
+        Ok(TerminalEvent::Input)
+    }
+
+    fn process_signals(&mut self) -> Result<bool> {
+        if self.sigtstp_receiver.received() {
+            self.suspend()?;
+        }
+
+        let mut need_redraw = false;
+        if self.sigcont_receiver.received() {
+            self.resume()?;
+            need_redraw = true;
+        }
+        if self.sigwinch_receiver.received() {
+            need_redraw = true;
+        }
+        if need_redraw {
+            self.update_screen_size()?;
+        }
+        Ok(need_redraw)
+    }
+
+    fn suspend(&mut self) -> Result<()> {
+        write_screen_mode(&self.output, &leave_seq(self.keyboard_protocol))?;
+        termios::tcsetattr(
+            self.input.as_raw_fd(),
+            SetArg::TCSAFLUSH,
+            &self.orig_termios,
+        )
+        .context(LeaveRawMode)?;
+
+        // SIGSTOP cannot be caught, so this returns after SIGCONT is received
+        signal::raise(Signal::SIGSTOP).context(Suspend)?;
+        Ok(())
     }
 }
 
-pub(crate) struct HideCursor {
-    stdout: Stdout,
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        write_screen_mode(&self.output, &leave_seq(self.keyboard_protocol))
+            .expect("failed to leave alternate screen");
+        let fd = self.input.as_raw_fd();
+        termios::tcsetattr(fd, SetArg::TCSAFLUSH, &self.orig_termios)
+            .expect("failed to restore terminal mode");
+    }
 }
 
-impl HideCursor {
-    fn new(mut stdout: Stdout) -> Result<Self> {
-        // Hide cursor
-        write!(&mut stdout, "\x1b[?25l").context(TerminalOutput)?;
+impl Read for RawTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending_input.is_empty() {
+            let n = self.pending_input.len().min(buf.len());
+            for (dst, src) in buf.iter_mut().zip(self.pending_input.drain(..n)) {
+                *dst = src;
+            }
+            return Ok(n);
+        }
+        self.input.read(buf)
+    }
+}
 
-        Ok(HideCursor { stdout })
+impl InputSource for RawTerminal {
+    fn poll_input(&mut self, timeout: Duration) -> io::Result<bool> {
+        if !self.pending_input.is_empty() {
+            return Ok(true);
+        }
+        let mut fds = [PollFd::new(self.input.as_raw_fd(), PollFlags::POLLIN)];
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        loop {
+            match poll::poll(&mut fds, timeout) {
+                Ok(n) => return Ok(n > 0),
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(e) => return Err(e.as_errno().unwrap_or(Errno::UnknownErrno).into()),
+            }
+        }
     }
 }
 
-impl Drop for HideCursor {
-    fn drop(&mut self) {
-        // Show cursor
-        write!(&mut self.stdout, "\x1b[?25h").expect("failed to write to terminal");
-        self.stdout.flush().expect("failed to flush to stdout");
+impl Write for RawTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.borrow_mut().flush()
     }
 }
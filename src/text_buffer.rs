@@ -1,22 +1,81 @@
 use crate::{
-    file,
+    config::Config,
+    directory::Listing,
+    editorconfig,
+    file::{self, FileFormat},
     geom::{Point, Rect},
+    indent::{self, IndentSettings},
     row::Row,
     syntax::{Highlight, Syntax},
     util::SliceExt,
 };
 use std::{
+    cmp, io,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
     usize,
 };
 
+const BRACKET_PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+const BRACKET_SEARCH_ROWS: usize = 10000;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum WrapMode {
+    NoWrap,
+    Char,
+    Word,
+}
+
+impl WrapMode {
+    pub(crate) fn next(self) -> Self {
+        match self {
+            Self::NoWrap => Self::Char,
+            Self::Char => Self::Word,
+            Self::Word => Self::NoWrap,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Self::NoWrap => "no wrap",
+            Self::Char => "char wrap",
+            Self::Word => "word wrap",
+        }
+    }
+}
+
+/// Identifies a buffer while it is open.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub(crate) struct BufferId(usize);
+
+impl BufferId {
+    fn next() -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        BufferId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct TextBuffer {
+    id: BufferId,
     filename: Option<PathBuf>,
+    // `filename` made absolute, to tell whether two names refer to the same file
+    canonical_path: Option<PathBuf>,
+    // The name of a buffer not visiting a file, such as `*Help*`
+    name: Option<String>,
+    // The files shown in a directory buffer
+    directory: Option<Listing>,
     syntax: &'static Syntax<'static>,
     rows: Vec<Row>,
     dirty: bool,
     readonly: bool,
+    wrap_mode: WrapMode,
+    indent: IndentSettings,
+    file_format: FileFormat,
+    to_stdout: bool,
+    // Modification time of the file when it was last read or written
+    modified_time: Option<SystemTime>,
     empty_row: Row,
 }
 
@@ -30,11 +89,20 @@ impl TextBuffer {
             .set_overlay(0..1, Highlight::LineMarker);
 
         Self {
+            id: BufferId::next(),
             filename,
+            canonical_path: None,
+            name: None,
+            directory: None,
             syntax,
             rows: vec![],
             dirty: false,
             readonly: false,
+            wrap_mode: WrapMode::NoWrap,
+            indent: IndentSettings::default(),
+            file_format: FileFormat::default(),
+            to_stdout: false,
+            modified_time: None,
             empty_row,
         }
     }
@@ -45,23 +113,98 @@ impl TextBuffer {
         buf
     }
 
-    pub(crate) fn from_file(filename: impl Into<PathBuf>) -> file::Result<Self> {
+    pub(crate) fn from_file(filename: impl Into<PathBuf>, config: &Config) -> file::Result<Self> {
         let filename = filename.into();
         let mut buf = Self::new_empty();
+        buf.set_filename(Some(filename.clone()));
+        buf.apply_config(config);
         if file::exists(&filename) {
             buf.readonly = !file::writable(&filename)?;
-            let lines = file::open(&filename)?;
+            let lines = file::open(&filename, &mut buf.file_format)?;
             for line in lines {
                 buf.append_row(line);
             }
+            buf.modified_time = file::modified_time(&filename);
         } else {
             buf.append_row("");
         }
         buf.dirty = false;
-        buf.set_filename(Some(filename));
         Ok(buf)
     }
 
+    /// Reads an unnamed buffer from the standard input. If `to_stdout` is set, saving the buffer
+    /// does not ask for a filename but produces the bytes to be written to the standard output.
+    pub(crate) fn from_stdin(
+        stdin: impl io::Read,
+        config: &Config,
+        to_stdout: bool,
+    ) -> file::Result<Self> {
+        let mut buf = Self::new_empty();
+        buf.apply_config(config);
+        buf.to_stdout = to_stdout;
+        for line in file::read_stdin(stdin, &mut buf.file_format)? {
+            buf.append_row(line);
+        }
+        if buf.rows.is_empty() {
+            buf.append_row("");
+        }
+        buf.dirty = false;
+        Ok(buf)
+    }
+
+    /// Creates a readonly buffer showing `text`, which is not associated with a file.
+    pub(crate) fn from_text(name: impl Into<String>, text: &str) -> Self {
+        let mut buf = Self::new_empty();
+        buf.name = Some(name.into());
+        for line in text.lines() {
+            buf.append_row(line);
+        }
+        if buf.rows.is_empty() {
+            buf.append_row("");
+        }
+        buf.dirty = false;
+        buf.readonly = true;
+        buf
+    }
+
+    /// Creates a readonly buffer listing the files in a directory.
+    pub(crate) fn from_directory(dirname: impl AsRef<Path>) -> file::Result<Self> {
+        let dir = file::canonical_path(dirname);
+        let listing = Listing::read(&dir)?;
+        let mut buf = Self::new_empty();
+        buf.name = Some(listing.name());
+        buf.canonical_path = Some(dir);
+        buf.set_listing(listing);
+        buf.readonly = true;
+        Ok(buf)
+    }
+
+    fn set_listing(&mut self, listing: Listing) {
+        self.rows.clear();
+        for line in listing.text().lines() {
+            self.append_row(line);
+        }
+        self.directory = Some(listing);
+        self.dirty = false;
+    }
+
+    pub(crate) fn directory(&self) -> Option<&Listing> {
+        self.directory.as_ref()
+    }
+
+    /// Reads the directory listed in the buffer again.
+    pub(crate) fn reload_directory(&mut self) -> file::Result<()> {
+        if let Some(listing) = &self.directory {
+            let listing = Listing::read(listing.dir())?;
+            self.set_listing(listing);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn id(&self) -> BufferId {
+        self.id
+    }
+
     pub(crate) fn dirty(&self) -> bool {
         self.dirty
     }
@@ -70,6 +213,40 @@ impl TextBuffer {
         self.readonly
     }
 
+    pub(crate) fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    pub(crate) fn wrap_mode(&self) -> WrapMode {
+        self.wrap_mode
+    }
+
+    pub(crate) fn set_wrap_mode(&mut self, wrap_mode: WrapMode) {
+        self.wrap_mode = wrap_mode;
+    }
+
+    pub(crate) fn indent(&self) -> IndentSettings {
+        self.indent
+    }
+
+    pub(crate) fn set_indent(&mut self, indent: IndentSettings) {
+        self.indent = indent;
+    }
+
+    pub(crate) fn apply_config(&mut self, config: &Config) {
+        self.indent = config.indent_settings(self.syntax);
+        // Keep the ending read from the file
+        self.file_format = FileFormat {
+            final_newline: self.file_format.final_newline,
+            ..FileFormat::default()
+        };
+        if let Some(filename) = &self.filename {
+            let props = editorconfig::Properties::lookup(filename);
+            self.indent.apply(&props.indent_override());
+            props.apply_file_format(&mut self.file_format);
+        }
+    }
+
     pub(crate) fn lines(&self) -> usize {
         self.rows.len()
     }
@@ -91,28 +268,191 @@ impl TextBuffer {
     }
 
     pub(crate) fn update_highlight(&mut self, render_rect: Rect) {
-        for cy in 0..render_rect.origin.y + render_rect.size.rows {
+        self.update_highlight_until(render_rect.origin.y + render_rect.size.rows);
+        self.empty_row.update_highlight(self.syntax, None, None);
+    }
+
+    fn update_highlight_until(&mut self, end_y: usize) {
+        for cy in 0..end_y {
             let [prev, row, next] = self.rows.get3_mut(cy);
             if let Some(row) = row {
                 row.update_highlight(self.syntax, prev, next);
             }
         }
-        self.empty_row.update_highlight(self.syntax, None, None);
+    }
+
+    fn bracket_at(&self, c: Point) -> Option<(char, char, bool)> {
+        let ch = self.rows.get(c.y)?.chars()[c.x..].chars().next()?;
+        if self.rows[c.y]
+            .syntax()
+            .syntax_highlight_at(c.x)
+            .is_comment_or_string()
+        {
+            return None;
+        }
+        BRACKET_PAIRS.iter().find_map(|&(open, close)| {
+            if ch == open {
+                Some((open, close, true))
+            } else if ch == close {
+                Some((open, close, false))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Finds the bracket at or just before the cursor and its partner.
+    ///
+    /// Brackets in strings and comments are ignored, and the partner is searched within
+    /// `BRACKET_SEARCH_ROWS` rows.
+    pub(crate) fn find_matching_bracket(&mut self, c: Point) -> Option<(Point, Point)> {
+        if c.y >= self.rows.len() {
+            return None;
+        }
+        self.update_highlight_until(c.y + 1);
+
+        let mut candidates = vec![c];
+        if let Some(ch) = self.rows[c.y].chars()[..c.x].chars().next_back() {
+            candidates.push(Point {
+                x: c.x - ch.len_utf8(),
+                y: c.y,
+            });
+        }
+
+        for start in candidates {
+            let (open, close, forward) = match self.bracket_at(start) {
+                Some(bracket) => bracket,
+                None => continue,
+            };
+            let end_y = if forward {
+                cmp::min(start.y + BRACKET_SEARCH_ROWS, self.rows.len())
+            } else {
+                start.y + 1
+            };
+            self.update_highlight_until(end_y);
+            return self
+                .search_bracket(start, open, close, forward)
+                .map(|pos| (start, pos));
+        }
+        None
+    }
+
+    fn search_bracket(
+        &self,
+        start: Point,
+        open: char,
+        close: char,
+        forward: bool,
+    ) -> Option<Point> {
+        let (inc, dec) = if forward {
+            (open, close)
+        } else {
+            (close, open)
+        };
+        let mut depth = 0;
+        let rows: Box<dyn Iterator<Item = usize>> = if forward {
+            Box::new(start.y..cmp::min(start.y + BRACKET_SEARCH_ROWS, self.rows.len()))
+        } else {
+            Box::new((start.y.saturating_sub(BRACKET_SEARCH_ROWS - 1)..=start.y).rev())
+        };
+        for y in rows {
+            let row = &self.rows[y];
+            let chars = row.chars();
+            let mut check = |x: usize, ch: char| {
+                if row.syntax().syntax_highlight_at(x).is_comment_or_string() {
+                    return None;
+                }
+                if ch == inc {
+                    depth += 1;
+                } else if ch == dec {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(Point { x, y });
+                    }
+                }
+                None
+            };
+            let found = if forward {
+                let from = if y == start.y { start.x } else { 0 };
+                chars[from..]
+                    .char_indices()
+                    .find_map(|(dx, ch)| check(from + dx, ch))
+            } else {
+                let to = if y == start.y {
+                    start.x + close.len_utf8()
+                } else {
+                    chars.len()
+                };
+                chars[..to]
+                    .char_indices()
+                    .rev()
+                    .find_map(|(x, ch)| check(x, ch))
+            };
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
     }
 
     pub(crate) fn save(&mut self) -> file::Result<usize> {
         let filename = self.filename.as_ref().unwrap();
         let lines = self.rows.iter().map(|row| row.chars());
-        let bytes = file::save(&filename, lines)?;
+        let bytes = file::save(&filename, lines, &self.file_format)?;
+        self.modified_time = file::modified_time(filename);
+        self.dirty = false;
+        Ok(bytes)
+    }
+
+    /// Returns `true` if the file has been modified by another program since it was last read,
+    /// written or checked.
+    pub(crate) fn check_modified_on_disk(&mut self) -> bool {
+        let filename = match (&self.filename, self.modified_time) {
+            (Some(filename), Some(_)) => filename,
+            _ => return false,
+        };
+        match file::modified_time(filename) {
+            Some(time) if Some(time) != self.modified_time => {
+                self.modified_time = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn to_stdout(&self) -> bool {
+        self.to_stdout
+    }
+
+    /// Saves the buffer into bytes instead of a file.
+    pub(crate) fn save_to_bytes(&mut self) -> file::Result<Vec<u8>> {
+        let lines = self.rows.iter().map(|row| row.chars());
+        let bytes = file::encode_lines(file::STDIN_NAME, lines, &self.file_format)?;
         self.dirty = false;
         Ok(bytes)
     }
 
+    /// Returns the name shown for the buffer: its own name, or the file name.
+    pub(crate) fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        match self.filename.as_ref().and_then(|p| p.file_name()) {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "[No Name]".to_string(),
+        }
+    }
+
     pub(crate) fn filename(&self) -> Option<&Path> {
         self.filename.as_ref().map(|p| p.as_ref())
     }
 
+    pub(crate) fn canonical_path(&self) -> Option<&Path> {
+        self.canonical_path.as_deref()
+    }
+
     pub(crate) fn set_filename(&mut self, filename: Option<PathBuf>) {
+        self.canonical_path = filename.as_ref().map(file::canonical_path);
         self.filename = filename;
         self.syntax = Syntax::select(self.filename.as_ref());
         for row in &mut self.rows {
@@ -134,15 +474,82 @@ impl TextBuffer {
         self.dirty = true;
     }
 
-    pub(crate) fn insert_char(&mut self, c: Point, ch: char) {
-        self.rows[c.y].insert_char(c.x, ch);
+    /// Inserts a char and returns the cursor position after it.
+    pub(crate) fn insert_char(&mut self, mut c: Point, ch: char) -> Point {
+        let row = &mut self.rows[c.y];
+        let head = &row.chars()[..c.x];
+        if self.syntax.indent_end.contains(&ch) && head.trim_start().is_empty() {
+            let leading = self.indent.decrease(head);
+            row.replace_range(0..c.x, &leading);
+            c.x = leading.len();
+        }
+        row.insert_char(c.x, ch);
         self.dirty = true;
+        Point {
+            x: c.x + ch.len_utf8(),
+            y: c.y,
+        }
     }
 
-    pub(crate) fn insert_newline(&mut self, c: Point) {
+    /// Splits the row at the cursor and returns the cursor position at the start of the new row,
+    /// which is indented like the previous one. Splitting within the indentation moves the rest
+    /// of the row as is.
+    pub(crate) fn insert_newline(&mut self, c: Point) -> Point {
+        let indent_len = indent::leading_whitespace(self.rows[c.y].chars()).len();
         let rest = self.rows[c.y].split(c.x);
-        self.insert_row(c.y + 1, rest);
+        if c.x < indent_len {
+            self.insert_row(c.y + 1, rest);
+            return Point { x: 0, y: c.y + 1 };
+        }
+        let rest = rest.trim_start();
+
+        let head = self.rows[c.y].chars();
+        let mut leading = indent::leading_whitespace(head).to_string();
+        let head = head.trim_end();
+        if self.syntax.indent_start.iter().any(|s| head.ends_with(s)) {
+            leading = self.indent.increase(&leading);
+        }
+        if rest.starts_with(self.syntax.indent_end) {
+            leading = self.indent.decrease(&leading);
+        }
+
+        let x = leading.len();
+        self.insert_row(c.y + 1, leading + rest);
+        self.dirty = true;
+        Point { x, y: c.y + 1 }
+    }
+
+    /// Inserts text as is without auto-indentation, and returns the position after it.
+    pub(crate) fn insert_str(&mut self, c: Point, s: &str) -> Point {
+        let s = s.replace("\r\n", "\n").replace('\r', "\n");
+        let s = s.replace(|ch: char| ch.is_control() && ch != '\n' && ch != '\t', "");
+        let mut lines = s.split('\n');
+        let first = lines.next().unwrap();
+        let rest = lines.collect::<Vec<_>>();
         self.dirty = true;
+
+        let last = match rest.split_last() {
+            Some((last, middle)) => {
+                let tail = self.rows[c.y].split(c.x);
+                self.rows[c.y].append_str(first);
+                for (idx, line) in middle.iter().enumerate() {
+                    self.insert_row(c.y + 1 + idx, line.to_string());
+                }
+                self.insert_row(c.y + rest.len(), format!("{}{}", last, tail));
+                last
+            }
+            None => {
+                self.rows[c.y].replace_range(c.x..c.x, first);
+                return Point {
+                    x: c.x + first.len(),
+                    y: c.y,
+                };
+            }
+        };
+        Point {
+            x: last.len(),
+            y: c.y + rest.len(),
+        }
     }
 
     pub(crate) fn delete_char(&mut self, c: Point) {
@@ -159,3 +566,124 @@ impl TextBuffer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rust_buffer() -> TextBuffer {
+        let mut buf = TextBuffer::new();
+        buf.set_filename(Some("main.rs".into()));
+        buf.set_indent(IndentSettings {
+            tab_width: 8,
+            indent_width: 4,
+            expand_tab: true,
+        });
+        buf
+    }
+
+    fn type_str(buf: &mut TextBuffer, mut c: Point, s: &str) -> Point {
+        for ch in s.chars() {
+            c = if ch == '\n' {
+                buf.insert_newline(c)
+            } else {
+                buf.insert_char(c, ch)
+            };
+        }
+        c
+    }
+
+    #[test]
+    fn auto_indent() {
+        let mut buf = rust_buffer();
+        let c = type_str(&mut buf, Point::default(), "fn f() {\nlet x = [\n1,\n];\n}");
+        let rows = buf.rows().iter().map(|r| r.chars()).collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            &["fn f() {", "    let x = [", "        1,", "    ];", "}"]
+        );
+        assert_eq!(c, Point { x: 1, y: 4 });
+    }
+
+    #[test]
+    fn auto_indent_split() {
+        let mut buf = rust_buffer();
+        let c = type_str(&mut buf, Point::default(), "    {}");
+        let c = buf.insert_newline(Point { x: c.x - 1, ..c });
+        let rows = buf.rows().iter().map(|r| r.chars()).collect::<Vec<_>>();
+        assert_eq!(rows, &["    {", "    }"]);
+        assert_eq!(c, Point { x: 4, y: 1 });
+    }
+
+    #[test]
+    fn split_in_indent() {
+        let mut buf = rust_buffer();
+        type_str(&mut buf, Point::default(), "    a");
+        let c = buf.insert_newline(Point { x: 0, y: 0 });
+        let rows = buf.rows().iter().map(|r| r.chars()).collect::<Vec<_>>();
+        assert_eq!(rows, &["", "    a"]);
+        assert_eq!(c, Point { x: 0, y: 1 });
+
+        let c = buf.insert_newline(Point { x: 2, y: 1 });
+        let rows = buf.rows().iter().map(|r| r.chars()).collect::<Vec<_>>();
+        assert_eq!(rows, &["", "  ", "  a"]);
+        assert_eq!(c, Point { x: 0, y: 2 });
+    }
+
+    #[test]
+    fn insert_pasted_text() {
+        let mut buf = rust_buffer();
+        let c = type_str(&mut buf, Point::default(), "fn f() {}");
+        let c = buf.insert_str(Point { x: c.x - 1, ..c }, "\r\n  a\r\tb\x07\n");
+        let rows = buf.rows().iter().map(|r| r.chars()).collect::<Vec<_>>();
+        assert_eq!(rows, &["fn f() {", "  a", "\tb", "}"]);
+        assert_eq!(c, Point { x: 0, y: 3 });
+
+        let c = buf.insert_str(Point { x: 2, y: 1 }, "bc");
+        assert_eq!(buf.row_at(1).chars(), "  bca");
+        assert_eq!(c, Point { x: 4, y: 1 });
+    }
+
+    #[test]
+    fn stdin_roundtrip() {
+        for &s in &["a\nb\n", "a\nb", "a\n\n", ""] {
+            let mut buf = TextBuffer::from_stdin(s.as_bytes(), &Config::default(), true).unwrap();
+            assert_eq!(buf.save_to_bytes().unwrap(), s.as_bytes());
+        }
+    }
+
+    #[test]
+    fn matching_bracket() {
+        let mut buf = rust_buffer();
+        type_str(
+            &mut buf,
+            Point::default(),
+            "fn f(a: [u8; 2]) {\ng(\"(\", ')'); // )\n}",
+        );
+        let p = |x, y| Point { x, y };
+
+        assert_eq!(
+            buf.find_matching_bracket(p(4, 0)),
+            Some((p(4, 0), p(15, 0)))
+        );
+        assert_eq!(
+            buf.find_matching_bracket(p(16, 0)),
+            Some((p(15, 0), p(4, 0)))
+        );
+        assert_eq!(
+            buf.find_matching_bracket(p(17, 0)),
+            Some((p(17, 0), p(0, 2)))
+        );
+        assert_eq!(
+            buf.find_matching_bracket(p(0, 2)),
+            Some((p(0, 2), p(17, 0)))
+        );
+        assert_eq!(
+            buf.find_matching_bracket(p(5, 1)),
+            Some((p(5, 1), p(14, 1)))
+        );
+        // in a string
+        assert_eq!(buf.find_matching_bracket(p(7, 1)), None);
+        assert_eq!(buf.find_matching_bracket(p(1, 0)), None);
+    }
+}
@@ -1,13 +1,14 @@
 use crate::{
     editor::CursorMove,
     geom::{Point, Rect, Segment, Size},
-    row::Row,
+    goto::GotoTarget,
+    row::RenderRow,
     syntax::{Highlight, Syntax},
-    text_buffer::TextBuffer,
+    text_buffer::{TextBuffer, WrapMode},
 };
 use std::{
     cell::{Ref, RefCell, RefMut},
-    path::Path,
+    ops::Range,
     rc::Rc,
 };
 
@@ -16,6 +17,17 @@ pub(crate) struct TextBufferView {
     buffer: Rc<RefCell<TextBuffer>>,
     c: Point,
     render_rect: Rect,
+    // Index of the first visible wrapped line in the row at `render_rect.origin.y`
+    render_wrap_idx: usize,
+    bracket_match: Option<(Point, Point)>,
+    // The other end of the selection from the cursor
+    mark: Option<Point>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum YScroll {
+    Up(usize),
+    Down(usize),
 }
 
 impl TextBufferView {
@@ -28,6 +40,9 @@ impl TextBufferView {
             buffer: Rc::new(RefCell::new(buffer)),
             c: Point::default(),
             render_rect,
+            render_wrap_idx: 0,
+            bracket_match: None,
+            mark: None,
         }
     }
 
@@ -35,15 +50,298 @@ impl TextBufferView {
         self.render_rect.size = render_size;
     }
 
-    pub(crate) fn render_row_at(&self, at: usize) -> (Segment, Ref<Row>) {
-        let row = Ref::map(self.buffer.borrow(), |b| {
-            b.row_at(self.render_rect.origin.y + at)
-        });
-        (self.render_rect.x_segment(), row)
+    pub(crate) fn render_row_at(&self, at: usize) -> RenderRow {
+        let tab_width = self.tab_width();
+        if self.wrap_mode() == WrapMode::NoWrap {
+            let row = Ref::map(self.buffer.borrow(), |b| {
+                b.row_at(self.render_rect.origin.y + at)
+            });
+            return RenderRow {
+                row,
+                segment: self.render_rect.x_segment(),
+                tab_width,
+                overlays: self.overlays_at(self.render_rect.origin.y + at),
+            };
+        }
+
+        let (y, idx) = self.wrap_forward((self.render_rect.origin.y, self.render_wrap_idx), at);
+        let wraps = self.wrap_cols_at(y);
+        let row = Ref::map(self.buffer.borrow(), |b| b.row_at(y));
+        let origin = wraps[idx];
+        let size = wraps
+            .get(idx + 1)
+            .map(|next| next - origin)
+            .unwrap_or(self.render_rect.size.cols);
+        RenderRow {
+            row,
+            segment: Segment { origin, size },
+            tab_width,
+            overlays: self.overlays_at(y),
+        }
+    }
+
+    fn overlays_at(&self, y: usize) -> Vec<(Range<usize>, Highlight)> {
+        let buffer = self.buffer.borrow();
+        let mut overlays = vec![];
+        if let Some((p1, p2)) = self.bracket_match {
+            overlays.extend([p1, p2].iter().filter(|p| p.y == y).filter_map(|p| {
+                let ch = buffer.rows().get(p.y)?.chars().get(p.x..)?.chars().next()?;
+                Some((p.x..p.x + ch.len_utf8(), Highlight::MatchingBracket))
+            }));
+        }
+        if let Some((start, end)) = self.selection() {
+            if start.y <= y && y <= end.y {
+                let x0 = if y == start.y { start.x } else { 0 };
+                let x1 = if y == end.y {
+                    end.x
+                } else {
+                    buffer.row_at(y).chars().len()
+                };
+                overlays.push((x0..x1, Highlight::Selection));
+            }
+        }
+        overlays
+    }
+
+    /// Returns the selected range from the start to the end.
+    pub(crate) fn selection(&self) -> Option<(Point, Point)> {
+        let mark = self.mark?;
+        if (mark.y, mark.x) <= (self.c.y, self.c.x) {
+            Some((mark, self.c))
+        } else {
+            Some((self.c, mark))
+        }
+    }
+
+    pub(crate) fn clear_selection(&mut self) {
+        self.mark = None;
+    }
+
+    // Converts a position relative to the view into a position in the buffer
+    fn position_at(&self, p: Point) -> Point {
+        let buffer = self.buffer.borrow();
+        let last = buffer.lines().saturating_sub(1);
+        let (y, rx) = if buffer.wrap_mode() == WrapMode::NoWrap {
+            (
+                self.render_rect.origin.y + p.y,
+                self.render_rect.origin.x + p.x,
+            )
+        } else {
+            let (y, idx) =
+                self.wrap_forward((self.render_rect.origin.y, self.render_wrap_idx), p.y);
+            let wraps = self.wrap_cols_at(y);
+            let mut rx = wraps[idx] + p.x;
+            if let Some(next) = wraps.get(idx + 1) {
+                rx = rx.min(next - 1);
+            }
+            (y, rx)
+        };
+        if y > last {
+            let x = buffer.rows().get(last).map(|row| row.chars().len());
+            return Point {
+                x: x.unwrap_or(0),
+                y: last,
+            };
+        }
+        Point {
+            x: buffer.row_at(y).get_cx_from_rx(rx, self.tab_width()),
+            y,
+        }
+    }
+
+    /// Places the cursor at the clicked position and starts a selection.
+    pub(crate) fn mouse_press(&mut self, p: Point) {
+        self.c = self.position_at(p);
+        self.mark = Some(self.c);
+    }
+
+    pub(crate) fn mouse_drag(&mut self, p: Point) {
+        self.c = self.position_at(p);
+    }
+
+    pub(crate) fn mouse_release(&mut self, p: Point) {
+        self.mouse_drag(p);
+        if self.mark == Some(self.c) {
+            self.mark = None;
+        }
+    }
+
+    /// Scrolls the view by `n` screen lines, keeping the cursor in the view.
+    pub(crate) fn scroll_view(&mut self, n: isize) {
+        let rows = self.render_rect.size.rows;
+        let last = self.buffer.borrow().lines().saturating_sub(1);
+        let rx = self.cursor_rx();
+        if self.wrap_mode() == WrapMode::NoWrap {
+            let y = &mut self.render_rect.origin.y;
+            *y = if n < 0 {
+                y.saturating_sub(n.unsigned_abs())
+            } else {
+                (*y + n as usize).min(last)
+            };
+            let x = rx.saturating_sub(self.render_rect.origin.x);
+            if self.c.y < self.render_rect.origin.y {
+                self.c = self.position_at(Point { x, y: 0 });
+            } else if self.c.y >= self.render_rect.origin.y + rows {
+                self.c = self.position_at(Point { x, y: rows - 1 });
+            }
+            return;
+        }
+
+        let origin = (self.render_rect.origin.y, self.render_wrap_idx);
+        let origin = if n < 0 {
+            self.wrap_backward(origin, n.unsigned_abs())
+        } else {
+            let last_origin = (last, self.wrap_cols_at(last).len() - 1);
+            self.wrap_forward(origin, n as usize).min(last_origin)
+        };
+        self.render_rect.origin.y = origin.0;
+        self.render_wrap_idx = origin.1;
+        let (cur, x) = self.wrap_pos(self.c.y, rx);
+        if cur < origin {
+            self.c = self.position_at(Point { x, y: 0 });
+        } else if self.wrap_distance(origin, cur, rows) >= rows {
+            self.c = self.position_at(Point { x, y: rows - 1 });
+        }
+    }
+
+    fn wrap_mode(&self) -> WrapMode {
+        self.buffer.borrow().wrap_mode()
+    }
+
+    fn tab_width(&self) -> usize {
+        self.buffer.borrow().indent().tab_width
+    }
+
+    fn wrap_cols_at(&self, y: usize) -> Vec<usize> {
+        let word_wrap = self.wrap_mode() == WrapMode::Word;
+        self.buffer.borrow().row_at(y).wrap_cols(
+            self.tab_width(),
+            self.render_rect.size.cols,
+            word_wrap,
+        )
+    }
+
+    fn wrap_pos(&self, y: usize, rx: usize) -> ((usize, usize), usize) {
+        let wraps = self.wrap_cols_at(y);
+        let idx = wraps.iter().rposition(|&col| col <= rx).unwrap_or(0);
+        ((y, idx), rx - wraps[idx])
+    }
+
+    fn wrap_forward(&self, (mut y, mut idx): (usize, usize), mut n: usize) -> (usize, usize) {
+        while n > 0 {
+            let lines = self.wrap_cols_at(y).len();
+            if idx + n < lines {
+                idx += n;
+                break;
+            }
+            n -= lines - idx;
+            y += 1;
+            idx = 0;
+        }
+        (y, idx)
+    }
+
+    fn wrap_backward(&self, (mut y, mut idx): (usize, usize), mut n: usize) -> (usize, usize) {
+        while n > 0 {
+            if idx >= n {
+                idx -= n;
+                break;
+            }
+            if y == 0 {
+                idx = 0;
+                break;
+            }
+            n -= idx + 1;
+            y -= 1;
+            idx = self.wrap_cols_at(y).len() - 1;
+        }
+        (y, idx)
+    }
+
+    // Counts wrapped lines from `from` to `to`, giving up at `limit`
+    fn wrap_distance(&self, from: (usize, usize), to: (usize, usize), limit: usize) -> usize {
+        let mut pos = from;
+        let mut n = 0;
+        while pos < to && n < limit {
+            pos = self.wrap_forward(pos, 1);
+            n += 1;
+        }
+        n
     }
 
     pub(crate) fn update_highlight(&mut self) {
-        self.buffer.borrow_mut().update_highlight(self.render_rect)
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.update_highlight(self.render_rect);
+        self.bracket_match = buffer.find_matching_bracket(self.c);
+    }
+
+    /// Moves the cursor to the line and the column in chars, and centres it in the view.
+    pub(crate) fn goto(&mut self, target: GotoTarget) {
+        let (y, col) = {
+            let buffer = self.buffer.borrow();
+            target.resolve(self.c.y, buffer.lines())
+        };
+        let chars = self.buffer.borrow().rows()[y].chars().to_string();
+        self.c.y = y;
+        self.c.x = match col {
+            Some(col) => chars
+                .char_indices()
+                .nth(col)
+                .map(|(idx, _)| idx)
+                .unwrap_or_else(|| chars.len()),
+            None => chars.len() - chars.trim_start().len(),
+        };
+        self.center_cursor();
+    }
+
+    pub(crate) fn cursor(&self) -> Point {
+        self.c
+    }
+
+    /// Returns the first line shown in the view.
+    pub(crate) fn top_line(&self) -> usize {
+        self.render_rect.origin.y
+    }
+
+    /// Moves the cursor and scrolls the view to show `top` first, clamping both to the buffer.
+    pub(crate) fn set_position(&mut self, cursor: Point, top: usize) {
+        let buffer = self.buffer.borrow();
+        let last = buffer.lines().saturating_sub(1);
+        let y = cursor.y.min(last);
+        let chars = buffer.rows().get(y).map(|row| row.chars()).unwrap_or("");
+        let mut x = cursor.x.min(chars.len());
+        while !chars.is_char_boundary(x) {
+            x -= 1;
+        }
+        self.c = Point { x, y };
+        self.render_rect.origin = Point {
+            x: 0,
+            y: top.min(last),
+        };
+        self.render_wrap_idx = 0;
+    }
+
+    fn center_cursor(&mut self) {
+        let half = self.render_rect.size.rows / 2;
+        if self.wrap_mode() == WrapMode::NoWrap {
+            self.render_rect.origin.y = self.c.y.saturating_sub(half);
+            return;
+        }
+        let (cur, _) = self.wrap_pos(self.c.y, self.cursor_rx());
+        let (y, idx) = self.wrap_backward(cur, half);
+        self.render_rect.origin.y = y;
+        self.render_wrap_idx = idx;
+    }
+
+    pub(crate) fn jump_to_matching_bracket(&mut self) -> bool {
+        let bracket_match = self.buffer.borrow_mut().find_matching_bracket(self.c);
+        match bracket_match {
+            Some((_, pos)) => {
+                self.c = pos;
+                true
+            }
+            None => false,
+        }
     }
 
     pub(crate) fn buffer(&self) -> Ref<TextBuffer> {
@@ -57,7 +355,7 @@ impl TextBufferView {
     pub(crate) fn status(&self) -> Status {
         let buffer = self.buffer.borrow();
         Status {
-            filename: ref_filter_map::ref_filter_map(self.buffer.borrow(), |b| b.filename()),
+            name: buffer.name(),
             dirty: buffer.dirty(),
             readonly: buffer.readonly(),
             cursor: self.c,
@@ -67,13 +365,11 @@ impl TextBufferView {
     }
 
     pub(crate) fn scroll(&mut self) -> Point {
-        let rx = self
-            .buffer
-            .borrow()
-            .rows()
-            .get(self.c.y)
-            .map(|row| row.get_rx_from_cx(self.c.x))
-            .unwrap_or(0);
+        if self.wrap_mode() != WrapMode::NoWrap {
+            return self.scroll_wrapped();
+        }
+
+        let rx = self.cursor_rx();
 
         if self.render_rect.origin.y > self.c.y {
             self.render_rect.origin.y = self.c.y;
@@ -93,14 +389,47 @@ impl TextBufferView {
         }
     }
 
+    fn scroll_wrapped(&mut self) -> Point {
+        let rx = self.cursor_rx();
+        let (cur, x) = self.wrap_pos(self.c.y, rx);
+
+        // Wrapped lines are changed when the frame is resized
+        let origin_lines = self.wrap_cols_at(self.render_rect.origin.y).len();
+        if self.render_wrap_idx >= origin_lines {
+            self.render_wrap_idx = origin_lines - 1;
+        }
+
+        let rows = self.render_rect.size.rows;
+        let mut origin = (self.render_rect.origin.y, self.render_wrap_idx);
+        if origin > cur {
+            origin = cur;
+        }
+        if self.wrap_distance(origin, cur, rows) >= rows {
+            origin = self.wrap_backward(cur, rows - 1);
+        }
+        self.render_rect.origin = Point { x: 0, y: origin.0 };
+        self.render_wrap_idx = origin.1;
+
+        Point {
+            x,
+            y: self.wrap_distance(origin, cur, rows),
+        }
+    }
+
+    fn cursor_rx(&self) -> usize {
+        self.buffer
+            .borrow()
+            .rows()
+            .get(self.c.y)
+            .map(|row| row.get_rx_from_cx(self.c.x, self.tab_width()))
+            .unwrap_or(0)
+    }
+
     pub(crate) fn move_cursor(&mut self, mv: CursorMove) {
         use CursorMove::*;
         let buffer = self.buffer.borrow();
         let row = &buffer.rows()[self.c.y];
-        enum YScroll {
-            Up(usize),
-            Down(usize),
-        }
+        let wrapped = buffer.wrap_mode() != WrapMode::NoWrap;
         let mut y_scroll = None;
         match mv {
             Left => {
@@ -123,6 +452,17 @@ impl TextBufferView {
             End => self.c.x = row.chars().len(),
             Up => y_scroll = Some(YScroll::Up(1)),
             Down => y_scroll = Some(YScroll::Down(1)),
+            PageUp if wrapped => {
+                let dy = self.cursor_wrap_distance();
+                y_scroll = Some(YScroll::Up(dy + self.render_rect.size.rows))
+            }
+            PageDown if wrapped => {
+                let dy = self.cursor_wrap_distance();
+                y_scroll = Some(YScroll::Down(
+                    (self.render_rect.size.rows - 1).saturating_sub(dy)
+                        + self.render_rect.size.rows,
+                ))
+            }
             PageUp => {
                 y_scroll = Some(YScroll::Up(
                     self.c.y + self.render_rect.size.rows - self.render_rect.origin.y,
@@ -145,8 +485,15 @@ impl TextBufferView {
         }
 
         if let Some(scroll) = y_scroll {
+            if wrapped {
+                drop(buffer);
+                self.move_cursor_wrapped(scroll);
+                return;
+            }
+
             // Adjust cursor x position to the nearest char boundary in rendered texts
-            let rx = buffer.rows()[self.c.y].get_rx_from_cx(self.c.x);
+            let tab_width = buffer.indent().tab_width;
+            let rx = buffer.rows()[self.c.y].get_rx_from_cx(self.c.x, tab_width);
             match scroll {
                 YScroll::Up(dy) => self.c.y = self.c.y.saturating_sub(dy),
                 YScroll::Down(dy) => {
@@ -157,18 +504,67 @@ impl TextBufferView {
                     }
                 }
             }
-            self.c.x = buffer.rows()[self.c.y].get_cx_from_rx(rx);
+            self.c.x = buffer.rows()[self.c.y].get_cx_from_rx(rx, tab_width);
+        }
+    }
+
+    // Moves the cursor by wrapped lines, keeping the column within the wrapped line
+    fn move_cursor_wrapped(&mut self, scroll: YScroll) {
+        let (pos, x) = self.wrap_pos(self.c.y, self.cursor_rx());
+        let (y, idx) = match scroll {
+            YScroll::Up(dy) => self.wrap_backward(pos, dy),
+            YScroll::Down(dy) => {
+                let max_y = self.buffer.borrow().rows().len() - 1;
+                let (y, idx) = self.wrap_forward(pos, dy);
+                if y > max_y {
+                    (max_y, self.wrap_cols_at(max_y).len() - 1)
+                } else {
+                    (y, idx)
+                }
+            }
+        };
+
+        let wraps = self.wrap_cols_at(y);
+        let mut rx = wraps[idx] + x;
+        if let Some(&next) = wraps.get(idx + 1) {
+            if rx >= next {
+                rx = next - 1;
+            }
         }
+        self.c.y = y;
+        self.c.x = self.buffer.borrow().rows()[y].get_cx_from_rx(rx, self.tab_width());
+    }
+
+    fn cursor_wrap_distance(&self) -> usize {
+        let (cur, _) = self.wrap_pos(self.c.y, self.cursor_rx());
+        let origin = (self.render_rect.origin.y, self.render_wrap_idx);
+        self.wrap_distance(origin, cur, usize::MAX)
     }
 
     pub(crate) fn insert_char(&mut self, ch: char) {
-        self.buffer.borrow_mut().insert_char(self.c, ch);
-        self.move_cursor(CursorMove::Right);
+        self.c = self.buffer.borrow_mut().insert_char(self.c, ch);
+    }
+
+    pub(crate) fn insert_tab(&mut self) {
+        let indent = self.buffer.borrow().indent();
+        if !indent.expand_tab {
+            self.insert_char('\t');
+            return;
+        }
+        // Insert spaces up to the next indent stop
+        let rx = self.cursor_rx();
+        let width = indent.indent_width - rx % indent.indent_width;
+        for _ in 0..width {
+            self.insert_char(' ');
+        }
     }
 
     pub(crate) fn insert_newline(&mut self) {
-        self.buffer.borrow_mut().insert_newline(self.c);
-        self.move_cursor(CursorMove::Right);
+        self.c = self.buffer.borrow_mut().insert_newline(self.c);
+    }
+
+    pub(crate) fn insert_str(&mut self, s: &str) {
+        self.c = self.buffer.borrow_mut().insert_str(self.c, s);
     }
 
     pub(crate) fn delete_back_char(&mut self) {
@@ -280,10 +676,66 @@ impl Find {
 
 #[derive(Debug)]
 pub(crate) struct Status<'a> {
-    pub(crate) filename: Option<Ref<'a, Path>>,
+    pub(crate) name: String,
     pub(crate) dirty: bool,
     pub(crate) readonly: bool,
     pub(crate) cursor: Point,
     pub(crate) lines: usize,
     pub(crate) syntax: Ref<'a, Syntax<'a>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(lines: usize) -> TextBufferView {
+        let mut buffer = TextBuffer::new();
+        let text = (0..lines)
+            .map(|y| format!("\tline {}", y))
+            .collect::<Vec<_>>()
+            .join("\n");
+        buffer.insert_str(Point::default(), &text);
+        TextBufferView::new(buffer, Size { cols: 20, rows: 5 })
+    }
+
+    #[test]
+    fn mouse_select() {
+        let mut view = view(10);
+        view.mouse_press(Point { x: 9, y: 1 });
+        assert_eq!(view.c, Point { x: 2, y: 1 });
+        view.mouse_release(Point { x: 9, y: 1 });
+        assert_eq!(view.selection(), None);
+
+        view.mouse_press(Point { x: 10, y: 2 });
+        view.mouse_drag(Point { x: 3, y: 1 });
+        view.mouse_release(Point { x: 30, y: 0 });
+        assert_eq!(
+            view.selection(),
+            Some((Point { x: 7, y: 0 }, Point { x: 3, y: 2 }))
+        );
+        assert_eq!(view.overlays_at(1), vec![(0..7, Highlight::Selection)]);
+
+        view.mouse_press(Point { x: 0, y: 4 });
+        view.mouse_release(Point { x: 0, y: 4 });
+        assert_eq!(view.c, Point { x: 0, y: 4 });
+    }
+
+    #[test]
+    fn scroll_view() {
+        let mut view = view(10);
+        view.scroll_view(3);
+        assert_eq!(view.render_rect.origin.y, 3);
+        assert_eq!(view.c, Point { x: 0, y: 3 });
+        view.scroll_view(20);
+        assert_eq!(view.render_rect.origin.y, 9);
+        assert_eq!(view.c, Point { x: 0, y: 9 });
+        view.scroll_view(-6);
+        assert_eq!(view.render_rect.origin.y, 3);
+        assert_eq!(view.c, Point { x: 0, y: 7 });
+
+        view.buffer_mut().set_wrap_mode(WrapMode::Char);
+        view.scroll_view(-20);
+        assert_eq!((view.render_rect.origin.y, view.render_wrap_idx), (0, 0));
+        assert_eq!(view.c, Point { x: 0, y: 4 });
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+const SLOTS: usize = 64;
+
+/// A hashed timer wheel.
+///
+/// Time is divided into ticks, and each timer is put into the slot for the tick of its deadline
+/// modulo the number of slots. A timer fires at the first tick at or after its deadline.
+#[derive(Debug)]
+pub(crate) struct TimerWheel<T> {
+    start: Instant,
+    tick: Duration,
+    // The last tick whose timers have been expired
+    current: u64,
+    slots: Vec<Vec<(u64, T)>>,
+}
+
+impl<T: PartialEq> TimerWheel<T> {
+    pub(crate) fn new(start: Instant, tick: Duration) -> Self {
+        assert!(tick > Duration::from_secs(0));
+        TimerWheel {
+            start,
+            tick,
+            current: 0,
+            slots: (0..SLOTS).map(|_| vec![]).collect(),
+        }
+    }
+
+    fn ticks_until(&self, instant: Instant) -> u64 {
+        let elapsed = instant.saturating_duration_since(self.start);
+        (elapsed.as_nanos() / self.tick.as_nanos()) as u64
+    }
+
+    fn instant_at(&self, tick: u64) -> Instant {
+        self.start + self.tick * tick as u32
+    }
+
+    /// Schedules `timer` to fire `after` the given time from `now`, replacing the same timer if
+    /// it is already scheduled.
+    pub(crate) fn schedule(&mut self, now: Instant, after: Duration, timer: T) {
+        self.cancel(&timer);
+        let deadline = now + after;
+        let mut tick = self.ticks_until(deadline);
+        if self.instant_at(tick) < deadline {
+            tick += 1;
+        }
+        let tick = tick.max(self.current + 1);
+        self.slots[tick as usize % SLOTS].push((tick, timer));
+    }
+
+    pub(crate) fn cancel(&mut self, timer: &T) {
+        for slot in &mut self.slots {
+            slot.retain(|(_, t)| t != timer);
+        }
+    }
+
+    /// Returns how long to wait from `now` until the next timer fires, or `None` if no timer is
+    /// scheduled.
+    pub(crate) fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let tick = self.slots.iter().flatten().map(|(tick, _)| *tick).min()?;
+        Some(self.instant_at(tick).saturating_duration_since(now))
+    }
+
+    /// Removes and returns the timers whose deadlines have passed at `now`.
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<T> {
+        let now_tick = self.ticks_until(now);
+        let mut expired = vec![];
+        if now_tick <= self.current {
+            return expired;
+        }
+
+        // Each slot needs to be visited at most once
+        let ticks = (now_tick - self.current).min(SLOTS as u64);
+        for tick in now_tick + 1 - ticks..=now_tick {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].0 <= now_tick {
+                    expired.push(slot.remove(i).1);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.current = now_tick;
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut wheel = TimerWheel::new(start, ms(100));
+        assert_eq!(wheel.next_timeout(start), None);
+
+        wheel.schedule(start, ms(250), 'a');
+        wheel.schedule(start, ms(100), 'b');
+        // Far enough to wrap around the wheel
+        wheel.schedule(start, ms(100 * SLOTS as u64 + 100), 'c');
+        assert_eq!(wheel.next_timeout(start), Some(ms(100)));
+        assert_eq!(wheel.next_timeout(start + ms(30)), Some(ms(70)));
+
+        assert!(wheel.expire(start + ms(99)).is_empty());
+        assert_eq!(wheel.expire(start + ms(100)), vec!['b']);
+        assert_eq!(wheel.next_timeout(start + ms(100)), Some(ms(200)));
+        assert!(wheel.expire(start + ms(250)).is_empty());
+        assert_eq!(wheel.expire(start + ms(300)), vec!['a']);
+        assert!(wheel.expire(start + ms(1000)).is_empty());
+        assert_eq!(
+            wheel.expire(start + ms(100 * SLOTS as u64 + 100)),
+            vec!['c']
+        );
+        assert_eq!(wheel.next_timeout(start), None);
+    }
+
+    #[test]
+    fn reschedule() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut wheel = TimerWheel::new(start, ms(100));
+
+        wheel.schedule(start, ms(100), 'a');
+        wheel.schedule(start, ms(500), 'a');
+        assert!(wheel.expire(start + ms(300)).is_empty());
+        assert_eq!(wheel.expire(start + ms(500)), vec!['a']);
+
+        wheel.schedule(start, ms(700), 'b');
+        wheel.cancel(&'b');
+        assert!(wheel.expire(start + ms(1000)).is_empty());
+
+        // Timers in the past fire at the next tick
+        wheel.schedule(start, ms(0), 'c');
+        assert!(wheel.expire(start + ms(1050)).is_empty());
+        assert_eq!(wheel.expire(start + ms(1100)), vec!['c']);
+    }
+}
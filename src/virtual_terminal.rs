@@ -0,0 +1,221 @@
+use crate::{
+    decode::InputSource,
+    geom::{Point, Size},
+    terminal::{self, Terminal, TerminalEvent},
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    str,
+    time::Duration,
+};
+use unicode_width::UnicodeWidthChar;
+
+/// An in-memory terminal for tests.
+///
+/// Input is fed by the test, and the escape sequences written by the editor are interpreted into a
+/// grid of characters.
+#[derive(Debug)]
+pub(crate) struct VirtualTerminal {
+    size: Size,
+    input: VecDeque<u8>,
+    // Output not interpreted yet, such as an incomplete escape sequence
+    output: Vec<u8>,
+    // `None` for the right half of a wide character
+    grid: Vec<Vec<Option<char>>>,
+    cursor: Point,
+    cursor_visible: bool,
+    resized: bool,
+}
+
+impl VirtualTerminal {
+    pub(crate) fn new(size: Size) -> Self {
+        VirtualTerminal {
+            size,
+            input: VecDeque::new(),
+            output: vec![],
+            grid: vec![vec![Some(' '); size.cols]; size.rows],
+            cursor: Point::default(),
+            cursor_visible: true,
+            resized: false,
+        }
+    }
+
+    /// Queues bytes to be read as input.
+    pub(crate) fn feed(&mut self, input: &str) {
+        self.input.extend(input.as_bytes());
+    }
+
+    pub(crate) fn has_input(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    /// Changes the screen size as if the window was resized.
+    pub(crate) fn resize(&mut self, size: Size) {
+        self.size = size;
+        for row in &mut self.grid {
+            row.resize(size.cols, Some(' '));
+        }
+        self.grid.resize(size.rows, vec![Some(' '); size.cols]);
+        self.resized = true;
+    }
+
+    /// Returns the text on a line of the screen without trailing spaces.
+    pub(crate) fn line(&self, y: usize) -> String {
+        let line = self.grid[y].iter().flatten().collect::<String>();
+        line.trim_end().to_string()
+    }
+
+    pub(crate) fn lines(&self) -> Vec<String> {
+        (0..self.size.rows).map(|y| self.line(y)).collect()
+    }
+
+    pub(crate) fn cursor(&self) -> Point {
+        self.cursor
+    }
+
+    pub(crate) fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    fn interpret(&mut self) {
+        loop {
+            let len = match self.output.first() {
+                None => return,
+                Some(b'\x1b') => match self.escape_sequence() {
+                    Some(len) => len,
+                    None => return,
+                },
+                Some(b'\r') => {
+                    self.cursor.x = 0;
+                    1
+                }
+                Some(b'\n') => {
+                    self.cursor.y = (self.cursor.y + 1).min(self.size.rows - 1);
+                    1
+                }
+                Some(&b) => {
+                    let width = match b {
+                        0x00..=0x7f => 1,
+                        0xc0..=0xdf => 2,
+                        0xe0..=0xef => 3,
+                        _ => 4,
+                    };
+                    if self.output.len() < width {
+                        return;
+                    }
+                    let s = str::from_utf8(&self.output[..width]).expect("invalid UTF-8 output");
+                    self.put_char(s.chars().next().unwrap());
+                    width
+                }
+            };
+            self.output.drain(..len);
+        }
+    }
+
+    fn put_char(&mut self, ch: char) {
+        let width = ch.width().unwrap_or(0);
+        let Point { x, y } = self.cursor;
+        if width == 0 || x + width > self.size.cols {
+            return;
+        }
+        let row = &mut self.grid[y];
+        row[x] = Some(ch);
+        if width == 2 {
+            row[x + 1] = None;
+        }
+        self.cursor.x = (x + width).min(self.size.cols - 1);
+    }
+
+    // Interprets the escape sequence at the start of the output and returns its length, or `None`
+    // if it is incomplete
+    fn escape_sequence(&mut self) -> Option<usize> {
+        let seq = &self.output;
+        if seq.len() < 2 {
+            return None;
+        }
+        assert_eq!(seq[1], b'[', "unexpected escape sequence {:?}", seq);
+        let len = seq[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 3;
+        let params = str::from_utf8(&seq[2..len - 1]).unwrap().to_string();
+        let final_byte = seq[len - 1];
+
+        let nums = params
+            .split(';')
+            .map(|p| p.parse::<usize>().ok())
+            .collect::<Vec<_>>();
+        let num = |i: usize, default: usize| nums.get(i).copied().flatten().unwrap_or(default);
+        match (params.as_str(), final_byte) {
+            ("?25", b'l') => self.cursor_visible = false,
+            ("?25", b'h') => self.cursor_visible = true,
+            (_, b'H') => {
+                self.cursor = Point {
+                    x: (num(1, 1) - 1).min(self.size.cols - 1),
+                    y: (num(0, 1) - 1).min(self.size.rows - 1),
+                }
+            }
+            (_, b'C') => self.cursor.x = (self.cursor.x + num(0, 1)).min(self.size.cols - 1),
+            ("2", b'J') => {
+                for row in &mut self.grid {
+                    row.iter_mut().for_each(|cell| *cell = Some(' '));
+                }
+            }
+            ("", b'K') => {
+                let x = self.cursor.x;
+                self.grid[self.cursor.y][x..]
+                    .iter_mut()
+                    .for_each(|cell| *cell = Some(' '));
+            }
+            // Graphic renditions are not recorded
+            (_, b'm') => {}
+            _ => panic!("unexpected escape sequence {:?}", &self.output[..len]),
+        }
+        Some(len)
+    }
+}
+
+impl Read for VirtualTerminal {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl InputSource for VirtualTerminal {
+    fn poll_input(&mut self, _timeout: Duration) -> io::Result<bool> {
+        Ok(!self.input.is_empty())
+    }
+}
+
+impl Write for VirtualTerminal {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.extend_from_slice(buf);
+        self.interpret();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Terminal for VirtualTerminal {
+    fn screen_size(&self) -> Size {
+        self.size
+    }
+
+    fn wait_event(&mut self, _timeout: Option<Duration>) -> terminal::Result<TerminalEvent> {
+        if self.resized {
+            return Ok(TerminalEvent::Signal);
+        }
+        // Waiting for more input than fed would never end
+        assert!(!self.input.is_empty(), "no more input");
+        Ok(TerminalEvent::Input)
+    }
+
+    fn process_signals(&mut self) -> terminal::Result<bool> {
+        Ok(std::mem::replace(&mut self.resized, false))
+    }
+
+    fn suspend(&mut self) -> terminal::Result<()> {
+        Ok(())
+    }
+}
@@ -1,12 +1,10 @@
 use crate::{
-    geom::{Point, Rect, Segment, Size},
-    row::Row,
+    geom::{Point, Rect, Size},
+    render::TAB_STOP,
+    row::{RenderRow, Row},
     syntax::Syntax,
 };
-use std::{
-    cell::{Ref, RefCell},
-    ops::Range,
-};
+use std::{cell::RefCell, ops::Range};
 
 #[derive(Debug)]
 pub(crate) struct Welcome {
@@ -64,15 +62,20 @@ pub(crate) struct RenderRows<'a> {
 }
 
 impl<'a> Iterator for RenderRows<'a> {
-    type Item = Vec<(Segment, Ref<'a, Row>)>;
+    type Item = Vec<RenderRow<'a>>;
 
     fn next(&mut self) -> Option<Self::Item> {
         let idx = self.idx.next()?;
-        let segment = self.render_rect.x_segment();
-        if idx == self.message_idx {
-            Some(vec![(segment, self.welcome.message_row.borrow())])
+        let row = if idx == self.message_idx {
+            self.welcome.message_row.borrow()
         } else {
-            Some(vec![(segment, self.welcome.empty_row.borrow())])
-        }
+            self.welcome.empty_row.borrow()
+        };
+        Some(vec![RenderRow {
+            row,
+            segment: self.render_rect.x_segment(),
+            tab_width: TAB_STOP,
+            overlays: vec![],
+        }])
     }
 }